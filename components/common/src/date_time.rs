@@ -4,11 +4,108 @@ use core::fmt;
 use std::error::Error;
 
 pub fn timestamp_to_interval_str(begin_time: u64, end_time: u64) -> String{
-    format!("[{},{}), {}/{}",
-             begin_time,
-             end_time,
-             timestamp_secs_to_string(begin_time),
-             timestamp_secs_to_string(end_time))
+    TimePrecision::Seconds.interval_to_string(begin_time, end_time)
+}
+
+/// TimePrecision
+///
+/// The unit of a timestamp, every table stores its timestamps in one precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TimePrecision {
+    #[default]
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimePrecision {
+    /// count of units in one second
+    pub fn units_per_sec(&self) -> u64 {
+        match *self {
+            TimePrecision::Seconds => 1,
+            TimePrecision::Millis => 1_000,
+            TimePrecision::Micros => 1_000_000,
+            TimePrecision::Nanos => 1_000_000_000,
+        }
+    }
+
+    /// short name used in configs and requests: s, ms, us, ns
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TimePrecision::Seconds => "s",
+            TimePrecision::Millis => "ms",
+            TimePrecision::Micros => "us",
+            TimePrecision::Nanos => "ns",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TimePrecision> {
+        match name {
+            "s" => Some(TimePrecision::Seconds),
+            "ms" => Some(TimePrecision::Millis),
+            "us" => Some(TimePrecision::Micros),
+            "ns" => Some(TimePrecision::Nanos),
+            _ => None,
+        }
+    }
+
+    /// current time in this precision
+    pub fn now(&self) -> u64 {
+        let since_the_epoch = SystemTime::now().duration_since(UNIX_EPOCH)
+            .expect("Time went backwards");
+        self.from_duration(since_the_epoch)
+    }
+
+    pub fn from_duration(&self, duration: Duration) -> u64 {
+        match *self {
+            TimePrecision::Seconds => duration.as_secs(),
+            TimePrecision::Millis => duration.as_millis() as u64,
+            TimePrecision::Micros => duration.as_micros() as u64,
+            TimePrecision::Nanos => duration.as_nanos() as u64,
+        }
+    }
+
+    pub fn to_duration(&self, timestamp: u64) -> Duration {
+        match *self {
+            TimePrecision::Seconds => Duration::from_secs(timestamp),
+            TimePrecision::Millis => Duration::from_millis(timestamp),
+            TimePrecision::Micros => Duration::from_micros(timestamp),
+            TimePrecision::Nanos => Duration::from_nanos(timestamp),
+        }
+    }
+
+    /// convert a count of seconds, such as a block period, to this precision
+    pub fn from_secs(&self, secs: u64) -> u64 {
+        secs * self.units_per_sec()
+    }
+
+    /// timestamp of a date time in this precision, dates before the epoch are clamped to 0
+    pub fn from_date_time<Tz: chrono::TimeZone>(&self, dt: &DateTime<Tz>) -> u64 {
+        let secs = dt.timestamp();
+        if secs < 0 {
+            return 0;
+        }
+        let nanos = u64::from(dt.timestamp_subsec_nanos());
+        self.from_secs(secs as u64) + nanos / (1_000_000_000 / self.units_per_sec())
+    }
+
+    /// format timestamp to string
+    pub fn timestamp_to_string(&self, timestamp: u64) -> String {
+        let origin_dt: DateTime<Utc> = (UNIX_EPOCH + self.to_duration(timestamp)).into();
+        match *self {
+            TimePrecision::Seconds => origin_dt.format("%Y-%m-%dT%T%z").to_string(),
+            _ => origin_dt.format("%Y-%m-%dT%T%.f%z").to_string(),
+        }
+    }
+
+    pub fn interval_to_string(&self, begin_time: u64, end_time: u64) -> String {
+        format!("[{},{}), {}/{}",
+                begin_time,
+                end_time,
+                self.timestamp_to_string(begin_time),
+                self.timestamp_to_string(end_time))
+    }
 }
 
 pub fn now_timestamp_secs() -> u64{
@@ -85,3 +182,54 @@ impl Error for DateTimeError {
         self.description()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::date_time::TimePrecision;
+    use chrono::DateTime;
+
+    const ALL: [TimePrecision; 4] = [
+        TimePrecision::Seconds,
+        TimePrecision::Millis,
+        TimePrecision::Micros,
+        TimePrecision::Nanos,
+    ];
+
+    #[test]
+    fn names() {
+        for precision in ALL.iter() {
+            assert_eq!(TimePrecision::from_name(precision.name()), Some(*precision));
+        }
+        assert_eq!(TimePrecision::from_name("m"), None);
+        assert_eq!(TimePrecision::from_name(""), None);
+    }
+
+    #[test]
+    fn from_secs() {
+        let expected = [2, 2_000, 2_000_000, 2_000_000_000];
+        for (precision, units) in ALL.iter().zip(expected.iter()) {
+            assert_eq!(precision.from_secs(2), *units);
+            assert_eq!(precision.from_duration(precision.to_duration(*units)), *units);
+        }
+    }
+
+    #[test]
+    fn from_date_time() {
+        // sub second parts are truncated to the precision
+        let dt = DateTime::parse_from_rfc3339("1970-01-01T00:00:02.123456789+00:00").unwrap();
+        let expected = [2, 2_123, 2_123_456, 2_123_456_789];
+        for (precision, units) in ALL.iter().zip(expected.iter()) {
+            assert_eq!(precision.from_date_time(&dt), *units);
+        }
+
+        // offsets are applied before converting
+        let dt = DateTime::parse_from_rfc3339("1970-01-01T08:00:01+08:00").unwrap();
+        assert_eq!(TimePrecision::Millis.from_date_time(&dt), 1_000);
+
+        // dates before the epoch are clamped to 0
+        let dt = DateTime::parse_from_rfc3339("1969-12-31T23:59:59.5+00:00").unwrap();
+        for precision in ALL.iter() {
+            assert_eq!(precision.from_date_time(&dt), 0);
+        }
+    }
+}
@@ -12,3 +12,4 @@ pub use date_time::now_timestamp_secs;
 pub use date_time::timestamp_secs_to_string;
 pub use date_time::string_to_date_times;
 pub use date_time::timestamp_to_interval_str;
+pub use date_time::TimePrecision;
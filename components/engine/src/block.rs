@@ -1,109 +1,308 @@
-use tszv1::stream::{BufferedReader, BufferedWriter};
-use tszv1::Buffer;
-use tszv1::{buffer_into_vec, DataPoint, Decode, Encode, StdDecoder, StdEncoder};
-
-pub trait Block {
-    //    fn get_decoder(&self) -> StdDecoder<BufferedReader>;
-    fn read<F>(&self, f: F)
-    where
-        F: Fn(DataPoint);
-}
+use crate::cache::Resident;
+use common::TimePrecision;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use tszv1::decode::Error;
+use tszv1::format::{self, CodecId};
+use tszv1::index::DEFAULT_CHECKPOINT_INTERVAL;
+use tszv1::stream::{
+    self, BufferedReader, MappedFile, MmapReader, Read, SharedReader, SharedWriter,
+};
+use tszv1::BlockIndex;
+use tszv1::{Bit, Encode, StdDecoder, StdEncoder};
 
 #[derive(Debug)]
 pub struct AppendOnlyBlock {
     pub time_begin: u64,
     pub time_end: u64,
+    pub precision: TimePrecision,
 
-    pub encoder: StdEncoder<BufferedWriter>,
+    pub encoder: StdEncoder<SharedWriter>,
 }
 
 impl AppendOnlyBlock {
-    pub fn new(time_begin: u64, time_end: u64) -> Self {
-        let writer = BufferedWriter::new();
-        let encoder = StdEncoder::new(time_begin, writer);
+    pub fn new(time_begin: u64, time_end: u64, precision: TimePrecision, codec: CodecId) -> Self {
+        let writer = SharedWriter::new();
+        let encoder = StdEncoder::with_codec(time_begin, precision, codec, writer);
 
         AppendOnlyBlock {
             time_begin,
             time_end,
+            precision,
             encoder,
         }
     }
 
+    /// the closed block bytes, copies the whole buffer
     pub fn get_buffer(&self) -> Box<[u8]> {
         self.encoder.clone().close()
     }
 
-    pub fn get_decoder(&self) -> StdDecoder<BufferedReader> {
-        let reader = BufferedReader::new(self.encoder.clone().close());
-        StdDecoder::new(reader)
+    /// a decoder of the points encoded so far, it reads the encoder's buffer in place
+    pub fn get_decoder(&self) -> Result<StdDecoder<SharedReader>, Error> {
+        StdDecoder::with_precision(self.encoder.snapshot(), self.precision)
+    }
+
+    /// a decoder which skips the checkpoints before `time`
+    pub fn get_decoder_at(&self, time: u64) -> Result<StdDecoder<BlockReader>, Error> {
+        let reader = BlockReader::Active(self.encoder.snapshot());
+        let mut decoder = StdDecoder::with_precision(reader, self.precision)?;
+        decoder.seek(self.encoder.index(), time)?;
+        Ok(decoder)
     }
 }
 
-impl Block for AppendOnlyBlock {
-    // TODO clone always
-    //    fn get_decoder(&self) -> StdDecoder<BufferedReader> {
-    //        let reader = BufferedReader::new(self.encoder.clone().close());
-    //        StdDecoder::new(reader)
-    //    }
-
-    fn read<F>(&self, f: F)
-    where
-        F: Fn(DataPoint),
-    {
-        let mut decoder = self.get_decoder();
-        loop {
-            match decoder.next() {
-                Ok(dp) => {
-                    f(dp);
-                }
-                Err(_) => {
-                    break;
-                }
+/// BlockBytes
+///
+/// The encoded bytes of a closed block, in memory or mapped from the block file. Mapped bytes
+/// are read through the page cache and take no heap memory.
+#[derive(Debug, Clone)]
+pub enum BlockBytes {
+    Memory(Arc<[u8]>),
+    Mapped(MappedFile),
+}
+
+impl BlockBytes {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, BlockBytes::Mapped(_))
+    }
+
+    fn reader(&self) -> BlockReader {
+        match self {
+            BlockBytes::Memory(bytes) => {
+                BlockReader::Closed(BufferedReader::from_bytes(bytes.clone()))
             }
+            BlockBytes::Mapped(file) => BlockReader::Mapped(MmapReader::new(file.clone())),
         }
     }
 }
 
-#[derive(Debug)]
+impl AsRef<[u8]> for BlockBytes {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            BlockBytes::Memory(bytes) => bytes,
+            BlockBytes::Mapped(file) => file.as_ref(),
+        }
+    }
+}
+
+/// ClosedBlock
+///
+/// The encoded bytes of a block which takes no more points. Its clones share the bytes, which
+/// the block cache may drop from memory once the block is persisted; the block file is mapped
+/// when they are needed again.
+#[derive(Debug, Clone)]
 pub struct ClosedBlock {
     time_begin: u64,
     time_end: u64,
-    bytes: Buffer, // Arc<Buffer>,
+    precision: TimePrecision,
+    resident: Arc<Resident>,          // shared by the decoders, never copied
+    index: Arc<OnceLock<BlockIndex>>, // rebuilt by the first seek when not kept
+    path: Option<PathBuf>,            // the block file once persisted
+    verified: Arc<AtomicBool>,        // whether the checksum of the bytes was checked
 }
 
 impl ClosedBlock {
     pub fn new(append_only_block: &AppendOnlyBlock) -> Self {
-        let bytes = buffer_into_vec(append_only_block.get_buffer());
-        //        let bytes = Arc::new(bytes);
+        let bytes = BlockBytes::Memory(Arc::from(append_only_block.get_buffer()));
         ClosedBlock {
             time_begin: append_only_block.time_begin,
             time_end: append_only_block.time_end,
-            bytes,
+            precision: append_only_block.precision,
+            resident: Arc::new(Resident::new(bytes)),
+            index: Arc::new(OnceLock::from(append_only_block.encoder.index().clone())),
+            path: None,
+            verified: Arc::new(AtomicBool::new(true)),
         }
     }
 
-    // TODO clone always
-    pub fn get_decoder(&self) -> StdDecoder<BufferedReader> {
-        let reader = BufferedReader::new_buffer(self.bytes.clone());
-        StdDecoder::new(reader)
+    /// a block of encoded `bytes` such as the output of a rewrite, its checkpoints are rebuilt
+    /// by the first decoder which seeks
+    pub fn from_bytes(
+        bytes: BlockBytes,
+        time_begin: u64,
+        time_end: u64,
+        precision: TimePrecision,
+    ) -> Self {
+        ClosedBlock {
+            time_begin,
+            time_end,
+            precision,
+            resident: Arc::new(Resident::new(bytes)),
+            index: Arc::new(OnceLock::new()),
+            path: None,
+            verified: Arc::new(AtomicBool::new(true)),
+        }
     }
-}
 
-impl Block for ClosedBlock {
-    fn read<F>(&self, f: F)
-    where
-        F: Fn(DataPoint),
-    {
-        let mut decoder = self.get_decoder();
-        loop {
-            match decoder.next() {
-                Ok(dp) => {
-                    f(dp);
-                }
-                Err(_) => {
-                    break;
-                }
+    /// a block read back from its file, usually mapped, the precision is taken from the block
+    /// header. The checksum is checked by the first decoder and the checkpoints, which are not
+    /// persisted, are rebuilt by the first seek, so loading does not read the mapped pages.
+    pub fn load(
+        bytes: BlockBytes,
+        time_begin: u64,
+        time_end: u64,
+        path: PathBuf,
+    ) -> Result<Self, Error> {
+        let header = match format::read_header(bytes.as_ref()).map_err(Error::InvalidHeader)? {
+            Some(header) => header,
+            None => {
+                return Err(Error::UnsupportedVersion(
+                    bytes.as_ref().get(2).cloned().unwrap_or(0),
+                ))
             }
+        };
+        let mut block = ClosedBlock::from_bytes(bytes, time_begin, time_end, header.precision);
+        block.path = Some(path);
+        block.verified = Arc::new(AtomicBool::new(false));
+        Ok(block)
+    }
+
+    pub fn time_begin(&self) -> u64 {
+        self.time_begin
+    }
+
+    pub fn time_end(&self) -> u64 {
+        self.time_end
+    }
+
+    /// the encoded bytes, the block file is mapped if they were evicted
+    pub fn bytes(&self) -> Result<BlockBytes, crate::Error> {
+        self.resident.get(|| {
+            let path = self
+                .path
+                .as_ref()
+                .ok_or_else(|| crate::Error::Storage("an evicted block has no file".to_string()))?;
+            MappedFile::open(path)
+                .map(BlockBytes::Mapped)
+                .map_err(|err| crate::Error::Storage(format!("{}: {}", path.display(), err)))
+        })
+    }
+
+    /// the count of encoded bytes
+    pub fn size(&self) -> usize {
+        self.resident.size()
+    }
+
+    pub(crate) fn resident(&self) -> &Arc<Resident> {
+        &self.resident
+    }
+
+    /// whether `other` is a clone of the block
+    pub fn same(&self, other: &ClosedBlock) -> bool {
+        Arc::ptr_eq(&self.resident, &other.resident)
+    }
+
+    /// the block file, `None` until the block is persisted
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    pub fn get_decoder(&self) -> Result<StdDecoder<BlockReader>, crate::Error> {
+        let reader = self.bytes()?.reader();
+        let decoder = StdDecoder::with_precision(reader, self.precision)?;
+        self.verify(&decoder)?;
+        Ok(decoder)
+    }
+
+    /// a decoder which skips the checkpoints before `time`
+    pub fn get_decoder_at(&self, time: u64) -> Result<StdDecoder<BlockReader>, crate::Error> {
+        let mut decoder = self.get_decoder()?;
+        decoder.seek(self.index()?, time)?;
+        Ok(decoder)
+    }
+
+    /// the checkpoints of the block, decodes the whole block the first time
+    fn index(&self) -> Result<&BlockIndex, crate::Error> {
+        if let Some(index) = self.index.get() {
+            return Ok(index);
+        }
+        let index = self
+            .get_decoder()?
+            .build_index(DEFAULT_CHECKPOINT_INTERVAL)?;
+        Ok(self.index.get_or_init(|| index))
+    }
+
+    /// check the checksum of a loaded block once, blocks encoded by the engine need no check
+    fn verify(&self, decoder: &StdDecoder<BlockReader>) -> Result<(), Error> {
+        if !self.verified.load(Ordering::Acquire) {
+            decoder.verify()?;
+            self.verified.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+/// BlockReader
+///
+/// BlockReader reads an active block, a closed block in memory or a block file mapped in place,
+/// so the decoders of all the blocks of a series have one type.
+#[derive(Debug)]
+pub enum BlockReader {
+    Active(SharedReader),
+    Closed(BufferedReader<Arc<[u8]>>),
+    Mapped(MmapReader),
+}
+
+impl Read for BlockReader {
+    fn read_bit(&mut self) -> Result<Bit, stream::Error> {
+        match self {
+            BlockReader::Active(r) => r.read_bit(),
+            BlockReader::Closed(r) => r.read_bit(),
+            BlockReader::Mapped(r) => r.read_bit(),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, stream::Error> {
+        match self {
+            BlockReader::Active(r) => r.read_byte(),
+            BlockReader::Closed(r) => r.read_byte(),
+            BlockReader::Mapped(r) => r.read_byte(),
+        }
+    }
+
+    fn read_bits(&mut self, num: u32) -> Result<u64, stream::Error> {
+        match self {
+            BlockReader::Active(r) => r.read_bits(num),
+            BlockReader::Closed(r) => r.read_bits(num),
+            BlockReader::Mapped(r) => r.read_bits(num),
+        }
+    }
+
+    fn peak_bits(&mut self, num: u32) -> Result<u64, stream::Error> {
+        match self {
+            BlockReader::Active(r) => r.peak_bits(num),
+            BlockReader::Closed(r) => r.peak_bits(num),
+            BlockReader::Mapped(r) => r.peak_bits(num),
+        }
+    }
+
+    fn checksum(&self, offset: usize) -> u32 {
+        match self {
+            BlockReader::Active(r) => r.checksum(offset),
+            BlockReader::Closed(r) => r.checksum(offset),
+            BlockReader::Mapped(r) => r.checksum(offset),
+        }
+    }
+
+    fn seek_bit(&mut self, offset: u64) -> Result<(), stream::Error> {
+        match self {
+            BlockReader::Active(r) => r.seek_bit(offset),
+            BlockReader::Closed(r) => r.seek_bit(offset),
+            BlockReader::Mapped(r) => r.seek_bit(offset),
+        }
+    }
+
+    fn bit_offset(&self) -> u64 {
+        match self {
+            BlockReader::Active(r) => r.bit_offset(),
+            BlockReader::Closed(r) => r.bit_offset(),
+            BlockReader::Mapped(r) => r.bit_offset(),
         }
     }
 }
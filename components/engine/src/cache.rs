@@ -0,0 +1,391 @@
+use crate::block::{BlockBytes, ClosedBlock};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+/// CacheStats
+///
+/// The closed blocks held in memory, the blocks mapped from their files and the reads of the
+/// blocks since the engine started.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    /// bytes, `None` holds every block in memory
+    pub budget: Option<u64>,
+    /// encoded bytes of the closed blocks in memory
+    pub resident: u64,
+    pub blocks: usize,
+    pub evictable: usize,
+    /// blocks read in place from their mapped files, not charged to the budget
+    pub mapped: usize,
+    /// reads of a block in memory, by searches and by the maintenance jobs
+    pub hits: u64,
+    /// reads of an evicted block, whose file was mapped again
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// Resident
+///
+/// The encoded bytes of a closed block, shared by the clones of the block. The cache drops the
+/// bytes of a persisted block to free memory, its file is mapped when they are needed again.
+pub(crate) struct Resident {
+    size: usize,
+    bytes: Mutex<Option<BlockBytes>>,
+    cached: OnceLock<(Arc<BlockCache>, u64)>, // the cache accounting the block and its id there
+}
+
+impl Resident {
+    pub(crate) fn new(bytes: BlockBytes) -> Self {
+        Resident {
+            size: bytes.as_ref().len(),
+            bytes: Mutex::new(Some(bytes)),
+            cached: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// the bytes in memory or mapped, `load` maps them again when they were evicted
+    pub(crate) fn get<F>(&self, load: F) -> Result<BlockBytes, crate::Error>
+    where
+        F: FnOnce() -> Result<BlockBytes, crate::Error>,
+    {
+        let loaded = {
+            // held while loading, so a block is mapped once by concurrent searches
+            let mut bytes = self.bytes.lock().unwrap();
+            if let Some(bytes) = bytes.as_ref() {
+                if let Some((cache, id)) = self.cached.get() {
+                    cache.hit(*id);
+                }
+                return Ok(bytes.clone());
+            }
+            let loaded = load()?;
+            *bytes = Some(loaded.clone());
+            loaded
+        };
+        // the cache is entered without the lock of the bytes, which an eviction takes
+        if let Some((cache, id)) = self.cached.get() {
+            cache.miss(*id);
+        }
+        Ok(loaded)
+    }
+}
+
+impl fmt::Debug for Resident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resident")
+            .field("size", &self.size)
+            .field(
+                "bytes",
+                &self.bytes.lock().unwrap().as_ref().map(|b| b.is_mapped()),
+            )
+            .finish()
+    }
+}
+
+impl Drop for Resident {
+    fn drop(&mut self) {
+        if let Some((cache, id)) = self.cached.get() {
+            cache.forget(*id);
+        }
+    }
+}
+
+struct Entry {
+    resident: Weak<Resident>,
+    size: usize,
+    loaded: bool, // the bytes are on the heap
+    mapped: bool,
+    persisted: bool,
+    used: u64, // the tick of the last read
+}
+
+#[derive(Default)]
+struct Lru {
+    resident: usize,
+    tick: u64,
+    next_id: u64,
+    entries: HashMap<u64, Entry>,
+    order: BTreeMap<u64, u64>, // tick of the last read -> id, of the blocks in memory
+}
+
+impl Lru {
+    fn touch(&mut self, id: u64) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(&id) {
+            if entry.loaded {
+                self.order.remove(&entry.used);
+                self.order.insert(tick, id);
+            }
+            entry.used = tick;
+        }
+    }
+}
+
+/// BlockCache
+///
+/// BlockCache accounts the encoded bytes of the closed blocks in memory against a budget shared
+/// by every series of an engine. Over the budget it evicts the persisted blocks read least
+/// recently, a search which touches an evicted block maps its file. Blocks without a file are
+/// never evicted, so the budget may be exceeded until they are persisted. Mapped blocks take
+/// no heap memory, the OS keeps their pages in the page cache or drops them, so they are not
+/// charged.
+pub(crate) struct BlockCache {
+    budget: Option<usize>,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl BlockCache {
+    pub(crate) fn new(budget: Option<u64>) -> Arc<Self> {
+        Arc::new(BlockCache {
+            budget: budget.map(|b| b as usize),
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        })
+    }
+
+    /// account `block`, once, then evict over the budget
+    pub(crate) fn admit(self: &Arc<Self>, block: &ClosedBlock) {
+        let resident = block.resident();
+        let (loaded, mapped) = match resident.bytes.lock().unwrap().as_ref() {
+            Some(bytes) => (!bytes.is_mapped(), bytes.is_mapped()),
+            None => (false, false),
+        };
+        {
+            let mut lru = self.lru.lock().unwrap();
+            let id = lru.next_id;
+            if resident.cached.set((self.clone(), id)).is_err() {
+                return;
+            }
+            lru.next_id += 1;
+            lru.tick += 1;
+            let used = lru.tick;
+            lru.entries.insert(
+                id,
+                Entry {
+                    resident: Arc::downgrade(resident),
+                    size: resident.size,
+                    loaded,
+                    mapped,
+                    persisted: block.path().is_some(),
+                    used,
+                },
+            );
+            if loaded {
+                lru.resident += resident.size;
+                lru.order.insert(used, id);
+            }
+        }
+        self.evict();
+    }
+
+    /// `block` was written to its file, it may be evicted from now on
+    pub(crate) fn persisted(&self, block: &ClosedBlock) {
+        if let Some((_, id)) = block.resident().cached.get() {
+            if let Some(entry) = self.lru.lock().unwrap().entries.get_mut(id) {
+                entry.persisted = true;
+            }
+            self.evict();
+        }
+    }
+
+    fn hit(&self, id: u64) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.lru.lock().unwrap().touch(id);
+    }
+
+    /// the file of an evicted block was mapped, which charges nothing
+    fn miss(&self, id: u64) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(entry) = lru.entries.get_mut(&id) {
+            entry.mapped = true;
+        }
+        lru.touch(id);
+    }
+
+    fn forget(&self, id: u64) {
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(entry) = lru.entries.remove(&id) {
+            if entry.loaded {
+                lru.resident -= entry.size;
+                lru.order.remove(&entry.used);
+            }
+        }
+    }
+
+    /// drop the bytes of the persisted blocks read least recently until the budget is met
+    fn evict(&self) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        // the blocks are released out of the lock, the drop of the last clone forgets it
+        let mut released = Vec::new();
+        let mut lru = self.lru.lock().unwrap();
+        let mut candidates: Vec<(u64, u64)> = Vec::new();
+        let mut over = lru.resident.saturating_sub(budget);
+        for (tick, id) in lru.order.iter() {
+            if over == 0 {
+                break;
+            }
+            let entry = &lru.entries[id];
+            if entry.persisted {
+                candidates.push((*tick, *id));
+                over = over.saturating_sub(entry.size);
+            }
+        }
+        for (tick, id) in candidates {
+            let resident = match lru.entries[&id].resident.upgrade() {
+                Some(resident) => resident,
+                None => continue,
+            };
+            // a block being read back is skipped, it is about to be used
+            let evicted = match resident.bytes.try_lock() {
+                Ok(mut bytes) => bytes.take().is_some(),
+                Err(_) => false,
+            };
+            if evicted {
+                let size = lru.entries[&id].size;
+                if let Some(entry) = lru.entries.get_mut(&id) {
+                    entry.loaded = false;
+                }
+                lru.resident -= size;
+                lru.order.remove(&tick);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+            released.push(resident);
+        }
+        drop(lru);
+        drop(released);
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            budget: self.budget.map(|b| b as u64),
+            resident: lru.resident as u64,
+            blocks: lru.order.len(),
+            evictable: lru
+                .order
+                .values()
+                .filter(|id| lru.entries[id].persisted)
+                .count(),
+            mapped: lru.entries.values().filter(|entry| entry.mapped).count(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{AppendOnlyBlock, ClosedBlock};
+    use crate::cache::{BlockCache, CacheStats};
+    use crate::store::{temp_dir, BlockStore, SeriesKey};
+    use common::TimePrecision;
+    use tszv1::format::CodecId;
+    use tszv1::{DataPoint, Encode};
+
+    fn block(i: u64) -> ClosedBlock {
+        let begin = i * 7200;
+        let mut aob = AppendOnlyBlock::new(
+            begin,
+            begin + 7200,
+            TimePrecision::Seconds,
+            CodecId::default(),
+        );
+        for t in 0..100 {
+            aob.encoder.encode(DataPoint::new(begin + t, t as f64));
+        }
+        ClosedBlock::new(&aob)
+    }
+
+    // whether the bytes of `block` are on the heap, mapped or evicted
+    fn state(block: &ClosedBlock) -> Option<bool> {
+        block
+            .resident()
+            .bytes
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|b| b.is_mapped())
+    }
+
+    #[test]
+    fn evicts_least_recently_read() {
+        let dir = temp_dir("cache");
+        let store = BlockStore::open(&dir).unwrap();
+        let key = SeriesKey::new("t", "k");
+        let mut blocks: Vec<ClosedBlock> = (1..4).map(block).collect();
+        let sizes: Vec<u64> = blocks.iter().map(|b| b.size() as u64).collect();
+        let size = |i: usize| sizes[i];
+        let cache = BlockCache::new(Some(size(1) + size(2)));
+        for block in &blocks {
+            cache.admit(block);
+        }
+        // blocks without a file are kept over the budget
+        assert_eq!(cache.stats().evictions, 0);
+        assert_eq!(cache.stats().resident, size(0) + size(1) + size(2));
+
+        for block in blocks.iter_mut() {
+            let path = store.write(&key, block).unwrap();
+            block.set_path(path);
+            cache.persisted(block);
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.resident, size(1) + size(2));
+        assert_eq!(stats.evictable, 2);
+        assert_eq!(state(&blocks[0]), None);
+
+        // the evicted block is decoded from its mapped file, which is not charged
+        let points: Vec<DataPoint> = blocks[0]
+            .get_decoder()
+            .unwrap()
+            .into_iter()
+            .map(|dp| dp.unwrap())
+            .collect();
+        assert_eq!(points.len(), 100);
+        assert_eq!(points[99], DataPoint::new(7200 + 99, 99.0));
+        assert_eq!(state(&blocks[0]), Some(true));
+        assert_eq!(cache.stats().resident, size(1) + size(2));
+
+        // a new block evicts the one read least recently
+        blocks[1].bytes().unwrap();
+        let mut last = block(4);
+        last.set_path(store.write(&key, &last).unwrap());
+        cache.admit(&last);
+        blocks.push(last);
+        assert_eq!(state(&blocks[1]), Some(false));
+        assert_eq!(state(&blocks[2]), None);
+        // the writes of the block files were reads too
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (4, 1, 2));
+        assert_eq!(stats.resident, size(1) + blocks[3].size() as u64);
+        assert_eq!(stats.mapped, 1);
+
+        drop(blocks);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                budget: stats.budget,
+                hits: 4,
+                misses: 1,
+                evictions: 2,
+                ..CacheStats::default()
+            }
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
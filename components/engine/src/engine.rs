@@ -1,91 +1,480 @@
-use crate::ts::TS;
-use crate::{Engine, Raw};
+use crate::cache::{BlockCache, CacheStats};
+use crate::rollup::{self, Rollup, RollupRule, Tier};
+use crate::scheduler::{JobStats, MaintenanceOptions, Scheduler};
+use crate::store::{BlockStore, LoadedSeries, SeriesKey};
+use crate::table::TableOptions;
+use crate::ts::{Compaction, TS};
+use crate::worker::{QueueStats, WorkerPool};
+use crate::{Appended, Engine, EngineOptions, Error, Raw, SeriesMatcher, ShutdownSummary};
 use std::collections::BTreeMap;
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::time::Duration;
+use std::sync::Arc;
+use tszv1::format::CodecId;
+use tszv1::DataPoint;
 
-pub type TSTreeMap = BTreeMap<String, TS>;
+pub(crate) type TSTreeMap = BTreeMap<SeriesKey, TS>;
+pub type TableTreeMap = BTreeMap<String, TableOptions>;
 
 #[derive(Clone)]
 pub(crate) struct BTreeEngine {
     ts_store: common::SharedRwLock<TSTreeMap>,
-    background_task_tx: SyncSender<TS>,
+    tables: common::SharedRwLock<TableTreeMap>,
+    workers: Arc<WorkerPool>,
+    store: Option<BlockStore>,
+    cache: Arc<BlockCache>,
+    scheduler: Arc<Scheduler>,
+}
+
+/// a snapshot of the series map, the map is not locked while the series are worked on
+fn series(ts_store: &common::SharedRwLock<TSTreeMap>) -> Vec<(SeriesKey, TS)> {
+    let store = ts_store.read().unwrap();
+    store
+        .iter()
+        .map(|(key, ts)| (key.clone(), ts.clone()))
+        .collect()
 }
 
 impl BTreeEngine {
-    pub(crate) fn new() -> Self {
-        let (bg_tx, bg_rx) = std::sync::mpsc::sync_channel(10);
-        let engine = BTreeEngine {
-            ts_store: common::new_shared_rw_lock(BTreeMap::new()),
-            background_task_tx: bg_tx,
+    /// new creates an engine holding the tables and series persisted in the data directory of
+    /// `options`
+    pub(crate) fn new(options: &EngineOptions) -> Result<Self, Error> {
+        let store = match &options.data_dir {
+            Some(dir) => Some(BlockStore::open(dir)?),
+            None => None,
+        };
+
+        let cache = BlockCache::new(options.memory_budget);
+        let (tables, series) = match &store {
+            Some(store) => {
+                let tables = store
+                    .load_tables()?
+                    .into_iter()
+                    .map(|options| (options.table_name.clone(), options))
+                    .collect();
+                (tables, load_series(store.load()?, &cache))
+            }
+            None => (BTreeMap::new(), BTreeMap::new()),
         };
-        engine.background_task(bg_rx);
-        engine
+
+        let mut engine = BTreeEngine {
+            ts_store: common::new_shared_rw_lock(series),
+            tables: common::new_shared_rw_lock(tables),
+            workers: Arc::new(WorkerPool::new(&options.ingest)),
+            store,
+            cache,
+            scheduler: Arc::new(Scheduler::new()),
+        };
+        let scheduler = engine.schedule(&options.maintenance);
+        scheduler.start();
+        engine.scheduler = Arc::new(scheduler);
+        Ok(engine)
     }
 
-    fn background_task(&self, bg_rx: Receiver<TS>) {
-        std::thread::spawn(move || {
-            let mut sources = Vec::new();
-            loop {
-                std::thread::sleep(Duration::from_secs(60));
+    /// the maintenance jobs, they work on a snapshot of the series map taken when they run
+    fn schedule(&self, options: &MaintenanceOptions) -> Scheduler {
+        let mut scheduler = Scheduler::new();
+
+        let ts_store = self.ts_store.clone();
+        let grace = options.roll_down_grace.as_secs();
+        scheduler.add("roll-down", options.roll_down, move || {
+            let closed: usize = series(&ts_store)
+                .iter()
+                .map(|(_, ts)| ts.roll_down(grace))
+                .sum();
+            if closed > 0 {
+                info!("rolled down {} blocks", closed);
+            }
+        });
+
+        if let Some(store) = self.store.clone() {
+            let ts_store = self.ts_store.clone();
+            scheduler.add("persistence", options.persistence, move || {
+                let mut written = 0;
+                for (key, ts) in series(&ts_store) {
+                    match ts.persist(&store, &key) {
+                        Ok(count) => written += count,
+                        Err(err) => error!("failed to persist {}: {}", key, err),
+                    }
+                }
+                if written > 0 {
+                    info!("persisted {} blocks", written);
+                }
+            });
+        }
 
-                match bg_rx.try_recv() {
-                    Ok(ts) => {
-                        &sources.push(ts);
+        // the rollup tiers have retentions of their own, the job runs without a raw one
+        let ts_store = self.ts_store.clone();
+        let period = options.retention_period;
+        scheduler.add("retention", options.retention, move || {
+            let mut dropped = 0;
+            for (key, ts) in series(&ts_store) {
+                let raw = match period {
+                    Some(period) => {
+                        let cutoff = ts
+                            .precision()
+                            .now()
+                            .saturating_sub(ts.precision().from_secs(period.as_secs()));
+                        ts.drop_before(cutoff)
                     }
-                    Err(_) => {}
+                    None => Ok(0),
+                };
+                match raw.and_then(|count| Ok(count + ts.expire_tiers()?)) {
+                    Ok(count) => dropped += count,
+                    Err(err) => error!("failed to drop old blocks of {}: {}", key, err),
                 }
+            }
+            if dropped > 0 {
+                info!("dropped {} blocks past retention", dropped);
+            }
+        });
 
-                for ts in &sources {
-                    ts.roll_down(100u64);
+        let ts_store = self.ts_store.clone();
+        let store = self.store.clone();
+        let window = options.compaction_window.as_secs();
+        let duplicates = options.duplicates;
+        scheduler.add("compaction", options.compaction, move || {
+            let mut compacted = Compaction::default();
+            for (key, ts) in series(&ts_store) {
+                let window = ts.precision().from_secs(window);
+                let files = store.as_ref().map(|s| (s, &key));
+                match ts.compact(window, duplicates, files) {
+                    Ok(c) => {
+                        compacted.windows += c.windows;
+                        compacted.blocks += c.blocks;
+                    }
+                    Err(err) => error!("failed to compact {}: {}", key, err),
                 }
             }
+            if compacted.windows > 0 {
+                info!(
+                    "compacted {} blocks into {} windows",
+                    compacted.blocks, compacted.windows
+                );
+            }
         });
+
+        let ts_store = self.ts_store.clone();
+        let workers = self.workers.clone();
+        let cache = self.cache.clone();
+        scheduler.add("stats", options.stats, move || {
+            let series = series(&ts_store);
+            let (active, closed) = series.iter().fold((0, 0), |(a, c), (_, ts)| {
+                let (active, closed) = ts.block_counts();
+                (a + active, c + closed)
+            });
+            let queued: usize = workers.stats().iter().map(|s| s.depth).sum();
+            let cached = cache.stats();
+            info!(
+                "stats: {} series, {} active blocks, {} closed blocks, {} queued points, {} block bytes in memory, {} hits, {} misses",
+                series.len(),
+                active,
+                closed,
+                queued,
+                cached.resident,
+                cached.hits,
+                cached.misses
+            );
+        });
+
+        scheduler
     }
 
-    fn append_ts(&self, ts: &TS, raw: Raw) {
-        ts.append_async(raw.data_point);
+    /// check `raw` against the value type and policy of its table
+    fn accept(&self, raw: &Raw) -> Result<bool, Error> {
+        let tables = self.tables.read().unwrap();
+        match tables.get(&raw.table_name) {
+            Some(options) => options.accept(&raw.data_point),
+            None => TableOptions::new(&raw.table_name).accept(&raw.data_point),
+        }
+    }
+
+    fn insert_key(&self, key: SeriesKey, dp: DataPoint) -> Result<(), Error> {
+        let mut store = self.ts_store.write().unwrap();
+        match store.get(&key) {
+            Some(ts) => self.append_ts(&key, ts, dp),
+            None => {
+                let options = self.table_options(&key.table);
+                let mut ts = TS::with_codec(options.precision, options.codec);
+                ts.set_cache(&self.cache);
+                if !options.rollups.is_empty() && options.value_type().is_numeric() {
+                    let tiers = options
+                        .rollups
+                        .iter()
+                        .map(|rule| {
+                            Tier::new(*rule, options.precision, options.codec, Some(&self.cache))
+                        })
+                        .collect();
+                    ts.set_tiers(tiers);
+                }
+
+                // the series is kept even if its first point is refused
+                let appended = self.append_ts(&key, &ts, dp);
+
+                info!("new key: {}", key);
+                store.insert(key, ts);
+                appended
+            }
+        }
+    }
+
+    fn append_ts(&self, key: &SeriesKey, ts: &TS, dp: DataPoint) -> Result<(), Error> {
+        self.workers.append(&key.key, ts, dp)
         //        info!("append raw: {}", raw.to_string());
     }
 }
 
-impl Engine for BTreeEngine {
-    fn create_key(&self, raw: Raw) {
-        let mut store = self.ts_store.write().unwrap();
-        match store.get(&raw.key) {
-            Some(ts) => {
-                self.append_ts(ts, raw);
+/// the series of the loaded files with their tiers, their blocks accounted in `cache`. A series
+/// whose raw blocks were all dropped past retention is made of the header of its tiers.
+fn load_series(loaded: Vec<LoadedSeries>, cache: &Arc<BlockCache>) -> TSTreeMap {
+    let mut raw = BTreeMap::new();
+    let mut tiers: BTreeMap<SeriesKey, Vec<(RollupRule, Rollup, LoadedSeries)>> = BTreeMap::new();
+    for series in loaded {
+        match rollup::parse_tier_key(&series.key.key) {
+            Some((key, rule, rollup)) => {
+                let key = SeriesKey::new(&series.key.table, &key);
+                tiers.entry(key).or_default().push((rule, rollup, series))
             }
             None => {
-                let ts = TS::new(100000);
-                self.background_task_tx.send(ts.clone()).unwrap();
+                raw.insert(series.key.clone(), series);
+            }
+        }
+    }
 
-                let key = raw.key.to_string();
-                self.append_ts(&ts, raw);
+    // the first block tells how a series is encoded
+    let header = |series: &LoadedSeries| match series.blocks.first() {
+        Some(block) => block
+            .bytes()
+            .ok()
+            .and_then(|bytes| tszv1::format::read_header(bytes.as_ref()).ok().flatten()),
+        None => None,
+    };
 
-                store.insert(key.to_string(), ts);
-                info!("new key: {}", key);
+    let mut series = BTreeMap::new();
+    for (key, loaded) in raw {
+        if let Some(header) = header(&loaded) {
+            info!("load key: {}, {} blocks", key, loaded.blocks.len());
+            let mut ts = TS::from_closed(
+                header.precision,
+                header.codec,
+                loaded.blocks,
+                loaded.tombstones,
+            );
+            ts.set_cache(cache);
+            series.insert(key, ts);
+        }
+    }
+
+    for (key, mut loaded) in tiers {
+        let (precision, codec) = match series.get(&key) {
+            Some(ts) => (ts.precision(), ts.codec()),
+            None => match loaded
+                .iter()
+                .find(|(_, rollup, _)| *rollup == Rollup::Min)
+                .and_then(|(_, _, s)| header(s))
+            {
+                Some(header) => (header.precision, header.codec),
+                None => continue,
+            },
+        };
+        let ts = series.entry(key.clone()).or_insert_with(|| {
+            let mut ts = TS::with_codec(precision, codec);
+            ts.set_cache(cache);
+            ts
+        });
+        // the closed blocks were rolled up before they were written
+        let covered = ts.closed_until();
+
+        let mut rules: Vec<RollupRule> = Vec::new();
+        for (rule, _, _) in &loaded {
+            if !rules.contains(rule) {
+                rules.push(*rule);
             }
         }
+        let mut ts_tiers = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let period = Tier::period(&rule, precision);
+            let rollups = Rollup::ALL
+                .iter()
+                .map(|rollup| {
+                    let at = loaded
+                        .iter()
+                        .position(|(r, kind, _)| *r == rule && kind == rollup);
+                    let (blocks, tombstones) = match at {
+                        Some(at) => {
+                            let (_, _, s) = loaded.swap_remove(at);
+                            (s.blocks, s.tombstones)
+                        }
+                        None => (Vec::new(), Vec::new()),
+                    };
+                    let codec = match *rollup {
+                        Rollup::Count => CodecId::Integer,
+                        _ => codec,
+                    };
+                    let mut ts = TS::from_closed(precision, codec, blocks, tombstones);
+                    ts.set_period(period);
+                    ts.set_cache(cache);
+                    ts
+                })
+                .collect();
+            let tier = Tier::with_series(rule, precision, rollups);
+            tier.cover(covered);
+            info!("load tier of key: {}, {}s", key, rule.interval);
+            ts_tiers.push(tier);
+        }
+        ts.set_tiers(ts_tiers);
     }
+    series
+}
+
+impl Engine for BTreeEngine {
+    fn create_table(&self, options: TableOptions) -> Result<(), Error> {
+        info!("create table: {:?}", options);
+        // the series are locked first like in `insert_key`, no series can be created with the
+        // replaced options
+        let series = self.ts_store.read().unwrap();
+        let mut tables = self.tables.write().unwrap();
+        let name = &options.table_name;
+        let current = match tables.get(name) {
+            Some(current) => current.clone(),
+            None => TableOptions::new(name),
+        };
+        if series.keys().any(|key| &key.table == name) {
+            if options.precision != current.precision {
+                return Err(Error::TableConflict(format!(
+                    "table {} has series in {:?} precision, it can not change to {:?}",
+                    name, current.precision, options.precision
+                )));
+            }
+            // the series keep their codec, another codec of the same value type only applies to
+            // new series
+            if options.value_type() != current.value_type() {
+                return Err(Error::TableConflict(format!(
+                    "table {} has series of {} values, it can not change to {}",
+                    name,
+                    current.value_type().name(),
+                    options.value_type().name()
+                )));
+            }
+        }
 
-    fn append(&self, raw: Raw) {
+        if let Some(store) = &self.store {
+            store.write_table(&options)?;
+        }
+        tables.insert(options.table_name.to_string(), options);
+        Ok(())
+    }
+
+    fn table_options(&self, table_name: &str) -> TableOptions {
+        let tables = self.tables.read().unwrap();
+        match tables.get(table_name) {
+            Some(options) => options.clone(),
+            None => TableOptions::new(table_name),
+        }
+    }
+
+    fn create_key(&self, raw: Raw) -> Result<Appended, Error> {
+        if !self.accept(&raw)? {
+            return Ok(Appended::Dropped);
+        }
+
+        self.insert_key(SeriesKey::new(&raw.table_name, &raw.key), raw.data_point)?;
+        Ok(Appended::Stored)
+    }
+
+    fn append(&self, raw: Raw) -> Result<Appended, Error> {
+        if !self.accept(&raw)? {
+            return Ok(Appended::Dropped);
+        }
+
+        let key = SeriesKey {
+            table: raw.table_name,
+            key: raw.key,
+        };
         {
             let store = self.ts_store.read().unwrap();
-            match store.get(&raw.key) {
+            match store.get(&key) {
                 Some(ts) => {
-                    self.append_ts(ts, raw);
-                    return;
+                    self.append_ts(&key, ts, raw.data_point)?;
+                    return Ok(Appended::Stored);
                 }
                 None => {}
             };
         }
-        self.create_key(raw);
+        self.insert_key(key, raw.data_point)?;
+        Ok(Appended::Stored)
+    }
+
+    fn flush(&self) {
+        self.workers.flush();
+    }
+
+    fn queue_stats(&self) -> Vec<QueueStats> {
+        self.workers.stats()
+    }
+
+    fn delete_series(&self, table_name: &str, matchers: &[SeriesMatcher]) -> Result<usize, Error> {
+        let mut store = self.ts_store.write().unwrap();
+        let keys: Vec<SeriesKey> = store
+            .keys()
+            .filter(|key| key.table == table_name && matchers.iter().any(|m| m.matches(&key.key)))
+            .cloned()
+            .collect();
+        for key in &keys {
+            // the files go first, a failure leaves the series in place
+            store[key].delete(self.store.as_ref().map(|s| (s, key)))?;
+            store.remove(key);
+            info!("delete key: {}", key);
+        }
+        Ok(keys.len())
+    }
+
+    fn delete_range(
+        &self,
+        table_name: &str,
+        matchers: &[SeriesMatcher],
+        begin: u64,
+        end: u64,
+    ) -> Result<usize, Error> {
+        let mut deleted = 0;
+        for (key, ts) in series(&self.ts_store) {
+            if key.table == table_name && matchers.iter().any(|m| m.matches(&key.key)) {
+                ts.delete_range(begin, end, self.store.as_ref().map(|s| (s, &key)))?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn job_stats(&self) -> Vec<JobStats> {
+        self.scheduler.stats()
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    fn shutdown(&self) -> Result<ShutdownSummary, Error> {
+        // no roll down or persistence runs along with the one below
+        self.scheduler.stop();
+        let drained = self.workers.shutdown();
+
+        let store = self.ts_store.read().unwrap();
+        let mut summary = ShutdownSummary {
+            series: store.len(),
+            drained,
+            ..ShutdownSummary::default()
+        };
+        for (key, ts) in store.iter() {
+            summary.blocks_closed += ts.close_all();
+            if let Some(block_store) = &self.store {
+                summary.blocks_persisted += ts.persist(block_store, key)?;
+            }
+        }
+        Ok(summary)
     }
 
-    fn get(&self, _table_name: &String, key: &String) -> Option<TS> {
+    fn get(&self, table_name: &String, key: &String) -> Option<TS> {
         let store = self.ts_store.read().unwrap();
-        match store.get(key) {
+        match store.get(&SeriesKey::new(table_name, key)) {
             Some(ts) => Some(ts.clone()),
             None => None,
         }
@@ -3,10 +3,29 @@ extern crate log;
 extern crate log4rs;
 
 mod block;
+mod cache;
 mod engine;
+pub mod query;
+pub mod rollup;
+mod scheduler;
+mod store;
+pub mod table;
 mod ts;
+mod worker;
 
+pub use crate::cache::CacheStats;
+pub use crate::query::Aggregation;
+pub use crate::rollup::RollupRule;
+pub use crate::scheduler::{JobOptions, JobStats, MaintenanceOptions};
+pub use crate::table::{TableOptions, ValueAction, ValuePolicy};
+pub use crate::ts::Tombstone;
 use crate::ts::TS;
+pub use crate::worker::{IngestOptions, OverloadPolicy, QueueStats};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+pub use tszv1::format::CodecId;
+pub use tszv1::rewrite::Duplicates;
 use tszv1::DataPoint;
 
 #[derive(Debug)]
@@ -25,42 +44,552 @@ impl Raw {
     }
 }
 
+/// SeriesMatcher
+///
+/// Selects series by their key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeriesMatcher {
+    Key(String),
+    /// every series whose key starts with the prefix
+    Prefix(String),
+}
+
+impl SeriesMatcher {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            SeriesMatcher::Key(k) => key == k,
+            SeriesMatcher::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Error
+///
+/// Error encapsulates the errors returned by an `Engine` to its callers.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// the value of the data point is refused by the value policy of the table
+    ValueRejected(String),
+    /// the value of the data point is not of the value type of the table
+    TypeMismatch(String),
+    /// the query can not be answered, e.g. an aggregate overflows
+    InvalidQuery(String),
+    /// a stored block can not be decoded
+    Decode(tszv1::decode::Error),
+    /// the ingestion queue is full, the append may be retried after the given time
+    Overloaded(String, Duration),
+    /// the ingestion workers stopped or the engine is shut down
+    Unavailable(String),
+    /// a block file can not be read or written
+    Storage(String),
+    /// the new options of a table do not fit the series already in it
+    TableConflict(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ValueRejected(ref msg) => write!(f, "Value rejected: {}", msg),
+            Error::TypeMismatch(ref msg) => write!(f, "Type mismatch: {}", msg),
+            Error::InvalidQuery(ref msg) => write!(f, "Invalid query: {}", msg),
+            Error::Decode(ref err) => write!(f, "Corrupted block: {}", err),
+            Error::Overloaded(ref msg, _) => write!(f, "Overloaded: {}", msg),
+            Error::Unavailable(ref msg) => write!(f, "Unavailable: {}", msg),
+            Error::Storage(ref msg) => write!(f, "Storage error: {}", msg),
+            Error::TableConflict(ref msg) => write!(f, "Table conflict: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<tszv1::decode::Error> for Error {
+    fn from(err: tszv1::decode::Error) -> Self {
+        Error::Decode(err)
+    }
+}
+
+/// Appended
+///
+/// The outcome of a successful append.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Appended {
+    Stored,
+    /// the data point was discarded by the value policy of the table
+    Dropped,
+}
+
+/// ShutdownSummary
+///
+/// What `Engine::shutdown` did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutdownSummary {
+    pub series: usize,
+    /// points still queued when the shutdown began, all of them were encoded
+    pub drained: u64,
+    /// active blocks rolled down to closed blocks
+    pub blocks_closed: usize,
+    /// block files written, 0 for an engine without a data directory
+    pub blocks_persisted: usize,
+}
+
+/// EngineOptions
+///
+/// The settings an engine is created with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineOptions {
+    /// where the closed blocks are persisted and loaded from at start, `None` keeps the blocks
+    /// in memory only
+    pub data_dir: Option<PathBuf>,
+    pub ingest: IngestOptions,
+    pub maintenance: MaintenanceOptions,
+    /// bytes of closed blocks held in memory, over it the persisted blocks read least recently
+    /// are evicted and read back from their files when searched. `None` keeps every block.
+    pub memory_budget: Option<u64>,
+}
+
 pub trait Engine {
-    fn create_key(&self, raw: Raw);
-    fn append(&self, raw: Raw);
+    /// create or replace the options of a table, they are kept in the data directory. The
+    /// precision and the value type of a table which has series can not change.
+    fn create_table(&self, options: TableOptions) -> Result<(), Error>;
+    fn table_options(&self, table_name: &str) -> TableOptions;
+    fn create_key(&self, raw: Raw) -> Result<Appended, Error>;
+    fn append(&self, raw: Raw) -> Result<Appended, Error>;
+    /// wait until the points appended so far are visible to searches
+    fn flush(&self);
+    /// the state of the ingestion queues
+    fn queue_stats(&self) -> Vec<QueueStats>;
+    /// delete the series of the table `table_name` selected by any of `matchers` with all their
+    /// points, returns the count of series deleted
+    fn delete_series(&self, table_name: &str, matchers: &[SeriesMatcher]) -> Result<usize, Error>;
+    /// delete the points in [begin, end) of the series of the table `table_name` selected by any
+    /// of `matchers`, the times are in the precision of the table. Searches skip the points at
+    /// once, compaction drops them from the blocks. Returns the count of series selected.
+    fn delete_range(
+        &self,
+        table_name: &str,
+        matchers: &[SeriesMatcher],
+        begin: u64,
+        end: u64,
+    ) -> Result<usize, Error>;
+    /// the runs of the maintenance jobs
+    fn job_stats(&self) -> Vec<JobStats>;
+    /// the closed blocks in memory and their hits and misses
+    fn cache_stats(&self) -> CacheStats;
+    /// refuse further appends, wait for the queued points, roll down every active block and
+    /// write the closed blocks to the data directory, the block files are synced
+    fn shutdown(&self) -> Result<ShutdownSummary, Error>;
     fn get(&self, table_name: &String, key: &String) -> Option<TS>;
 }
 
 pub fn create_engine(engine_type: &str) -> Option<Box<dyn Engine + Send + Sync>> {
+    create_engine_with(engine_type, EngineOptions::default())
+        .ok()
+        .flatten()
+}
+
+/// create_engine_with creates an engine set up by `options`, `None` for an unknown type. It
+/// fails when the data directory can not be loaded.
+pub fn create_engine_with(
+    engine_type: &str,
+    options: EngineOptions,
+) -> Result<Option<Box<dyn Engine + Send + Sync>>, Error> {
     if engine_type.eq("b-tree") {
-        Some(Box::new(engine::BTreeEngine::new()))
+        Ok(Some(Box::new(engine::BTreeEngine::new(&options)?)))
     } else {
-        None
+        Ok(None)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{create_engine, Raw};
+    use crate::query::{aggregate_series, Aggregation};
+    use crate::ts::TS;
+    use crate::{
+        create_engine_with, CodecId, Engine, EngineOptions, Error, IngestOptions, JobOptions,
+        MaintenanceOptions, OverloadPolicy, Raw, RollupRule, SeriesMatcher, TableOptions,
+    };
+    use common::TimePrecision;
+    use std::path::PathBuf;
+    use std::time::Duration;
     use tszv1::DataPoint;
 
+    /// a fresh data directory named after `name` and the options of an engine keeping its
+    /// blocks there
+    fn data_dir(name: &str) -> (PathBuf, EngineOptions) {
+        let dir = crate::store::temp_dir(name);
+        let options = EngineOptions {
+            data_dir: Some(dir.clone()),
+            ..EngineOptions::default()
+        };
+        (dir, options)
+    }
+
+    fn open(options: &EngineOptions) -> Box<dyn Engine + Send + Sync> {
+        create_engine_with("b-tree", options.clone())
+            .unwrap()
+            .unwrap()
+    }
+
+    fn raw(table: &str, key: &str, data_point: DataPoint) -> Raw {
+        Raw {
+            table_name: table.to_string(),
+            key: key.to_string(),
+            data_point,
+        }
+    }
+
+    fn get(engine: &dyn Engine, table: &str, key: &str) -> Option<TS> {
+        engine.get(&table.to_string(), &key.to_string())
+    }
+
+    /// every point of the series
+    fn points(ts: &TS) -> Vec<DataPoint> {
+        ts.get_decoder(0, u64::MAX, 0, |decoder, dp_vec| {
+            for dp in decoder {
+                dp_vec.push(dp?);
+            }
+            Ok(())
+        })
+        .unwrap()
+    }
+
     #[test]
     fn engine_test() {
-        let engine = create_engine("b-tree").unwrap();
+        // wait for the workers rather than fail when the producer outruns them
+        let ingest = IngestOptions {
+            policy: OverloadPolicy::Block(Duration::from_secs(60)),
+            ..IngestOptions::default()
+        };
+        let engine = open(&EngineOptions {
+            ingest,
+            ..EngineOptions::default()
+        });
         let begin = common::now_timestamp_secs();
 
         for i in 0..1000000 {
-            engine.append(Raw {
-                table_name: "table".to_string(),
-                key: "k".to_string(),
-                data_point: DataPoint {
-                    time: common::now_timestamp_secs(),
-                    value: i as f64,
-                },
-            })
+            let data_point = DataPoint::new(common::now_timestamp_secs(), i as f64);
+            engine.append(raw("table", "k", data_point)).unwrap();
         }
 
         let end = common::now_timestamp_secs();
         println!("time spend: {}", end - begin);
+
+        // the points are searchable once the shard workers caught up
+        engine.flush();
+        let ts = get(engine.as_ref(), "table", "k").unwrap();
+        assert_eq!(points(&ts).len(), 1000000);
+    }
+
+    #[test]
+    fn shutdown_and_reload() {
+        let (dir, options) = data_dir("engine");
+        let point = |time: u64| DataPoint::new(time, time as f64);
+        let search = |engine: &dyn Engine, key: &str| points(&get(engine, "table", key).unwrap());
+
+        // two blocks of "a", one of "b"
+        let engine = open(&options);
+        for time in 0..100 {
+            engine.append(raw("table", "a", point(time * 100))).unwrap();
+        }
+        engine.append(raw("table", "b", point(1))).unwrap();
+
+        let summary = engine.shutdown().unwrap();
+        assert_eq!(summary.series, 2);
+        assert_eq!(summary.blocks_closed, 3);
+        assert_eq!(summary.blocks_persisted, 3);
+        // the points are still searched, now in closed blocks
+        assert_eq!(search(engine.as_ref(), "a").len(), 100);
+        match engine.append(raw("table", "a", point(10_000))) {
+            Err(Error::Unavailable(_)) => {}
+            other => panic!("append after shutdown: {:?}", other),
+        }
+        // nothing is left to write
+        assert_eq!(engine.shutdown().unwrap().blocks_persisted, 0);
+        drop(engine);
+
+        let engine = open(&options);
+        let expected: Vec<DataPoint> = (0..100).map(|time| point(time * 100)).collect();
+        assert_eq!(search(engine.as_ref(), "a"), expected);
+        assert_eq!(search(engine.as_ref(), "b"), vec![DataPoint::new(1, 1.0)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn maintenance_jobs() {
+        let (dir, mut options) = data_dir("maintenance");
+        let every = JobOptions {
+            interval: Duration::from_millis(10),
+            jitter: Duration::from_millis(1),
+        };
+        options.maintenance = MaintenanceOptions {
+            roll_down: every,
+            roll_down_grace: Duration::from_secs(0),
+            persistence: every,
+            retention: every,
+            retention_period: Some(Duration::from_secs(365 * 24 * 60 * 60)),
+            compaction: every,
+            stats: every,
+            ..MaintenanceOptions::default()
+        };
+        let engine = open(&options);
+
+        // the periods of these blocks ended long ago, the first block is past retention
+        let now = common::now_timestamp_secs();
+        for (i, key) in ["a", "b", "c"].iter().enumerate() {
+            let data_point = DataPoint::new(now - 10 * 24 * 60 * 60 + i as u64, 1.0);
+            engine.append(raw("table", key, data_point)).unwrap();
+        }
+        engine
+            .append(raw("table", "old", DataPoint::new(1, 1.0)))
+            .unwrap();
+        engine.flush();
+
+        // every series is worked on, not only the first one of a burst
+        let block_counts =
+            |engine: &dyn Engine, key: &str| get(engine, "table", key).unwrap().block_counts();
+        let settled = |engine: &dyn Engine| {
+            ["a", "b", "c"]
+                .iter()
+                .all(|key| block_counts(engine, key) == (0, 1))
+                && block_counts(engine, "old") == (0, 0)
+        };
+        let begin = std::time::Instant::now();
+        while !settled(engine.as_ref()) {
+            assert!(begin.elapsed() < Duration::from_secs(10), "not rolled down");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let begin = std::time::Instant::now();
+        let series_dirs = |dir: &std::path::Path| match std::fs::read_dir(dir) {
+            Ok(tables) => tables
+                .map(|table| std::fs::read_dir(table.unwrap().path()).unwrap().count())
+                .sum(),
+            Err(_) => 0,
+        };
+        while series_dirs(&dir) < 3 {
+            assert!(begin.elapsed() < Duration::from_secs(10), "not persisted");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let summary = engine.shutdown().unwrap();
+        assert_eq!(summary.blocks_closed, 0);
+        let stats = engine.job_stats();
+        let names: Vec<&str> = stats.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "roll-down",
+                "persistence",
+                "retention",
+                "compaction",
+                "stats"
+            ]
+        );
+        assert!(stats.iter().all(|s| s.runs > 0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tables_own_their_series() {
+        let (dir, options) = data_dir("tables");
+        let search = |engine: &dyn Engine, table: &str| {
+            let ts = get(engine, table, "k").unwrap();
+            (ts.precision(), points(&ts))
+        };
+
+        let engine = open(&options);
+        let mut millis = TableOptions::new("millis");
+        millis.precision = TimePrecision::Millis;
+        engine.create_table(millis.clone()).unwrap();
+        for (table, time) in [("secs", 10), ("millis", 10_500)].iter() {
+            engine
+                .append(raw(table, "k", DataPoint::new(*time, 1.0)))
+                .unwrap();
+        }
+        engine.shutdown().unwrap();
+
+        // the same key in two tables is two series, also once read back
+        let expected = |engine: &dyn Engine| {
+            assert_eq!(
+                search(engine, "secs"),
+                (TimePrecision::Seconds, vec![DataPoint::new(10, 1.0)])
+            );
+            assert_eq!(
+                search(engine, "millis"),
+                (TimePrecision::Millis, vec![DataPoint::new(10_500, 1.0)])
+            );
+            assert!(get(engine, "other", "k").is_none());
+        };
+        expected(engine.as_ref());
+        drop(engine);
+        let engine = open(&options);
+        expected(engine.as_ref());
+
+        // the options of a created table are read back, new series of the table follow them
+        assert_eq!(engine.table_options("millis"), millis);
+        assert_eq!(engine.table_options("secs"), TableOptions::new("secs"));
+        engine
+            .append(raw("millis", "new", DataPoint::new(20_500, 1.0)))
+            .unwrap();
+        let ts = get(engine.as_ref(), "millis", "new").unwrap();
+        assert_eq!(ts.precision(), TimePrecision::Millis);
+
+        // the series keep their precision, so does a table with series
+        let mut nanos = millis.clone();
+        nanos.precision = TimePrecision::Nanos;
+        let mut secs = TableOptions::new("secs");
+        secs.precision = TimePrecision::Millis;
+        for options in [nanos, secs].iter() {
+            match engine.create_table(options.clone()) {
+                Err(Error::TableConflict(_)) => {}
+                other => panic!("precision changed: {:?}", other),
+            }
+        }
+        assert_eq!(engine.table_options("millis"), millis);
+        assert_eq!(engine.table_options("secs"), TableOptions::new("secs"));
+
+        // the value type is kept as well, another float codec only applies to new series
+        let mut strings = TableOptions::new("secs");
+        strings.codec = CodecId::String;
+        match engine.create_table(strings) {
+            Err(Error::TableConflict(_)) => {}
+            other => panic!("value type changed: {:?}", other),
+        }
+        assert_eq!(engine.table_options("secs"), TableOptions::new("secs"));
+        let mut chimp = TableOptions::new("secs");
+        chimp.codec = CodecId::Chimp;
+        engine.create_table(chimp.clone()).unwrap();
+        assert_eq!(engine.table_options("secs"), chimp);
+        engine
+            .append(raw("secs", "chimp", DataPoint::new(20, 2.0)))
+            .unwrap();
+        assert_eq!(
+            get(engine.as_ref(), "secs", "k").unwrap().codec(),
+            CodecId::GorillaXor
+        );
+        assert_eq!(
+            get(engine.as_ref(), "secs", "chimp").unwrap().codec(),
+            CodecId::Chimp
+        );
+        let mut empty = TableOptions::new("empty");
+        empty.precision = TimePrecision::Nanos;
+        engine.create_table(empty.clone()).unwrap();
+        empty.precision = TimePrecision::Micros;
+        engine.create_table(empty.clone()).unwrap();
+        assert_eq!(engine.table_options("empty"), empty);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deletes() {
+        let (dir, options) = data_dir("deletes");
+        let search = |engine: &dyn Engine, table: &str, key: &str| {
+            let ts = get(engine, table, key)?;
+            Some(points(&ts).iter().map(|dp| dp.time).collect::<Vec<u64>>())
+        };
+
+        let engine = open(&options);
+        for table in &["table", "other"] {
+            for key in &["cpu.a", "cpu.b", "mem.a"] {
+                for time in 0..10 {
+                    engine
+                        .append(raw(table, key, DataPoint::new(time, 1.0)))
+                        .unwrap();
+                }
+            }
+        }
+        engine.flush();
+
+        let cpu = [SeriesMatcher::Prefix("cpu.".to_string())];
+        assert_eq!(engine.delete_range("table", &cpu, 2, 8).unwrap(), 2);
+        assert_eq!(
+            search(engine.as_ref(), "table", "cpu.b"),
+            Some(vec![0, 1, 8, 9])
+        );
+        assert_eq!(search(engine.as_ref(), "table", "mem.a").unwrap().len(), 10);
+
+        let mem = [SeriesMatcher::Key("mem.a".to_string())];
+        assert_eq!(engine.delete_series("table", &mem).unwrap(), 1);
+        assert_eq!(search(engine.as_ref(), "table", "mem.a"), None);
+        assert_eq!(engine.delete_series("table", &mem).unwrap(), 0);
+        assert_eq!(engine.delete_series("missing", &mem).unwrap(), 0);
+
+        // the deletes outlive a restart and leave the same keys of other tables alone
+        engine.shutdown().unwrap();
+        drop(engine);
+        let engine = open(&options);
+        assert_eq!(
+            search(engine.as_ref(), "table", "cpu.a"),
+            Some(vec![0, 1, 8, 9])
+        );
+        assert_eq!(search(engine.as_ref(), "table", "mem.a"), None);
+        for key in &["cpu.a", "cpu.b", "mem.a"] {
+            assert_eq!(search(engine.as_ref(), "other", key).unwrap().len(), 10);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn memory_budget() {
+        let (dir, mut options) = data_dir("budget");
+        options.memory_budget = Some(1);
+        let engine = open(&options);
+        for time in 0..100 {
+            engine
+                .append(raw("table", "k", DataPoint::new(time * 100, 1.0)))
+                .unwrap();
+        }
+        engine.shutdown().unwrap();
+        // both persisted blocks are evicted, searches map their files
+        let stats = engine.cache_stats();
+        assert_eq!((stats.resident, stats.evictions), (0, 2));
+
+        let ts = get(engine.as_ref(), "table", "k").unwrap();
+        assert_eq!(points(&ts).len(), 100);
+        // the first block only is searched, in place as the mapped bytes are not evicted
+        ts.get_decoder(0, 100, 0, |_, _| Ok(())).unwrap();
+        let stats = engine.cache_stats();
+        // the block files were written from memory
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+        assert_eq!((stats.resident, stats.mapped), (0, 2));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollups() {
+        let (dir, options) = data_dir("rollups");
+        let sums = |engine: &dyn Engine, step: u64| {
+            let ts = get(engine, "table", "k").unwrap();
+            aggregate_series(&[ts], Aggregation::Sum, 0, 7199, step).unwrap()
+        };
+
+        let engine = open(&options);
+        let mut table = TableOptions::new("table");
+        table.rollups = vec![RollupRule::new(600, None)];
+        engine.create_table(table).unwrap();
+        for time in 0..7200 {
+            engine
+                .append(raw("table", "k", DataPoint::new(time, 1.0)))
+                .unwrap();
+        }
+        engine.shutdown().unwrap();
+        let expected: Vec<DataPoint> = (0..6).map(|i| DataPoint::new(i * 1200, 1200.0)).collect();
+        assert_eq!(sums(engine.as_ref(), 1200), expected);
+        drop(engine);
+
+        // the tier is read back with the series, and outlives the raw blocks
+        let engine = open(&options);
+        assert_eq!(sums(engine.as_ref(), 1200), expected);
+        let ts = get(engine.as_ref(), "table", "k").unwrap();
+        ts.drop_before(7200).unwrap();
+        assert_eq!(sums(engine.as_ref(), 1200), expected);
+        assert!(sums(engine.as_ref(), 900).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
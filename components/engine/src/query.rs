@@ -0,0 +1,528 @@
+use crate::rollup::{self, Summary};
+use crate::ts::TS;
+use crate::Error;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use tszv1::{DDSketch, DataPoint, Decode, Value, ValueType};
+
+/// Aggregation
+///
+/// A function applied to the data points of each time bucket of a query. Integer series keep
+/// integer results for count, sum, min, max, first and last, avg is always a float. Bool and
+/// string series only support count, first and last.
+///
+/// Merge and quantile combine the sketches of a bucket, numeric values are counted into a
+/// sketch of the default accuracy first. Sketch series support count, first, last, merge and
+/// quantile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    First,
+    Last,
+    Merge,
+    /// the quantile in [0, 1], NaN for a bucket of empty sketches
+    Quantile(f64),
+}
+
+impl Aggregation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregation::Count => "count",
+            Aggregation::Sum => "sum",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Avg => "avg",
+            Aggregation::First => "first",
+            Aggregation::Last => "last",
+            Aggregation::Merge => "merge",
+            Aggregation::Quantile(_) => "quantile",
+        }
+    }
+
+    /// the function called `name`, quantiles are named after their percentile as `p99` or
+    /// `p99.9`
+    pub fn from_name(name: &str) -> Option<Aggregation> {
+        if let Some(percentile) = name.strip_prefix('p') {
+            return match percentile.parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Some(Aggregation::Quantile(p / 100.0)),
+                _ => None,
+            };
+        }
+
+        match name {
+            "count" => Some(Aggregation::Count),
+            "sum" => Some(Aggregation::Sum),
+            "min" => Some(Aggregation::Min),
+            "max" => Some(Aggregation::Max),
+            "avg" => Some(Aggregation::Avg),
+            "first" => Some(Aggregation::First),
+            "last" => Some(Aggregation::Last),
+            "merge" => Some(Aggregation::Merge),
+            _ => None,
+        }
+    }
+
+    /// whether the function needs values which can be ordered and summed
+    pub fn is_numeric(self) -> bool {
+        match self {
+            Aggregation::Count | Aggregation::First | Aggregation::Last => false,
+            Aggregation::Merge | Aggregation::Quantile(_) => false,
+            Aggregation::Sum | Aggregation::Min | Aggregation::Max | Aggregation::Avg => true,
+        }
+    }
+
+    /// whether the function works on distributions, which numeric values are converted to
+    pub fn is_distribution(self) -> bool {
+        matches!(self, Aggregation::Merge | Aggregation::Quantile(_))
+    }
+
+    /// apply the function to the values of one bucket, `values` is not empty and in time order
+    fn apply(self, values: &[Value]) -> Result<Value, Error> {
+        if self.is_numeric() {
+            if let Some(v) = values.iter().find(|v| !v.value_type().is_numeric()) {
+                return Err(Error::InvalidQuery(format!(
+                    "function {} is only supported by numeric series, got {} values",
+                    self.name(),
+                    v.value_type().name()
+                )));
+            }
+        }
+        if self.is_distribution() {
+            if let Some(v) = values
+                .iter()
+                .find(|v| !v.value_type().is_numeric() && v.value_type() != ValueType::Sketch)
+            {
+                return Err(Error::InvalidQuery(format!(
+                    "function {} is only supported by numeric and sketch series, got {} values",
+                    self.name(),
+                    v.value_type().name()
+                )));
+            }
+        }
+
+        let integers = values.iter().all(|v| matches!(v, Value::Integer(_)));
+        match self {
+            Aggregation::Count => Ok(Value::Integer(values.len() as i64)),
+            Aggregation::First => Ok(values[0].clone()),
+            Aggregation::Last => Ok(values[values.len() - 1].clone()),
+            Aggregation::Min => Ok(values[1..].iter().fold(values[0].clone(), |a, b| a.min(b))),
+            Aggregation::Max => Ok(values[1..].iter().fold(values[0].clone(), |a, b| a.max(b))),
+            Aggregation::Sum if integers => {
+                let sum = integer_sum(values);
+                i64::try_from(sum).map(Value::Integer).map_err(|_| {
+                    Error::InvalidQuery(format!("sum {} overflows a 64 bit integer", sum))
+                })
+            }
+            Aggregation::Sum => Ok(Value::Float(values.iter().map(|v| v.as_f64()).sum())),
+            Aggregation::Avg if integers => Ok(Value::Float(
+                integer_sum(values) as f64 / values.len() as f64,
+            )),
+            Aggregation::Avg => {
+                let sum: f64 = values.iter().map(|v| v.as_f64()).sum();
+                Ok(Value::Float(sum / values.len() as f64))
+            }
+            Aggregation::Merge => Ok(Value::from(merge_sketches(values)?)),
+            Aggregation::Quantile(q) => {
+                let sketch = merge_sketches(values)?;
+                Ok(Value::Float(sketch.quantile(q).unwrap_or(f64::NAN)))
+            }
+        }
+    }
+}
+
+/// the merge of the sketches in `values`, other values are counted into a sketch of the default
+/// accuracy
+fn merge_sketches(values: &[Value]) -> Result<DDSketch, Error> {
+    let invalid = |e| Error::InvalidQuery(format!("can not merge sketches: {}", e));
+
+    let mut numbers = Vec::new();
+    let mut sketches = Vec::new();
+    for v in values {
+        match v {
+            Value::Sketch(sketch) => sketches.push(sketch.as_ref()),
+            other => numbers.push(other.as_f64()),
+        }
+    }
+
+    let mut merged = match sketches.first() {
+        Some(first) if numbers.is_empty() => DDSketch::new(first.alpha).map_err(invalid)?,
+        _ => DDSketch::from_values(&numbers).map_err(invalid)?,
+    };
+    for sketch in sketches {
+        merged.merge(sketch).map_err(invalid)?;
+    }
+    Ok(merged)
+}
+
+/// the exact sum of integer values, a bucket would need 2^64 values to overflow an i128
+fn integer_sum(values: &[Value]) -> i128 {
+    values.iter().map(|v| i128::from(v.as_i64())).sum()
+}
+
+/// aggregate the data points in [begin_time, end_time] into buckets of `step` starting at
+/// `begin_time`, a step of 0 is a single bucket. Each result is stamped with the start of its
+/// bucket, empty buckets are skipped. The data points of several series may be passed
+/// together, they are aggregated as one series.
+pub fn aggregate(
+    datapoints: &[DataPoint],
+    aggregation: Aggregation,
+    begin_time: u64,
+    end_time: u64,
+    step: u64,
+) -> Result<Vec<DataPoint>, Error> {
+    let mut buckets: BTreeMap<u64, Vec<DataPoint>> = BTreeMap::new();
+    for dp in datapoints {
+        if dp.time < begin_time || dp.time > end_time {
+            continue;
+        }
+        let bucket = match step {
+            0 => begin_time,
+            _ => begin_time + (dp.time - begin_time) / step * step,
+        };
+        buckets.entry(bucket).or_default().push(dp.clone());
+    }
+
+    let mut result = Vec::with_capacity(buckets.len());
+    for (time, mut bucket) in buckets {
+        // blocks may hold out of order points, first and last follow time
+        bucket.sort_by_key(|dp| dp.time);
+        let values: Vec<Value> = bucket.iter().map(|dp| dp.value.clone()).collect();
+        result.push(DataPoint {
+            time,
+            value: aggregation.apply(&values)?,
+        });
+    }
+    Ok(result)
+}
+
+/// aggregate the data points of `series` in [begin_time, end_time] like `aggregate`. A series
+/// rolled up answers count, sum, min, max, avg and last from its coarsest tier whose intervals
+/// fall into the buckets, up to the time its closed blocks were rolled up, and from its raw
+/// points after it. Points appended late to a block older than that time are not seen by such
+/// a query until their block is closed. The intervals a delete hid from the tier are read from
+/// the raw points as well, their points are missed once the raw blocks are past retention.
+/// Other functions and queries no tier fits read the raw points.
+pub fn aggregate_series(
+    series: &[TS],
+    aggregation: Aggregation,
+    begin_time: u64,
+    end_time: u64,
+    step: u64,
+) -> Result<Vec<DataPoint>, Error> {
+    let tiers: Vec<_> = series
+        .iter()
+        .map(|ts| match rollup::supports(aggregation) {
+            true => ts.tier_for(begin_time, step),
+            false => None,
+        })
+        .collect();
+    if tiers.iter().all(Option::is_none) {
+        let mut datapoints = Vec::new();
+        for ts in series {
+            datapoints.extend(raw_points(ts, begin_time, end_time)?);
+        }
+        return aggregate(&datapoints, aggregation, begin_time, end_time, step);
+    }
+
+    let mut summaries: Vec<(u64, Summary)> = Vec::new();
+    for (ts, tier) in series.iter().zip(tiers) {
+        let mut split = begin_time;
+        if let Some(tier) = tier {
+            // the intervals rolled up which end within the query
+            let interval = tier.interval();
+            let whole = end_time.saturating_add(1) / interval * interval;
+            split = tier.covered().min(whole).max(begin_time);
+            summaries.extend(tier.summaries(begin_time, split)?);
+            for hidden in tier.hidden() {
+                let begin = hidden.begin.max(begin_time);
+                let end = hidden.end.min(split);
+                if begin < end {
+                    for dp in raw_points(ts, begin, end - 1)? {
+                        summaries.push((dp.time, Summary::of_point(&dp, aggregation)?));
+                    }
+                }
+            }
+        }
+        if split <= end_time {
+            for dp in raw_points(ts, split, end_time)? {
+                summaries.push((dp.time, Summary::of_point(&dp, aggregation)?));
+            }
+        }
+    }
+
+    // blocks may hold out of order points, last follows time
+    summaries.sort_by_key(|(time, _)| *time);
+    let mut buckets: BTreeMap<u64, Summary> = BTreeMap::new();
+    for (time, summary) in summaries {
+        let bucket = match step {
+            0 => begin_time,
+            _ => begin_time + (time - begin_time) / step * step,
+        };
+        match buckets.entry(bucket) {
+            Entry::Vacant(entry) => {
+                entry.insert(summary);
+            }
+            Entry::Occupied(mut entry) => entry.get_mut().merge(&summary),
+        }
+    }
+    buckets
+        .into_iter()
+        .map(|(time, summary)| {
+            Ok(DataPoint {
+                time,
+                value: summary.finish(aggregation)?,
+            })
+        })
+        .collect()
+}
+
+/// the raw points of `ts` in [begin_time, end_time]
+fn raw_points(ts: &TS, begin_time: u64, end_time: u64) -> Result<Vec<DataPoint>, Error> {
+    ts.get_decoder(begin_time, end_time, 0, |decoder, dp_vec| {
+        for dp in decoder.points().range(begin_time, end_time) {
+            dp_vec.push(dp?);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{aggregate, aggregate_series, Aggregation};
+    use crate::rollup::{RollupRule, Tier};
+    use crate::ts::TS;
+    use crate::Error;
+    use common::TimePrecision;
+    use tszv1::format::CodecId;
+    use tszv1::{DDSketch, DataPoint, Value};
+
+    #[test]
+    fn integer_aggregates() {
+        let big = 9_007_199_254_740_993;
+        let datapoints = vec![
+            DataPoint::integer(10, big),
+            DataPoint::integer(5, 1),
+            DataPoint::integer(15, 2),
+            DataPoint::integer(25, 4),
+            DataPoint::integer(100, 1000),
+        ];
+
+        let sum = aggregate(&datapoints, Aggregation::Sum, 0, 99, 0).unwrap();
+        assert_eq!(sum, vec![DataPoint::integer(0, big + 7)]);
+
+        let last = aggregate(&datapoints, Aggregation::Last, 0, 99, 10).unwrap();
+        assert_eq!(
+            last,
+            vec![
+                DataPoint::integer(0, 1),
+                DataPoint::integer(10, 2),
+                DataPoint::integer(20, 4),
+            ]
+        );
+
+        let max = aggregate(&datapoints, Aggregation::Max, 0, 99, 0).unwrap();
+        assert_eq!(max[0].value, Value::Integer(big));
+
+        let avg = aggregate(&datapoints, Aggregation::Avg, 20, 30, 0).unwrap();
+        assert_eq!(avg, vec![DataPoint::new(20, 4.0)]);
+
+        let count = aggregate(&datapoints, Aggregation::Count, 0, 1000, 0).unwrap();
+        assert_eq!(count[0].value, Value::Integer(5));
+    }
+
+    #[test]
+    fn integer_sum_overflow() {
+        let datapoints = vec![DataPoint::integer(1, i64::MAX), DataPoint::integer(2, 1)];
+        match aggregate(&datapoints, Aggregation::Sum, 0, 10, 0) {
+            Err(Error::InvalidQuery(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn float_aggregates() {
+        let datapoints = vec![
+            DataPoint::new(1, 1.5),
+            DataPoint::new(2, f64::NAN),
+            DataPoint::new(3, -2.5),
+        ];
+        let min = aggregate(&datapoints, Aggregation::Min, 0, 10, 0).unwrap();
+        assert_eq!(min[0].value, Value::Float(-2.5));
+        let first = aggregate(&datapoints, Aggregation::First, 0, 10, 0).unwrap();
+        assert_eq!(first[0].value, Value::Float(1.5));
+    }
+
+    #[test]
+    fn non_numeric_aggregates() {
+        let datapoints = vec![
+            DataPoint::with_value(1, "up"),
+            DataPoint::with_value(2, "down"),
+            DataPoint::with_value(12, "up"),
+        ];
+        let last = aggregate(&datapoints, Aggregation::Last, 0, 20, 10).unwrap();
+        assert_eq!(
+            last,
+            vec![
+                DataPoint::with_value(0, "down"),
+                DataPoint::with_value(10, "up")
+            ]
+        );
+        let count = aggregate(&datapoints, Aggregation::Count, 0, 20, 0).unwrap();
+        assert_eq!(count[0].value, Value::Integer(3));
+
+        let flags = vec![DataPoint::with_value(1, true)];
+        for aggregation in [Aggregation::Sum, Aggregation::Avg, Aggregation::Max].iter() {
+            match aggregate(&flags, *aggregation, 0, 20, 0) {
+                Err(Error::InvalidQuery(_)) => {}
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn sketch_aggregates() {
+        let sketch = |values: &[f64]| Value::from(DDSketch::from_values(values).unwrap());
+        let datapoints = vec![
+            // two series of the same table, queried together
+            DataPoint::with_value(1, sketch(&[1.0, 2.0, 3.0])),
+            DataPoint::with_value(2, sketch(&[4.0, 5.0])),
+            DataPoint::with_value(1, sketch(&[100.0])),
+            DataPoint::with_value(11, sketch(&[7.0])),
+        ];
+
+        let merged = aggregate(&datapoints, Aggregation::Merge, 0, 20, 0).unwrap();
+        match &merged[0].value {
+            Value::Sketch(s) => {
+                assert_eq!(s.count, 7);
+                assert_eq!(s.max, 100.0);
+            }
+            other => panic!("unexpected value {:?}", other),
+        }
+
+        let p = Aggregation::from_name("p100").unwrap();
+        let max = aggregate(&datapoints, p, 0, 20, 10).unwrap();
+        assert_eq!(max, vec![DataPoint::new(0, 100.0), DataPoint::new(10, 7.0)]);
+
+        let median = aggregate(&datapoints, Aggregation::Quantile(0.5), 0, 9, 0).unwrap();
+        assert!((median[0].value.as_f64() - 3.0).abs() <= 0.03);
+
+        match aggregate(&datapoints, Aggregation::Sum, 0, 20, 0) {
+            Err(Error::InvalidQuery(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn quantile_of_numbers() {
+        let datapoints: Vec<DataPoint> =
+            (1..=100).map(|i| DataPoint::integer(i, i as i64)).collect();
+        let p90 = aggregate(
+            &datapoints,
+            Aggregation::from_name("p90").unwrap(),
+            0,
+            100,
+            0,
+        )
+        .unwrap();
+        assert!((p90[0].value.as_f64() - 90.0).abs() <= 0.9);
+
+        assert_eq!(
+            Aggregation::from_name("p50"),
+            Some(Aggregation::Quantile(0.5))
+        );
+        assert!(Aggregation::from_name("p99.9").is_some());
+        assert_eq!(Aggregation::from_name("p101"), None);
+        assert_eq!(Aggregation::from_name("pX"), None);
+
+        let states = vec![DataPoint::with_value(1, "up")];
+        match aggregate(&states, Aggregation::Merge, 0, 10, 0) {
+            Err(Error::InvalidQuery(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rollup_tiers() {
+        let precision = TimePrecision::Seconds;
+        let mut ts = TS::with_codec(precision, CodecId::Integer);
+        ts.set_tiers(vec![
+            Tier::new(RollupRule::new(60, None), precision, CodecId::Integer, None),
+            Tier::new(
+                RollupRule::new(600, None),
+                precision,
+                CodecId::Integer,
+                None,
+            ),
+        ]);
+        let raw: Vec<DataPoint> = (0..10_000)
+            .map(|time| DataPoint::integer(time, (time % 7) as i64 - 3))
+            .collect();
+        // the first block is closed and rolled up, the second one is still active
+        for dp in &raw[..7200] {
+            ts.append(dp.clone());
+        }
+        ts.close_all();
+        for dp in &raw[7200..] {
+            ts.append(dp.clone());
+        }
+
+        let series = [ts.clone()];
+        let queries = [
+            (Aggregation::Sum, 0, 9999, 600),
+            (Aggregation::Avg, 0, 9999, 3600),
+            (Aggregation::Last, 600, 8999, 1200),
+            (Aggregation::Min, 0, 9999, 0),
+            (Aggregation::Count, 120, 5000, 60),
+            (Aggregation::Max, 30, 9999, 600),
+            (Aggregation::First, 0, 9999, 600),
+        ];
+        let check = |raw: &[DataPoint]| {
+            for (aggregation, begin, end, step) in queries.iter() {
+                assert_eq!(
+                    aggregate_series(&series, *aggregation, *begin, *end, *step).unwrap(),
+                    aggregate(raw, *aggregation, *begin, *end, *step).unwrap(),
+                    "{:?} of [{}, {}] by {}",
+                    aggregation,
+                    begin,
+                    end,
+                    step
+                );
+            }
+        };
+        check(&raw);
+
+        // the intervals a delete touches are read from the raw points, the deleted points are
+        // not counted even where only part of an interval is deleted
+        ts.delete_range(100, 130, None).unwrap();
+        ts.delete_range(590, 650, None).unwrap();
+        let kept: Vec<DataPoint> = raw
+            .iter()
+            .filter(|dp| !(100..130).contains(&dp.time) && !(590..650).contains(&dp.time))
+            .cloned()
+            .collect();
+        check(&kept);
+
+        // the tiers answer alone once the raw blocks are dropped
+        let total = |step| {
+            aggregate_series(&series, Aggregation::Count, 0, 9999, step)
+                .unwrap()
+                .iter()
+                .map(|dp| dp.value.as_i64())
+                .sum::<i64>()
+        };
+        ts.drop_before(7200).unwrap();
+        // the hidden intervals [0, 1200) have no raw points left
+        assert_eq!(total(600), 8800);
+        // a step no tier fits reads the raw points
+        assert_eq!(total(90), 2800);
+
+        // a delete hides the intervals it covers
+        ts.delete_range(1200, 1800, None).unwrap();
+        assert_eq!(total(0), 8200);
+    }
+}
@@ -0,0 +1,595 @@
+use crate::block::ClosedBlock;
+use crate::cache::BlockCache;
+use crate::store::{BlockStore, SeriesKey};
+use crate::ts::{Tombstone, BLOCK_PERIOD_SECS, TS};
+use crate::{Aggregation, Error};
+use common::TimePrecision;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tszv1::format::CodecId;
+use tszv1::{DataPoint, Decode, Value};
+
+/// TIER_BLOCK_INTERVALS is the count of rollup intervals a block of a tier spans, a day of 5
+/// minute rollups
+const TIER_BLOCK_INTERVALS: u64 = 288;
+
+/// TIER_KEY_SEP separates the parts of the key a tier series is persisted under, the keys of
+/// series must not contain it
+pub const TIER_KEY_SEP: char = '\u{1f}';
+
+/// RollupRule
+///
+/// A rollup tier of a table: when a block of a series is closed its points are summarised per
+/// `interval` into the min, max, sum, count and last value of each interval. The summaries are
+/// kept for `retention`, independently of the raw blocks, `None` keeps them forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollupRule {
+    /// seconds, a divisor of the 2 hour block period so no interval spans two blocks
+    pub interval: u64,
+    pub retention: Option<Duration>,
+}
+
+impl RollupRule {
+    pub fn new(interval: u64, retention: Option<Duration>) -> Self {
+        RollupRule {
+            interval,
+            retention,
+        }
+    }
+
+    /// whether every block period is made of whole intervals
+    pub fn fits_blocks(&self) -> bool {
+        self.interval > 0 && BLOCK_PERIOD_SECS.is_multiple_of(self.interval)
+    }
+}
+
+/// Rollup
+///
+/// A summary kept by every tier, each in a series of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollup {
+    Min,
+    Max,
+    Sum,
+    Count,
+    Last,
+}
+
+impl Rollup {
+    pub const ALL: [Rollup; 5] = [
+        Rollup::Min,
+        Rollup::Max,
+        Rollup::Sum,
+        Rollup::Count,
+        Rollup::Last,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rollup::Min => "min",
+            Rollup::Max => "max",
+            Rollup::Sum => "sum",
+            Rollup::Count => "count",
+            Rollup::Last => "last",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rollup> {
+        Rollup::ALL.iter().copied().find(|r| r.name() == name)
+    }
+
+    /// the codec of the series of the rollup, counts are integers whatever the values are
+    fn codec(self, codec: CodecId) -> CodecId {
+        match self {
+            Rollup::Count => CodecId::Integer,
+            _ => codec,
+        }
+    }
+
+    fn value(self, summary: &Summary) -> Value {
+        match self {
+            Rollup::Min => summary.min.clone(),
+            Rollup::Max => summary.max.clone(),
+            Rollup::Sum => summary.sum_value(),
+            Rollup::Count => Value::Integer(summary.count as i64),
+            Rollup::Last => summary.last.clone(),
+        }
+    }
+}
+
+/// the key the series of `rollup` of the tier `rule` of the series `key` is persisted under
+pub(crate) fn tier_key(key: &str, rule: &RollupRule, rollup: Rollup) -> String {
+    format!(
+        "{}{sep}rollup{sep}{}{sep}{}{sep}{}",
+        key,
+        rule.interval,
+        rule.retention.map_or(0, |r| r.as_secs()),
+        rollup.name(),
+        sep = TIER_KEY_SEP
+    )
+}
+
+/// the series key, tier and rollup of a key made by `tier_key`, `None` for the key of a series
+pub(crate) fn parse_tier_key(key: &str) -> Option<(String, RollupRule, Rollup)> {
+    let mut parts = key.rsplitn(5, TIER_KEY_SEP);
+    let rollup = Rollup::from_name(parts.next()?)?;
+    let retention = match parts.next()?.parse().ok()? {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let interval = parts.next()?.parse().ok()?;
+    if parts.next()? != "rollup" {
+        return None;
+    }
+    let series = parts.next()?.to_string();
+    Some((series, RollupRule::new(interval, retention), rollup))
+}
+
+/// Summary
+///
+/// The numeric points of an interval summed up, summaries of consecutive intervals merge into
+/// the summary of both.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Summary {
+    count: u64,
+    /// exact while every value is an integer
+    integer_sum: Option<i128>,
+    sum: f64,
+    min: Value,
+    max: Value,
+    last: Value,
+}
+
+impl Summary {
+    fn of(value: &Value) -> Self {
+        Summary {
+            count: 1,
+            integer_sum: match value {
+                Value::Integer(v) => Some(i128::from(*v)),
+                _ => None,
+            },
+            sum: value.as_f64(),
+            min: value.clone(),
+            max: value.clone(),
+            last: value.clone(),
+        }
+    }
+
+    /// the summary of a raw point, only numeric series are summarised
+    pub(crate) fn of_point(dp: &DataPoint, aggregation: Aggregation) -> Result<Self, Error> {
+        if !dp.value.value_type().is_numeric() {
+            return Err(Error::InvalidQuery(format!(
+                "function {} over rollups is only supported by numeric series, got {} values",
+                aggregation.name(),
+                dp.value.value_type().name()
+            )));
+        }
+        Ok(Summary::of(&dp.value))
+    }
+
+    /// add the summary of the points which follow the points of `self`
+    pub(crate) fn merge(&mut self, next: &Summary) {
+        self.count += next.count;
+        self.integer_sum = match (self.integer_sum, next.integer_sum) {
+            (Some(a), Some(b)) => Some(a + b),
+            _ => None,
+        };
+        self.sum += next.sum;
+        self.min = self.min.min(&next.min);
+        self.max = self.max.max(&next.max);
+        self.last = next.last.clone();
+    }
+
+    fn sum_value(&self) -> Value {
+        match self.integer_sum {
+            // an interval holding more than i64 takes the nearest integer, queries of such sums
+            // fail on the raw points anyway
+            Some(sum) => Value::Integer(i64::try_from(sum).unwrap_or(if sum < 0 {
+                i64::MIN
+            } else {
+                i64::MAX
+            })),
+            None => Value::Float(self.sum),
+        }
+    }
+
+    /// the value of `aggregation` for the points summed up, as computed from the points
+    pub(crate) fn finish(&self, aggregation: Aggregation) -> Result<Value, Error> {
+        match aggregation {
+            Aggregation::Count => Ok(Value::Integer(self.count as i64)),
+            Aggregation::Min => Ok(self.min.clone()),
+            Aggregation::Max => Ok(self.max.clone()),
+            Aggregation::Last => Ok(self.last.clone()),
+            Aggregation::Sum => match self.integer_sum {
+                Some(sum) => i64::try_from(sum).map(Value::Integer).map_err(|_| {
+                    Error::InvalidQuery(format!("sum {} overflows a 64 bit integer", sum))
+                }),
+                None => Ok(Value::Float(self.sum)),
+            },
+            Aggregation::Avg => {
+                let sum = match self.integer_sum {
+                    Some(sum) => sum as f64,
+                    None => self.sum,
+                };
+                Ok(Value::Float(sum / self.count as f64))
+            }
+            other => Err(Error::InvalidQuery(format!(
+                "function {} can not be computed from rollups",
+                other.name()
+            ))),
+        }
+    }
+
+    /// the summary kept by the series of a tier for one interval, `values` in `Rollup::ALL`
+    /// order
+    fn from_rollups(values: &[Value]) -> Option<Self> {
+        let count = values.get(3)?.as_i64().max(0) as u64;
+        let sum = values.get(2)?;
+        Some(Summary {
+            count,
+            integer_sum: match sum {
+                Value::Integer(v) => Some(i128::from(*v)),
+                _ => None,
+            },
+            sum: sum.as_f64(),
+            min: values.first()?.clone(),
+            max: values.get(1)?.clone(),
+            last: values.get(4)?.clone(),
+        })
+    }
+}
+
+/// whether the rollups can answer `aggregation`
+pub(crate) fn supports(aggregation: Aggregation) -> bool {
+    matches!(
+        aggregation,
+        Aggregation::Count
+            | Aggregation::Sum
+            | Aggregation::Min
+            | Aggregation::Max
+            | Aggregation::Avg
+            | Aggregation::Last
+    )
+}
+
+/// Tier
+///
+/// The rollups of a series for one rule, a series per `Rollup` whose points are stamped with
+/// the start of their interval. The tier knows up to which time the closed blocks of its
+/// series were rolled up; later points are only in the raw blocks.
+#[derive(Clone)]
+pub(crate) struct Tier {
+    rule: RollupRule,
+    interval: u64, // in the precision of the series
+    series: Vec<TS>,
+    covered: Arc<AtomicU64>,
+}
+
+impl Tier {
+    /// an empty tier of a series of `precision` and `codec`, whose blocks are accounted in the
+    /// `cache` of the series
+    pub(crate) fn new(
+        rule: RollupRule,
+        precision: TimePrecision,
+        codec: CodecId,
+        cache: Option<&Arc<BlockCache>>,
+    ) -> Self {
+        let series = Rollup::ALL
+            .iter()
+            .map(|r| {
+                let period = Tier::period(&rule, precision);
+                let mut ts = TS::with_period(precision, r.codec(codec), period);
+                if let Some(cache) = cache {
+                    ts.set_cache(cache);
+                }
+                ts
+            })
+            .collect();
+        Tier::with_series(rule, precision, series)
+    }
+
+    /// a tier of the series read back from the files, one per `Rollup` in its order
+    pub(crate) fn with_series(rule: RollupRule, precision: TimePrecision, series: Vec<TS>) -> Self {
+        Tier {
+            rule,
+            interval: precision.from_secs(rule.interval),
+            series,
+            covered: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// the block period of the series of a tier
+    pub(crate) fn period(rule: &RollupRule, precision: TimePrecision) -> u64 {
+        precision.from_secs(rule.interval * TIER_BLOCK_INTERVALS)
+    }
+
+    /// in the precision of the series
+    pub(crate) fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// the time before which every closed block of the series is rolled up
+    pub(crate) fn covered(&self) -> u64 {
+        self.covered.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn cover(&self, time: u64) {
+        self.covered.fetch_max(time, Ordering::AcqRel);
+    }
+
+    /// summarise the points of `block` not deleted by `tombstones` into the tier
+    pub(crate) fn roll_up(
+        &self,
+        block: &ClosedBlock,
+        tombstones: &[Tombstone],
+    ) -> Result<(), Error> {
+        let mut summaries: Vec<(u64, Summary)> = Vec::new();
+        let mut points = Vec::new();
+        for dp in block.get_decoder()? {
+            let dp = dp?;
+            if dp.value.value_type().is_numeric() && !tombstones.iter().any(|t| t.covers(dp.time)) {
+                points.push(dp);
+            }
+        }
+        // blocks may hold out of order points, last follows time
+        points.sort_by_key(|dp| dp.time);
+        for dp in points {
+            let bucket = dp.time - dp.time % self.interval;
+            match summaries.last_mut() {
+                Some((time, summary)) if *time == bucket => summary.merge(&Summary::of(&dp.value)),
+                _ => summaries.push((bucket, Summary::of(&dp.value))),
+            }
+        }
+
+        // a block closed after later ones, of late points, is rolled up into blocks of its own
+        // rather than skipped, the tier covers it as well
+        for (rollup, ts) in Rollup::ALL.iter().zip(self.series.iter()) {
+            ts.append_or_close(
+                summaries
+                    .iter()
+                    .map(|(time, summary)| DataPoint {
+                        time: *time,
+                        value: rollup.value(summary),
+                    })
+                    .collect(),
+            );
+        }
+        self.cover(block.time_end());
+        Ok(())
+    }
+
+    /// the summaries of the intervals starting in [begin_time, end_time), in time order
+    pub(crate) fn summaries(
+        &self,
+        begin_time: u64,
+        end_time: u64,
+    ) -> Result<Vec<(u64, Summary)>, Error> {
+        if begin_time >= end_time {
+            return Ok(Vec::new());
+        }
+        let mut columns = Vec::with_capacity(self.series.len());
+        for ts in &self.series {
+            let mut points = ts.get_decoder(begin_time, end_time - 1, 0, |decoder, dp_vec| {
+                for dp in decoder.points().range(begin_time, end_time - 1) {
+                    dp_vec.push(dp?);
+                }
+                Ok(())
+            })?;
+            points.sort_by_key(|dp| dp.time);
+            columns.push(points);
+        }
+
+        // the rollups of an interval are appended together, an interval missing from one of
+        // them was cut by a retention or delete in between and is skipped. An interval rolled up
+        // from several blocks, one of them of late points, has a summary per block in the same
+        // order in every rollup, they are merged.
+        let mut summaries: Vec<(u64, Summary)> = Vec::new();
+        let mut i = 0;
+        while i < columns[0].len() {
+            let time = columns[0][i].time;
+            let rows: Vec<&[DataPoint]> = columns
+                .iter()
+                .map(|points| {
+                    let from = points.partition_point(|p| p.time < time);
+                    let to = points.partition_point(|p| p.time <= time);
+                    &points[from..to]
+                })
+                .collect();
+            i += rows[0].len();
+            if rows.iter().any(|row| row.len() != rows[0].len()) {
+                continue;
+            }
+            let mut merged: Option<Summary> = None;
+            for k in 0..rows[0].len() {
+                let values: Vec<Value> = rows.iter().map(|row| row[k].value.clone()).collect();
+                if let Some(summary) = Summary::from_rollups(&values) {
+                    match merged.as_mut() {
+                        Some(merged) => merged.merge(&summary),
+                        None => merged = Some(summary),
+                    }
+                }
+            }
+            if let Some(summary) = merged {
+                summaries.push((time, summary));
+            }
+        }
+        Ok(summaries)
+    }
+
+    /// the time ranges hidden by `delete_range`, whole intervals, ordered and disjoint
+    pub(crate) fn hidden(&self) -> Vec<Tombstone> {
+        self.series[0].tombstones()
+    }
+
+    pub(crate) fn roll_down(&self, timeout: u64) -> usize {
+        self.series.iter().map(|ts| ts.roll_down(timeout)).sum()
+    }
+
+    pub(crate) fn close_all(&self) -> usize {
+        self.series.iter().map(|ts| ts.close_all()).sum()
+    }
+
+    /// the series `rollup` of the tier of the series `key` is persisted as
+    fn series_key(&self, key: &SeriesKey, rollup: Rollup) -> SeriesKey {
+        SeriesKey {
+            table: key.table.clone(),
+            key: tier_key(&key.key, &self.rule, rollup),
+        }
+    }
+
+    /// write the closed blocks of the tier of the series `key`
+    pub(crate) fn persist(&self, store: &BlockStore, key: &SeriesKey) -> Result<usize, Error> {
+        let mut written = 0;
+        for (rollup, ts) in Rollup::ALL.iter().zip(self.series.iter()) {
+            written += ts.persist(store, &self.series_key(key, *rollup))?;
+        }
+        Ok(written)
+    }
+
+    /// drop the blocks past the retention of the tier, returns the count of blocks dropped
+    pub(crate) fn expire(&self) -> Result<usize, Error> {
+        let retention = match self.rule.retention {
+            Some(retention) => retention,
+            None => return Ok(0),
+        };
+        let mut dropped = 0;
+        for ts in &self.series {
+            let precision = ts.precision();
+            let cutoff = precision
+                .now()
+                .saturating_sub(precision.from_secs(retention.as_secs()));
+            dropped += ts.drop_before(cutoff)?;
+        }
+        Ok(dropped)
+    }
+
+    /// hide every interval overlapping [begin, end), the summary of an interval partly deleted
+    /// still counts its deleted points, so queries read the raw points of the hidden intervals
+    pub(crate) fn delete_range(
+        &self,
+        begin: u64,
+        end: u64,
+        store: Option<(&BlockStore, &SeriesKey)>,
+    ) -> Result<(), Error> {
+        let begin = begin - begin % self.interval;
+        let end = match end % self.interval {
+            0 => end,
+            rem => end.saturating_add(self.interval - rem),
+        };
+        for (rollup, ts) in Rollup::ALL.iter().zip(self.series.iter()) {
+            let key = store.map(|(store, key)| (store, self.series_key(key, *rollup)));
+            ts.delete_range(begin, end, key.as_ref().map(|(s, k)| (*s, k)))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete(&self, store: Option<(&BlockStore, &SeriesKey)>) -> Result<(), Error> {
+        for (rollup, ts) in Rollup::ALL.iter().zip(self.series.iter()) {
+            let key = store.map(|(store, key)| (store, self.series_key(key, *rollup)));
+            ts.delete(key.as_ref().map(|(s, k)| (*s, k)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{AppendOnlyBlock, ClosedBlock};
+    use crate::rollup::{parse_tier_key, tier_key, Rollup, RollupRule, Summary, Tier};
+    use crate::Aggregation;
+    use common::TimePrecision;
+    use std::time::Duration;
+    use tszv1::format::CodecId;
+    use tszv1::{DataPoint, Encode, Value};
+
+    #[test]
+    fn tier_keys() {
+        let rule = RollupRule::new(300, Some(Duration::from_secs(86400)));
+        let key = tier_key("cpu/a", &rule, Rollup::Count);
+        assert_eq!(
+            parse_tier_key(&key),
+            Some(("cpu/a".to_string(), rule, Rollup::Count))
+        );
+        let forever = RollupRule::new(3600, None);
+        let key = tier_key("cpu/a", &forever, Rollup::Last);
+        assert_eq!(parse_tier_key(&key).unwrap().1, forever);
+        assert_eq!(parse_tier_key("cpu/a"), None);
+
+        assert!(rule.fits_blocks());
+        assert!(!RollupRule::new(7, None).fits_blocks());
+        assert!(!RollupRule::new(0, None).fits_blocks());
+    }
+
+    #[test]
+    fn summaries() {
+        let points = [
+            DataPoint::integer(1, 4),
+            DataPoint::integer(2, -1),
+            DataPoint::integer(3, 7),
+        ];
+        let summary = |points: &[DataPoint]| {
+            let mut summaries = points
+                .iter()
+                .map(|dp| Summary::of_point(dp, Aggregation::Sum).unwrap());
+            let mut summary = summaries.next().unwrap();
+            summaries.for_each(|s| summary.merge(&s));
+            summary
+        };
+
+        // merging the summaries of parts is the summary of the whole
+        let mut merged = summary(&points[..1]);
+        merged.merge(&summary(&points[1..]));
+        assert_eq!(merged, summary(&points));
+        assert_eq!(merged.finish(Aggregation::Sum), Ok(Value::Integer(10)));
+        assert_eq!(merged.finish(Aggregation::Min), Ok(Value::Integer(-1)));
+        assert_eq!(merged.finish(Aggregation::Last), Ok(Value::Integer(7)));
+        assert_eq!(merged.finish(Aggregation::Count), Ok(Value::Integer(3)));
+        assert!(merged.finish(Aggregation::First).is_err());
+
+        // and the summary kept by a tier
+        let values: Vec<Value> = Rollup::ALL.iter().map(|r| r.value(&merged)).collect();
+        assert_eq!(Summary::from_rollups(&values), Some(merged));
+
+        let float = summary(&[DataPoint::new(1, 1.5), DataPoint::new(2, 2.5)]);
+        assert_eq!(float.finish(Aggregation::Avg), Ok(Value::Float(2.0)));
+        assert!(Summary::of_point(&DataPoint::with_value(1, "up"), Aggregation::Max).is_err());
+    }
+
+    #[test]
+    fn late_blocks() {
+        let precision = TimePrecision::Seconds;
+        let tier = Tier::new(RollupRule::new(60, None), precision, CodecId::Integer, None);
+        let block = |begin: u64, times: &[u64]| {
+            let mut aob = AppendOnlyBlock::new(begin, begin + 7200, precision, CodecId::Integer);
+            for time in times {
+                aob.encoder
+                    .encode(DataPoint::integer(*time, (*time % 7) as i64 - 3));
+            }
+            ClosedBlock::new(&aob)
+        };
+
+        // a block, a block of an earlier tier block and one of late points of the same
+        // intervals as the first
+        let even: Vec<u64> = (21600..28800).step_by(2).collect();
+        let early: Vec<u64> = (0..7200).collect();
+        let odd: Vec<u64> = (21600..22200).skip(1).step_by(2).collect();
+        tier.roll_up(&block(21600, &even), &[]).unwrap();
+        tier.roll_up(&block(0, &early), &[]).unwrap();
+        tier.roll_up(&block(21600, &odd), &[]).unwrap();
+        assert_eq!(tier.covered(), 28800);
+
+        let mut times: Vec<u64> = even.into_iter().chain(early).chain(odd).collect();
+        times.sort_unstable();
+        let mut expected: Vec<(u64, Summary)> = Vec::new();
+        for time in times {
+            let summary = Summary::of(&Value::Integer((time % 7) as i64 - 3));
+            match expected.last_mut() {
+                Some((bucket, merged)) if *bucket == time - time % 60 => merged.merge(&summary),
+                _ => expected.push((time - time % 60, summary)),
+            }
+        }
+        assert_eq!(tier.summaries(0, 28800).unwrap(), expected);
+    }
+}
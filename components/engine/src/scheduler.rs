@@ -0,0 +1,303 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tszv1::rewrite::Duplicates;
+
+/// JobOptions
+///
+/// When a maintenance job runs: every `interval` plus a random delay of up to `jitter`, which
+/// keeps the jobs of several engines from running in step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JobOptions {
+    pub interval: Duration,
+    pub jitter: Duration,
+}
+
+impl JobOptions {
+    pub fn every(interval: Duration) -> Self {
+        JobOptions {
+            interval,
+            jitter: interval / 10,
+        }
+    }
+}
+
+/// MaintenanceOptions
+///
+/// The settings of the maintenance jobs of an engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceOptions {
+    /// roll down the active blocks whose period ended `roll_down_grace` ago
+    pub roll_down: JobOptions,
+    pub roll_down_grace: Duration,
+    /// write the closed blocks to the data directory, if the engine has one
+    pub persistence: JobOptions,
+    /// drop the closed blocks which ended `retention_period` ago, `None` keeps them forever
+    pub retention: JobOptions,
+    pub retention_period: Option<Duration>,
+    /// merge the closed blocks of each past `compaction_window` of a series into one block,
+    /// applying the tombstones and resolving the points of the same time by `duplicates`
+    pub compaction: JobOptions,
+    pub compaction_window: Duration,
+    pub duplicates: Duplicates,
+    /// log the state of the engine
+    pub stats: JobOptions,
+}
+
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        MaintenanceOptions {
+            roll_down: JobOptions::every(Duration::from_secs(60)),
+            roll_down_grace: Duration::from_secs(100),
+            persistence: JobOptions::every(Duration::from_secs(60)),
+            retention: JobOptions::every(Duration::from_secs(60 * 60)),
+            retention_period: None,
+            compaction: JobOptions::every(Duration::from_secs(60 * 60)),
+            compaction_window: Duration::from_secs(24 * 60 * 60),
+            duplicates: Duplicates::KeepLast,
+            stats: JobOptions::every(Duration::from_secs(5 * 60)),
+        }
+    }
+}
+
+/// JobStats
+///
+/// The runs of a maintenance job since the engine started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStats {
+    pub name: String,
+    pub runs: u64,
+    /// runs skipped because the previous run was not done
+    pub skipped: u64,
+    pub last_duration: Duration,
+    pub max_duration: Duration,
+    pub total_duration: Duration,
+}
+
+struct Job {
+    name: &'static str,
+    options: JobOptions,
+    run: Box<dyn Fn() + Send + Sync>,
+    running: AtomicBool,
+    stats: Mutex<JobStats>,
+}
+
+impl Job {
+    /// the delay before the next run
+    fn delay(&self, random: &RandomState) -> Duration {
+        let jitter = self.options.jitter.as_nanos() as u64;
+        let mut hasher = random.build_hasher();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        hasher.write_u64(now.map_or(0, |d| d.as_nanos() as u64));
+        let extra = hasher.finish().checked_rem(jitter).unwrap_or(0);
+        self.options.interval + Duration::from_nanos(extra)
+    }
+
+    /// run on a thread of its own unless the previous run is still going
+    fn start(self: &Arc<Self>, idle: &Arc<(Mutex<usize>, Condvar)>) {
+        if self.running.swap(true, Ordering::AcqRel) {
+            self.stats.lock().unwrap().skipped += 1;
+            warn!("maintenance job {} is still running, skipped", self.name);
+            return;
+        }
+
+        *idle.0.lock().unwrap() += 1;
+        let job = self.clone();
+        let done = idle.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("job-{}", self.name))
+            .spawn(move || {
+                let begin = Instant::now();
+                // a panic fails the run, the job keeps its schedule
+                if panic::catch_unwind(AssertUnwindSafe(|| (job.run)())).is_err() {
+                    error!("maintenance job {} panicked", job.name);
+                }
+                let elapsed = begin.elapsed();
+                {
+                    let mut stats = job.stats.lock().unwrap();
+                    stats.runs += 1;
+                    stats.last_duration = elapsed;
+                    stats.max_duration = stats.max_duration.max(elapsed);
+                    stats.total_duration += elapsed;
+                }
+                job.running.store(false, Ordering::Release);
+                *done.0.lock().unwrap() -= 1;
+                done.1.notify_all();
+            });
+        if let Err(err) = spawned {
+            error!("failed to start maintenance job {}: {}", self.name, err);
+            self.running.store(false, Ordering::Release);
+            *idle.0.lock().unwrap() -= 1;
+        }
+    }
+}
+
+/// Scheduler
+///
+/// Scheduler runs named maintenance jobs periodically. One thread waits for the next job which
+/// is due and starts it on a thread of its own, so a slow job delays no other job; a job which
+/// is due while its previous run is still going is skipped. Jobs are added before `start`.
+pub(crate) struct Scheduler {
+    jobs: Vec<Arc<Job>>,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    running: Arc<(Mutex<usize>, Condvar)>, // jobs running now
+}
+
+impl Scheduler {
+    pub(crate) fn new() -> Self {
+        Scheduler {
+            jobs: Vec::new(),
+            stopped: Arc::new((Mutex::new(false), Condvar::new())),
+            running: Arc::new((Mutex::new(0), Condvar::new())),
+        }
+    }
+
+    pub(crate) fn add<F>(&mut self, name: &'static str, options: JobOptions, run: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.jobs.push(Arc::new(Job {
+            name,
+            options,
+            run: Box::new(run),
+            running: AtomicBool::new(false),
+            stats: Mutex::new(JobStats {
+                name: name.to_string(),
+                runs: 0,
+                skipped: 0,
+                last_duration: Duration::default(),
+                max_duration: Duration::default(),
+                total_duration: Duration::default(),
+            }),
+        }));
+    }
+
+    /// start the thread which runs the jobs, each job first runs one delay after now
+    pub(crate) fn start(&self) {
+        let jobs = self.jobs.clone();
+        let stopped = self.stopped.clone();
+        let running = self.running.clone();
+        std::thread::Builder::new()
+            .name("scheduler".to_string())
+            .spawn(move || {
+                let random = RandomState::new();
+                let now = Instant::now();
+                let mut due: Vec<Instant> =
+                    jobs.iter().map(|job| now + job.delay(&random)).collect();
+                let mut stop = stopped.0.lock().unwrap();
+                while !*stop {
+                    let now = Instant::now();
+                    for (job, at) in jobs.iter().zip(due.iter_mut()) {
+                        if *at <= now {
+                            job.start(&running);
+                            *at = now + job.delay(&random);
+                        }
+                    }
+                    let next = match due.iter().min() {
+                        Some(next) => next.saturating_duration_since(now),
+                        None => Duration::from_secs(60),
+                    };
+                    stop = stopped.1.wait_timeout(stop, next).unwrap().0;
+                }
+            })
+            .unwrap();
+    }
+
+    /// stop starting jobs and wait for the running ones
+    pub(crate) fn stop(&self) {
+        *self.stopped.0.lock().unwrap() = true;
+        self.stopped.1.notify_all();
+        let mut running = self.running.0.lock().unwrap();
+        while *running > 0 {
+            running = self.running.1.wait(running).unwrap();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<JobStats> {
+        self.jobs
+            .iter()
+            .map(|job| job.stats.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        *self.stopped.0.lock().unwrap() = true;
+        self.stopped.1.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scheduler::{JobOptions, Scheduler};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn runs_and_overlaps() {
+        let fast = Arc::new(AtomicU64::new(0));
+        let slow = Arc::new(AtomicU64::new(0));
+        let mut scheduler = Scheduler::new();
+        let every = |millis| JobOptions {
+            interval: Duration::from_millis(millis),
+            jitter: Duration::from_millis(1),
+        };
+        let (fast_tx, fast_rx) = mpsc::channel();
+        let count = fast.clone();
+        let fast_tx = Mutex::new(fast_tx);
+        scheduler.add("fast", every(5), move || {
+            count.fetch_add(1, Ordering::Relaxed);
+            let _ = fast_tx.lock().unwrap().send(());
+        });
+        // the first run of the slow job lasts until it is released, the next ones return at once
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let count = slow.clone();
+        let slow_job = Mutex::new((started_tx, release_rx));
+        scheduler.add("slow", every(5), move || {
+            let job = slow_job.lock().unwrap();
+            let _ = job.0.send(());
+            let _ = job.1.recv();
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+        scheduler.start();
+
+        // the slow job does not hold the fast one back, nor runs twice at once
+        started_rx.recv().unwrap();
+        let blocked = Instant::now();
+        for _ in 0..3 {
+            fast_rx.recv().unwrap();
+        }
+        while scheduler.stats()[1].skipped == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(slow.load(Ordering::Relaxed), 0);
+        std::thread::sleep(Duration::from_millis(10));
+        let elapsed = blocked.elapsed();
+        drop(release_tx);
+        scheduler.stop();
+
+        let stats = scheduler.stats();
+        assert_eq!(stats[0].name, "fast");
+        assert!(stats[0].runs >= 3, "{:?}", stats[0]);
+        assert!(
+            stats[1].runs >= 1 && stats[1].skipped >= 1,
+            "{:?}",
+            stats[1]
+        );
+        assert!(stats[1].max_duration >= elapsed, "{:?}", stats[1]);
+
+        // stop waited for the running jobs, nothing runs after it
+        assert_eq!(fast.load(Ordering::Relaxed), stats[0].runs);
+        assert_eq!(slow.load(Ordering::Relaxed), stats[1].runs);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(fast.load(Ordering::Relaxed), stats[0].runs);
+        assert_eq!(slow.load(Ordering::Relaxed), stats[1].runs);
+    }
+}
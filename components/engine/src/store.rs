@@ -0,0 +1,588 @@
+use crate::block::{BlockBytes, ClosedBlock};
+use crate::rollup::RollupRule;
+use crate::table::{TableOptions, ValueAction};
+use crate::ts::Tombstone;
+use crate::Error;
+use common::TimePrecision;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tszv1::format::CodecId;
+use tszv1::stream::MappedFile;
+
+/// BLOCK_FILE_EXT is the extension of block files, other files of the data directory are ignored
+pub const BLOCK_FILE_EXT: &str = "blk";
+
+/// TOMBSTONE_FILE is the name of the file of the deleted time ranges of a series
+pub const TOMBSTONE_FILE: &str = "tombstones";
+
+/// TABLE_FILE is the name of the file of the options of a table
+pub const TABLE_FILE: &str = "table";
+
+/// SeriesKey
+///
+/// A series is named by its table and its key, the same key in two tables makes two series.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct SeriesKey {
+    pub table: String,
+    pub key: String,
+}
+
+impl SeriesKey {
+    pub(crate) fn new(table: &str, key: &str) -> Self {
+        SeriesKey {
+            table: table.to_string(),
+            key: key.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.table, self.key)
+    }
+}
+
+/// LoadedSeries
+///
+/// The files of a series read back by `BlockStore::load`.
+#[derive(Debug)]
+pub(crate) struct LoadedSeries {
+    pub key: SeriesKey,
+    /// ordered by time
+    pub blocks: Vec<ClosedBlock>,
+    pub tombstones: Vec<Tombstone>,
+}
+
+/// BlockStore
+///
+/// BlockStore keeps the closed blocks of every series as files under a data directory: one
+/// directory per table and in it one directory per series, both named hex encoded, holding one
+/// file per block named by its time range, `<data dir>/<hex table>/<hex key>/<time_begin>-
+/// <time_end>.blk`. Another block of the same range, such as one of late points after a
+/// restart, gets a sequence number, `<time_begin>-<time_end>-<n>.blk`, no block file is ever
+/// overwritten. A file holds the block bytes as encoded, the header tells the codec and
+/// precision back. A block is written to a temporary file which is synced and linked to its
+/// name, so a crash leaves either the whole block or none of it. The deleted time ranges of a
+/// series are kept next to its blocks, one `<begin> <end>` line per range in the `tombstones`
+/// file, written to a temporary file and renamed over the previous one. The options of a
+/// created table are kept the same way in the `table` file of its directory, one `<name>
+/// <value>` line per option.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockStore {
+    dir: PathBuf,
+}
+
+impl BlockStore {
+    /// open the data directory `dir`, created if missing
+    pub(crate) fn open(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir).map_err(|err| storage(dir, err))?;
+        Ok(BlockStore {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn table_dir(&self, table_name: &str) -> PathBuf {
+        self.dir.join(hex_encode(table_name.as_bytes()))
+    }
+
+    fn series_dir(&self, key: &SeriesKey) -> PathBuf {
+        self.table_dir(&key.table)
+            .join(hex_encode(key.key.as_bytes()))
+    }
+
+    /// replace the options of the table `options.table_name`
+    pub(crate) fn write_table(&self, options: &TableOptions) -> Result<(), Error> {
+        let path = self.table_dir(&options.table_name).join(TABLE_FILE);
+        write_synced(&path, format_table(options).as_bytes())
+    }
+
+    /// read back the options of every created table
+    pub(crate) fn load_tables(&self) -> Result<Vec<TableOptions>, Error> {
+        let mut tables = Vec::new();
+        for (table_name, dir) in named_dirs(&self.dir)? {
+            let path = dir.join(TABLE_FILE);
+            match fs::read_to_string(&path) {
+                Ok(lines) => tables.push(parse_table(&table_name, &lines).ok_or_else(|| {
+                    Error::Storage(format!("{}: malformed table options", path.display()))
+                })?),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(storage(&path, err)),
+            }
+        }
+        Ok(tables)
+    }
+
+    /// write `block` of the series `key` to a new file and sync it, returns the path of the
+    /// block file
+    pub(crate) fn write(&self, key: &SeriesKey, block: &ClosedBlock) -> Result<PathBuf, Error> {
+        let dir = self.series_dir(key);
+        fs::create_dir_all(&dir).map_err(|err| storage(&dir, err))?;
+
+        let range = format!("{}-{}", block.time_begin(), block.time_end());
+        let tmp = dir.join(&range).with_extension("tmp");
+        let mut file = File::create(&tmp).map_err(|err| storage(&tmp, err))?;
+        file.write_all(block.bytes()?.as_ref())
+            .and_then(|_| file.sync_all())
+            .map_err(|err| storage(&tmp, err))?;
+
+        // linking fails rather than replace a block file of the same range
+        let mut seq = 0;
+        let path = loop {
+            let name = match seq {
+                0 => range.clone(),
+                seq => format!("{}-{}", range, seq),
+            };
+            let path = dir.join(name).with_extension(BLOCK_FILE_EXT);
+            match fs::hard_link(&tmp, &path) {
+                Ok(()) => break path,
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => seq += 1,
+                Err(err) => return Err(storage(&path, err)),
+            }
+        };
+        fs::remove_file(&tmp).map_err(|err| storage(&tmp, err))?;
+        sync_dir(&dir)?;
+        Ok(path)
+    }
+
+    /// replace the tombstones of the series `key`, the file is removed when there are none
+    pub(crate) fn write_tombstones(
+        &self,
+        key: &SeriesKey,
+        tombstones: &[Tombstone],
+    ) -> Result<(), Error> {
+        let path = self.series_dir(key).join(TOMBSTONE_FILE);
+        if tombstones.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(storage(&path, err)),
+                _ => Ok(()),
+            };
+        }
+        let lines: String = tombstones
+            .iter()
+            .map(|t| format!("{} {}\n", t.begin, t.end))
+            .collect();
+        write_synced(&path, lines.as_bytes())
+    }
+
+    /// remove the files of the series `key`
+    pub(crate) fn remove_series(&self, key: &SeriesKey) -> Result<(), Error> {
+        let dir = self.series_dir(key);
+        match fs::remove_dir_all(&dir) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(storage(&dir, err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// read back the blocks and tombstones of every series
+    pub(crate) fn load(&self) -> Result<Vec<LoadedSeries>, Error> {
+        let mut series = Vec::new();
+        for (table, dir) in named_dirs(&self.dir)? {
+            for (key, dir) in named_dirs(&dir)? {
+                series.push(load_series(
+                    SeriesKey {
+                        table: table.clone(),
+                        key,
+                    },
+                    &dir,
+                )?);
+            }
+        }
+        Ok(series)
+    }
+}
+
+/// the sub directories of `dir` with their hex decoded names, others are ignored
+fn named_dirs(dir: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|err| storage(dir, err))? {
+        let path = entry.map_err(|err| storage(dir, err))?.path();
+        match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(hex_decode)
+        {
+            Some(name) if path.is_dir() => dirs.push((name, path)),
+            _ => continue,
+        }
+    }
+    Ok(dirs)
+}
+
+/// read back the blocks and tombstones of the series `key` from its directory
+fn load_series(key: SeriesKey, dir: &Path) -> Result<LoadedSeries, Error> {
+    let mut blocks = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|err| storage(dir, err))? {
+        let path = entry.map_err(|err| storage(dir, err))?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(BLOCK_FILE_EXT) {
+            continue;
+        }
+        let (time_begin, time_end) = match path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(parse_range)
+        {
+            Some(range) => range,
+            None => continue,
+        };
+        // mapped, the pages are read when the block is decoded
+        let bytes = MappedFile::open(&path).map_err(|err| storage(&path, err))?;
+        blocks.push(ClosedBlock::load(
+            BlockBytes::Mapped(bytes),
+            time_begin,
+            time_end,
+            path,
+        )?);
+    }
+    blocks.sort_by_key(|block| block.time_begin());
+
+    let path = dir.join(TOMBSTONE_FILE);
+    let tombstones = match fs::read_to_string(&path) {
+        Ok(lines) => parse_tombstones(&lines)
+            .ok_or_else(|| Error::Storage(format!("{}: malformed tombstones", path.display())))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(storage(&path, err)),
+    };
+    Ok(LoadedSeries {
+        key,
+        blocks,
+        tombstones,
+    })
+}
+
+/// write `bytes` to a temporary file, sync it and rename it to `path`, then sync the directory
+/// so the rename is durable
+fn write_synced(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir).map_err(|err| storage(dir, err))?;
+
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp).map_err(|err| storage(&tmp, err))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|err| storage(&tmp, err))?;
+    fs::rename(&tmp, path).map_err(|err| storage(path, err))?;
+    sync_dir(dir)
+}
+
+/// sync `dir` so the files created, renamed or removed in it are durable
+fn sync_dir(dir: &Path) -> Result<(), Error> {
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|err| storage(dir, err))
+}
+
+fn storage(path: &Path, err: std::io::Error) -> Error {
+    Error::Storage(format!("{}: {}", path.display(), err))
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_tombstones(lines: &str) -> Option<Vec<Tombstone>> {
+    lines
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let begin = parts.next()?.parse().ok()?;
+            let end = parts.next()?.parse().ok()?;
+            Some(Tombstone { begin, end })
+        })
+        .collect()
+}
+
+/// the lines of the `table` file of `options`, a rollup kept forever has no retention
+fn format_table(options: &TableOptions) -> String {
+    let policy = &options.value_policy;
+    let mut lines = format!(
+        "precision {}\ncodec {}\nnegative {}\nnan {}\ninfinite {}\n",
+        options.precision.name(),
+        options.codec.name(),
+        policy.negative.name(),
+        policy.nan.name(),
+        policy.infinite.name()
+    );
+    for rule in &options.rollups {
+        match rule.retention {
+            Some(retention) => lines.push_str(&format!(
+                "rollup {} {}\n",
+                rule.interval,
+                retention.as_secs()
+            )),
+            None => lines.push_str(&format!("rollup {}\n", rule.interval)),
+        }
+    }
+    lines
+}
+
+/// the options written by `format_table`, the options missing from `lines` keep their defaults
+fn parse_table(table_name: &str, lines: &str) -> Option<TableOptions> {
+    let mut options = TableOptions::new(table_name);
+    for line in lines.lines().filter(|line| !line.trim().is_empty()) {
+        let mut parts = line.split_whitespace();
+        let name = parts.next()?;
+        let value = parts.next()?;
+        match name {
+            "precision" => options.precision = TimePrecision::from_name(value)?,
+            "codec" => options.codec = CodecId::from_name(value)?,
+            "negative" => options.value_policy.negative = ValueAction::from_name(value)?,
+            "nan" => options.value_policy.nan = ValueAction::from_name(value)?,
+            "infinite" => options.value_policy.infinite = ValueAction::from_name(value)?,
+            "rollup" => {
+                let retention = match parts.next() {
+                    Some(secs) => Some(Duration::from_secs(secs.parse().ok()?)),
+                    None => None,
+                };
+                options
+                    .rollups
+                    .push(RollupRule::new(value.parse().ok()?, retention));
+            }
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+/// the time range of a block file stem `<time_begin>-<time_end>`, optionally followed by the
+/// sequence number `-<n>` of a block of the same range
+fn parse_range(stem: &str) -> Option<(u64, u64)> {
+    let mut parts = stem.splitn(3, '-');
+    let begin = parts.next()?.parse().ok()?;
+    let end = parts.next()?.parse().ok()?;
+    if let Some(seq) = parts.next() {
+        seq.parse::<u64>().ok()?;
+    }
+    Some((begin, end))
+}
+
+/// a fresh directory under the system temporary directory
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("teemo-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{AppendOnlyBlock, ClosedBlock};
+    use crate::rollup::RollupRule;
+    use crate::store::{
+        hex_decode, hex_encode, parse_range, parse_table, temp_dir, BlockStore, SeriesKey,
+    };
+    use crate::table::{TableOptions, ValueAction};
+    use crate::ts::Tombstone;
+    use common::TimePrecision;
+    use std::time::Duration;
+    use tszv1::format::CodecId;
+    use tszv1::{DataPoint, Decode, Encode};
+
+    #[test]
+    fn hex_keys() {
+        let key = "cpu/host=a b";
+        assert_eq!(hex_decode(&hex_encode(key.as_bytes())).unwrap(), key);
+        assert_eq!(hex_decode("6"), None);
+        assert_eq!(hex_decode("zz"), None);
+    }
+
+    #[test]
+    fn write_and_load() {
+        let dir = temp_dir("store");
+        let store = BlockStore::open(&dir).unwrap();
+
+        let mut aob = AppendOnlyBlock::new(7200, 14400, TimePrecision::Seconds, CodecId::default());
+        for i in 0..100 {
+            aob.encoder.encode(DataPoint::new(7200 + i, i as f64));
+        }
+        let block = ClosedBlock::new(&aob);
+        let key = SeriesKey::new("t", "cpu/a");
+        let path = store.write(&key, &block).unwrap();
+        assert!(path.ends_with("74/6370752f61/7200-14400.blk"));
+        // the same key in another table is another series
+        let other = SeriesKey::new("u", "cpu/a");
+        store.write(&other, &block).unwrap();
+        // leftovers of an interrupted write are ignored
+        std::fs::write(path.with_extension("tmp"), b"partial").unwrap();
+
+        let tombstones = vec![Tombstone {
+            begin: 7250,
+            end: 7260,
+        }];
+        store.write_tombstones(&key, &tombstones).unwrap();
+        store.remove_series(&other).unwrap();
+
+        let series = store.load().unwrap();
+        assert_eq!(series.len(), 1);
+        let blocks = &series[0].blocks;
+        assert_eq!(series[0].key, key);
+        assert_eq!(series[0].tombstones, tombstones);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].path(), Some(path.as_path()));
+        assert_eq!(blocks[0].time_end(), 14400);
+        let points: Vec<DataPoint> = blocks[0]
+            .get_decoder()
+            .unwrap()
+            .into_iter()
+            .map(|dp| dp.unwrap())
+            .collect();
+        assert_eq!(points.len(), 100);
+        assert_eq!(points[99], DataPoint::new(7299, 99.0));
+
+        store.write_tombstones(&key, &[]).unwrap();
+        assert!(store.load().unwrap()[0].tombstones.is_empty());
+        store.remove_series(&key).unwrap();
+        assert!(store.load().unwrap().is_empty());
+        store.remove_series(&key).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tables() {
+        let dir = temp_dir("tables");
+        let store = BlockStore::open(&dir).unwrap();
+
+        let mut options = TableOptions::new("cpu/usage");
+        options.precision = TimePrecision::Millis;
+        options.codec = CodecId::Integer;
+        options.value_policy.negative = ValueAction::Drop;
+        options.value_policy.infinite = ValueAction::Store;
+        options.rollups = vec![
+            RollupRule::new(60, Some(Duration::from_secs(86400))),
+            RollupRule::new(3600, None),
+        ];
+        store.write_table(&TableOptions::new("cpu/usage")).unwrap();
+        store.write_table(&options).unwrap();
+        // a table appended to without being created has no options to keep
+        let mut aob = AppendOnlyBlock::new(0, 7200, TimePrecision::Seconds, CodecId::default());
+        aob.encoder.encode(DataPoint::new(1, 1.0));
+        store
+            .write(&SeriesKey::new("mem", "k"), &ClosedBlock::new(&aob))
+            .unwrap();
+
+        assert_eq!(store.load_tables().unwrap(), vec![options]);
+        let series = store.load().unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].key, SeriesKey::new("mem", "k"));
+
+        assert!(parse_table("t", "codec lzma\n").is_none());
+        assert!(parse_table("t", "rollup\n").is_none());
+        assert_eq!(parse_table("t", "\n"), Some(TableOptions::new("t")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn same_range() {
+        let dir = temp_dir("same-range");
+        let store = BlockStore::open(&dir).unwrap();
+        let key = SeriesKey::new("t", "k");
+
+        // a block of late points after a restart covers the range of a persisted one
+        let mut paths = Vec::new();
+        for i in 0..3 {
+            let mut aob =
+                AppendOnlyBlock::new(7200, 14400, TimePrecision::Seconds, CodecId::default());
+            aob.encoder.encode(DataPoint::new(7200 + i, i as f64));
+            paths.push(store.write(&key, &ClosedBlock::new(&aob)).unwrap());
+        }
+        assert!(paths[0].ends_with("7200-14400.blk"));
+        assert!(paths[1].ends_with("7200-14400-1.blk"));
+        assert!(paths[2].ends_with("7200-14400-2.blk"));
+        assert_eq!(parse_range("7200-14400-x"), None);
+
+        let series = store.load().unwrap();
+        let mut points: Vec<DataPoint> = series[0]
+            .blocks
+            .iter()
+            .flat_map(|block| block.get_decoder().unwrap().into_iter())
+            .map(|dp| dp.unwrap())
+            .collect();
+        points.sort_by_key(|dp| dp.time);
+        assert_eq!(
+            points,
+            (0..3)
+                .map(|i| DataPoint::new(7200 + i, i as f64))
+                .collect::<Vec<_>>()
+        );
+
+        // a freed name is taken again
+        std::fs::remove_file(&paths[0]).unwrap();
+        let aob = AppendOnlyBlock::new(7200, 14400, TimePrecision::Seconds, CodecId::default());
+        assert_eq!(
+            store.write(&key, &ClosedBlock::new(&aob)).unwrap(),
+            paths[0]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reloaded_checkpoints() {
+        let dir = temp_dir("checkpoints");
+        let store = BlockStore::open(&dir).unwrap();
+
+        let mut aob = AppendOnlyBlock::new(0, 7200, TimePrecision::Seconds, CodecId::default());
+        for i in 0..2000 {
+            aob.encoder.encode(DataPoint::new(i, i as f64));
+        }
+        let block = ClosedBlock::new(&aob);
+        let key = SeriesKey::new("t", "k");
+        store.write(&key, &block).unwrap();
+        let rewritten =
+            ClosedBlock::from_bytes(block.bytes().unwrap(), 0, 7200, TimePrecision::Seconds);
+
+        // loaded and rewritten blocks seek as the block which kept its checkpoints
+        let series = store.load().unwrap();
+        for block in [&block, &rewritten, &series[0].blocks[0]].iter() {
+            let mut decoder = block.get_decoder_at(1500).unwrap();
+            assert_eq!(decoder.next().unwrap(), DataPoint::new(1024, 1024.0));
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let dir = temp_dir("corrupted");
+        let store = BlockStore::open(&dir).unwrap();
+
+        let mut aob = AppendOnlyBlock::new(0, 7200, TimePrecision::Seconds, CodecId::default());
+        for i in 0..100 {
+            aob.encoder.encode(DataPoint::new(i, i as f64));
+        }
+        let key = SeriesKey::new("t", "k");
+        let path = store.write(&key, &ClosedBlock::new(&aob)).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 8;
+        bytes[last] ^= 0b0001_0000;
+        std::fs::write(&path, bytes).unwrap();
+
+        // loading does not read the payload, every decoder fails until the block is dropped
+        let series = store.load().unwrap();
+        let block = &series[0].blocks[0];
+        for _ in 0..2 {
+            match block.get_decoder_at(50).err().unwrap() {
+                crate::Error::Decode(_) => {}
+                err => panic!("unexpected error {:?}", err),
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
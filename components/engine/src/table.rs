@@ -0,0 +1,204 @@
+use crate::rollup::RollupRule;
+use crate::Error;
+use common::TimePrecision;
+use tszv1::format::CodecId;
+use tszv1::{DataPoint, Value, ValueType};
+
+/// ValueAction
+///
+/// What to do with a data point whose value falls in one of the classes of `ValuePolicy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueAction {
+    /// encode the value as it is
+    Store,
+    /// refuse the data point and report an error to the caller
+    Reject,
+    /// silently discard the data point
+    Drop,
+}
+
+impl ValueAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueAction::Store => "store",
+            ValueAction::Reject => "reject",
+            ValueAction::Drop => "drop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ValueAction> {
+        match name {
+            "store" => Some(ValueAction::Store),
+            "reject" => Some(ValueAction::Reject),
+            "drop" => Some(ValueAction::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// ValuePolicy
+///
+/// ValuePolicy decides per table how values which are often produced by broken collectors are
+/// handled on append. Negative values are legitimate (temperatures, deltas) and are stored by
+/// default, NaN and infinite values are rejected by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValuePolicy {
+    pub negative: ValueAction,
+    pub nan: ValueAction,
+    pub infinite: ValueAction,
+}
+
+impl Default for ValuePolicy {
+    fn default() -> Self {
+        ValuePolicy {
+            negative: ValueAction::Store,
+            nan: ValueAction::Reject,
+            infinite: ValueAction::Reject,
+        }
+    }
+}
+
+impl ValuePolicy {
+    /// the action for `value`, NaN and infinite are checked before the sign. Bools and strings
+    /// are always stored, so are sketches which can not hold NaN or infinite values.
+    pub fn action(&self, value: &Value) -> (ValueAction, &'static str) {
+        let value = match *value {
+            Value::Float(v) => v,
+            Value::Integer(v) if v < 0 => return (self.negative, "negative"),
+            Value::Integer(_) | Value::Bool(_) | Value::String(_) | Value::Sketch(_) => {
+                return (ValueAction::Store, "")
+            }
+        };
+        if value.is_nan() {
+            (self.nan, "NaN")
+        } else if value.is_infinite() {
+            (self.infinite, "infinite")
+        } else if value < 0f64 {
+            (self.negative, "negative")
+        } else {
+            (ValueAction::Store, "")
+        }
+    }
+
+    /// check the data point against the policy, `Ok(false)` means the data point should be
+    /// dropped
+    pub fn accept(&self, table_name: &str, dp: &DataPoint) -> Result<bool, Error> {
+        match self.action(&dp.value) {
+            (ValueAction::Store, _) => Ok(true),
+            (ValueAction::Drop, _) => Ok(false),
+            (ValueAction::Reject, class) => Err(Error::ValueRejected(format!(
+                "{} value {} is not accepted by table {}",
+                class, dp.value, table_name
+            ))),
+        }
+    }
+}
+
+/// TableOptions
+///
+/// Per table settings, tables which are appended to before being created use the defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableOptions {
+    pub table_name: String,
+    pub value_policy: ValuePolicy,
+    /// unit of the timestamps of all series in the table, fixed once a series is created,
+    /// `Engine::create_table` refuses to change it
+    pub precision: TimePrecision,
+    /// codec of the values of new series, existing series keep the codec they were created
+    /// with. The codec decides the value type of the table, fixed once a series is created.
+    pub codec: CodecId,
+    /// the rollup tiers of the series created from now on, only numeric tables are rolled up
+    pub rollups: Vec<RollupRule>,
+}
+
+impl TableOptions {
+    pub fn new(table_name: &str) -> Self {
+        TableOptions {
+            table_name: table_name.to_string(),
+            value_policy: ValuePolicy::default(),
+            precision: TimePrecision::default(),
+            codec: CodecId::default(),
+            rollups: Vec::new(),
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.codec.value_type()
+    }
+
+    /// check the type of the value then the value policy, see `ValuePolicy::accept`
+    pub fn accept(&self, dp: &DataPoint) -> Result<bool, Error> {
+        let value_type = dp.value.value_type();
+        if value_type != self.value_type() {
+            return Err(Error::TypeMismatch(format!(
+                "{} value {} does not match the {} values of table {}",
+                value_type.name(),
+                dp.value,
+                self.value_type().name(),
+                self.table_name
+            )));
+        }
+        self.value_policy.accept(&self.table_name, dp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::table::{TableOptions, ValueAction};
+    use crate::Error;
+    use tszv1::format::CodecId;
+    use tszv1::DataPoint;
+
+    #[test]
+    fn default_policy() {
+        let options = TableOptions::new("t");
+
+        assert_eq!(options.accept(&DataPoint::new(1, 1.5)), Ok(true));
+        assert_eq!(options.accept(&DataPoint::new(1, -12.5)), Ok(true));
+        assert!(options.accept(&DataPoint::new(1, f64::NAN)).is_err());
+        assert!(options
+            .accept(&DataPoint::new(1, f64::NEG_INFINITY))
+            .is_err());
+    }
+
+    #[test]
+    fn custom_policy() {
+        let mut options = TableOptions::new("t");
+        options.value_policy.negative = ValueAction::Reject;
+        options.value_policy.nan = ValueAction::Drop;
+        options.value_policy.infinite = ValueAction::Store;
+
+        match options.accept(&DataPoint::new(1, -1.0)) {
+            Err(Error::ValueRejected(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(options.accept(&DataPoint::new(1, f64::NAN)), Ok(false));
+        assert_eq!(options.accept(&DataPoint::new(1, f64::INFINITY)), Ok(true));
+    }
+
+    #[test]
+    fn integer_table() {
+        let mut options = TableOptions::new("t");
+        options.codec = CodecId::Integer;
+        options.value_policy.negative = ValueAction::Drop;
+
+        assert_eq!(options.accept(&DataPoint::integer(1, i64::MAX)), Ok(true));
+        assert_eq!(options.accept(&DataPoint::integer(1, -1)), Ok(false));
+        match options.accept(&DataPoint::new(1, 1.5)) {
+            Err(Error::TypeMismatch(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn string_table() {
+        let mut options = TableOptions::new("t");
+        options.codec = CodecId::String;
+
+        assert_eq!(options.accept(&DataPoint::with_value(1, "down")), Ok(true));
+        match options.accept(&DataPoint::with_value(1, true)) {
+            Err(Error::TypeMismatch(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -1,110 +1,591 @@
-use crate::block::{AppendOnlyBlock, ClosedBlock};
+use crate::block::{AppendOnlyBlock, BlockBytes, BlockReader, ClosedBlock};
+use crate::cache::BlockCache;
+use crate::rollup::Tier;
+use crate::store::{BlockStore, SeriesKey};
+use crate::Error;
+use common::TimePrecision;
 use std::ops::DerefMut;
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::time::Duration;
-use tszv1::stream::BufferedReader;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tszv1::decode::Error as DecodeError;
+use tszv1::format::CodecId;
+use tszv1::rewrite::{self, Duplicates, RewriteError};
 use tszv1::{DataPoint, Encode, StdDecoder};
 
+/// BLOCK_PERIOD_SECS is the time span of the blocks of a series
+pub(crate) const BLOCK_PERIOD_SECS: u64 = 2 * 60 * 60;
+
+/// Tombstone
+///
+/// The time range [begin, end) of a series whose points are deleted. The points stay in the
+/// blocks until compaction rewrites them, searches skip them meanwhile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tombstone {
+    pub begin: u64,
+    pub end: u64,
+}
+
+impl Tombstone {
+    pub fn covers(&self, time: u64) -> bool {
+        time >= self.begin && time < self.end
+    }
+}
+
+/// Compaction
+///
+/// What `TS::compact` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Compaction {
+    /// windows rewritten into one block
+    pub windows: usize,
+    /// closed blocks replaced
+    pub blocks: usize,
+}
+
 #[derive(Clone)]
 pub struct TS {
     append_only_blocks: common::SharedRwLockVec<AppendOnlyBlock>,
     closed_blocks: common::SharedRwLockVec<ClosedBlock>,
+    tombstones: common::SharedRwLockVec<Tombstone>,
+    deleted: Arc<AtomicBool>, // the whole series is deleted, it takes no more points
+    precision: TimePrecision,
+    codec: CodecId,
     period: u64,
+    tiers: Arc<Vec<Tier>>, // the rollups of the closed blocks
+    cache: Option<Arc<BlockCache>>,
     timer_guard: Option<timer::Guard>,
-    data_tx: SyncSender<DataPoint>,
-    close: bool,
 }
 
 impl TS {
-    pub fn new(buffer_size: usize) -> Self {
-        let (data_tx, data_rx) = std::sync::mpsc::sync_channel(buffer_size);
+    /// new creates a series whose timestamps are in `precision`, blocks span 2 hours
+    pub fn new(precision: TimePrecision) -> Self {
+        TS::with_codec(precision, CodecId::default())
+    }
 
-        let ts = TS {
+    /// with_codec creates a series whose blocks compress values with `codec`
+    pub fn with_codec(precision: TimePrecision, codec: CodecId) -> Self {
+        TS::with_period(precision, codec, precision.from_secs(BLOCK_PERIOD_SECS))
+    }
+
+    /// with_period creates a series whose blocks span `period`, in the precision of the series
+    pub fn with_period(precision: TimePrecision, codec: CodecId, period: u64) -> Self {
+        TS {
             append_only_blocks: common::new_shared_rw_lock_vec(), // new_shared_rw_lock(AppendOnlyBlock::new(0, 0)),
             closed_blocks: common::new_shared_rw_lock_vec(),
-            period: 2 * 60 * 60,
+            tombstones: common::new_shared_rw_lock_vec(),
+            deleted: Arc::new(AtomicBool::new(false)),
+            precision,
+            codec,
+            period,
+            tiers: Arc::new(Vec::new()),
+            cache: None,
             timer_guard: None,
-            data_tx,
-            close: false,
-        };
+        }
+    }
 
-        ts.table_consumer(data_rx);
+    /// from_closed creates a series of the closed blocks and tombstones read back from its files
+    pub fn from_closed(
+        precision: TimePrecision,
+        codec: CodecId,
+        blocks: Vec<ClosedBlock>,
+        tombstones: Vec<Tombstone>,
+    ) -> Self {
+        let ts = TS::with_codec(precision, codec);
+        ts.closed_blocks.write().unwrap().extend(blocks);
+        ts.tombstones.write().unwrap().extend(tombstones);
         ts
     }
 
-    pub fn set_close(&mut self) {
-        self.close = true;
+    pub fn set_timer_guard(&mut self, guard: timer::Guard) {
+        self.timer_guard = Some(guard);
     }
 
-    fn table_consumer(&self, data_rx: Receiver<DataPoint>) {
-        let clone = self.clone();
-        std::thread::spawn(move || loop {
-            match data_rx.try_recv() {
-                Ok(raw) => {
-                    clone.append(raw);
-                }
-                Err(_) => {
-                    if clone.close {
-                        break;
+    pub fn precision(&self) -> TimePrecision {
+        self.precision
+    }
+
+    pub fn codec(&self) -> CodecId {
+        self.codec
+    }
+
+    /// the blocks created from now on span `period`
+    pub(crate) fn set_period(&mut self, period: u64) {
+        self.period = period;
+    }
+
+    /// the end of the last closed block, 0 without one
+    pub(crate) fn closed_until(&self) -> u64 {
+        let closed_blocks = self.closed_blocks.read().unwrap();
+        closed_blocks
+            .iter()
+            .map(|b| b.time_end())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// account the closed blocks in `cache`, which may evict them once persisted
+    pub(crate) fn set_cache(&mut self, cache: &Arc<BlockCache>) {
+        for block in self.closed_blocks.read().unwrap().iter() {
+            cache.admit(block);
+        }
+        self.cache = Some(cache.clone());
+    }
+
+    /// roll up each block from now on as it is closed into `tiers`
+    pub(crate) fn set_tiers(&mut self, tiers: Vec<Tier>) {
+        self.tiers = Arc::new(tiers);
+    }
+
+    /// the coarsest tier whose intervals fall into the buckets of `step` starting at
+    /// `begin_time`, a step of 0 is a single bucket
+    pub(crate) fn tier_for(&self, begin_time: u64, step: u64) -> Option<&Tier> {
+        self.tiers
+            .iter()
+            .filter(|tier| {
+                begin_time.is_multiple_of(tier.interval()) && step.is_multiple_of(tier.interval())
+            })
+            .max_by_key(|tier| tier.interval())
+    }
+
+    /// drop the rolled up blocks past the retention of their tier, returns the count of blocks
+    /// dropped
+    pub(crate) fn expire_tiers(&self) -> Result<usize, Error> {
+        let mut dropped = 0;
+        for tier in self.tiers.iter() {
+            dropped += tier.expire()?;
+        }
+        Ok(dropped)
+    }
+
+    /// check and roll down the append_only_blocks which ended `timeout` ago to closed_blocks,
+    /// the closed ones are rolled up into the tiers, which are rolled down in turn. Returns the
+    /// count of blocks rolled down, those of the tiers included
+    /// timeout: sec
+    pub fn roll_down(&self, timeout: u64) -> usize {
+        let now = self.precision.now();
+        let grace = self.precision.from_secs(timeout);
+        let closed = self.close_blocks(|block| now.saturating_sub(block.time_end) >= grace);
+        closed
+            + self
+                .tiers
+                .iter()
+                .map(|tier| tier.roll_down(timeout))
+                .sum::<usize>()
+    }
+
+    /// roll down every append_only_block of the series and its tiers, returns the count of
+    /// blocks rolled down
+    pub fn close_all(&self) -> usize {
+        let closed = self.close_blocks(|_| true);
+        closed
+            + self
+                .tiers
+                .iter()
+                .map(|tier| tier.close_all())
+                .sum::<usize>()
+    }
+
+    fn close_blocks<P>(&self, due: P) -> usize
+    where
+        P: Fn(&AppendOnlyBlock) -> bool,
+    {
+        // read check
+        if !self.append_only_blocks.read().unwrap().iter().any(&due) {
+            return 0;
+        }
+
+        // write check and roll down, both locks are held so searches see every point once
+        let mut closed = 0;
+        let mut rolled = Vec::new();
+        {
+            let mut append_only_blocks = self.append_only_blocks.write().unwrap();
+            let mut closed_blocks = self.closed_blocks.write().unwrap();
+            let mut i = 0;
+            while i < append_only_blocks.len() {
+                if due(&append_only_blocks[i]) {
+                    let block = ClosedBlock::new(&append_only_blocks.remove(i));
+                    let at = closed_blocks
+                        .iter()
+                        .position(|b| b.time_begin() > block.time_begin())
+                        .unwrap_or(closed_blocks.len());
+                    if let Some(cache) = &self.cache {
+                        cache.admit(&block);
                     }
-                    std::thread::sleep(Duration::from_secs(1));
+                    if !self.tiers.is_empty() {
+                        rolled.push(block.clone());
+                    }
+                    closed_blocks.insert(at, block);
+                    closed += 1;
+                } else {
+                    i += 1;
                 }
             }
-        });
+        }
+
+        // the tiers are written out of the locks, a search meanwhile reads the raw points
+        let tombstones = self.tombstones();
+        for block in &rolled {
+            for tier in self.tiers.iter() {
+                if let Err(err) = tier.roll_up(block, &tombstones) {
+                    error!(
+                        "failed to roll up block {}: {}",
+                        self.precision
+                            .interval_to_string(block.time_begin(), block.time_end()),
+                        err
+                    );
+                }
+            }
+        }
+        closed
     }
 
-    pub fn set_timer_guard(&mut self, guard: timer::Guard) {
-        self.timer_guard = Some(guard);
+    /// drop the closed_blocks which ended at or before `time` and remove their files, returns
+    /// the count of blocks dropped
+    pub fn drop_before(&self, time: u64) -> Result<usize, Error> {
+        let mut closed_blocks = self.closed_blocks.write().unwrap();
+        let count = closed_blocks.len();
+        let mut failed = None;
+        closed_blocks.retain(|block| {
+            if block.time_end() > time {
+                return true;
+            }
+            match block.path().map(std::fs::remove_file) {
+                Some(Err(err)) => {
+                    failed = Some(Error::Storage(format!(
+                        "{}: {}",
+                        block.path().unwrap().display(),
+                        err
+                    )));
+                    true
+                }
+                _ => false,
+            }
+        });
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(count - closed_blocks.len()),
+        }
     }
 
-    /// check and roll down append_only_block to closed_block
-    pub fn roll_down(&self, timeout: u64) {
-        // read check
-        let closed_block = {
-            let append_only_blocks = self.append_only_blocks.read().unwrap();
-            match append_only_blocks.get(0) {
-                Some(block) => {
-                    let now = common::now_timestamp_secs();
-                    if (now - block.time_begin) < timeout {
-                        None
+    /// merge the closed_blocks of each past time window of `window` into one block, dropping the
+    /// points deleted by a tombstone and resolving the points of the same time by `duplicates`.
+    /// Windows of a single block are rewritten only to apply tombstones. The new block replaces
+    /// the old ones at once under the lock, so searches never see both or neither. With `store`
+    /// the new block is written before the lock is taken and the old files are removed after it
+    /// is released; a crash in between leaves both, the duplicated points are resolved when the
+    /// window is compacted again.
+    /// window: in the precision of the series
+    pub(crate) fn compact(
+        &self,
+        window: u64,
+        duplicates: Duplicates,
+        store: Option<(&BlockStore, &SeriesKey)>,
+    ) -> Result<Compaction, Error> {
+        let window = window.max(1);
+        let now = self.precision.now();
+        let tombstones = self.tombstones();
+        let deleted =
+            |begin: u64, end: u64| tombstones.iter().any(|t| t.begin < end && t.end > begin);
+
+        // the source blocks of each window
+        let mut plans: Vec<(u64, Vec<ClosedBlock>)> = Vec::new();
+        {
+            let closed = self.closed_blocks.read().unwrap();
+            for block in closed.iter() {
+                let begin = block.time_begin() - block.time_begin() % window;
+                if begin.saturating_add(window) > now {
+                    continue;
+                }
+                match plans.iter_mut().find(|(b, _)| *b == begin) {
+                    Some((_, blocks)) => blocks.push(block.clone()),
+                    None => plans.push((begin, vec![block.clone()])),
+                }
+            }
+            plans.retain(|(begin, blocks)| {
+                blocks.len() > 1 || deleted(*begin, begin.saturating_add(window))
+            });
+        }
+
+        let mut compaction = Compaction::default();
+        let mut compacted: Vec<ClosedBlock> = Vec::new();
+        for (begin, sources) in plans {
+            let bytes = sources
+                .iter()
+                .map(|b| b.bytes())
+                .collect::<Result<Vec<_>, Error>>()?;
+            let slices: Vec<&[u8]> = bytes.iter().map(|b| b.as_ref()).collect();
+            let merged =
+                rewrite::merge_retain(&slices, self.precision, self.codec, duplicates, |dp| {
+                    !tombstones.iter().any(|t| t.covers(dp.time))
+                })
+                .map_err(|err| match err {
+                    RewriteError::Decode(err) => Error::Decode(err),
+                    err => Error::Storage(format!("compaction failed: {}", err)),
+                })?;
+
+            let end = sources
+                .iter()
+                .map(|b| b.time_end())
+                .fold(begin.saturating_add(window), u64::max);
+            let mut block = merged.map(|bytes| {
+                ClosedBlock::from_bytes(
+                    BlockBytes::Memory(Arc::from(bytes)),
+                    begin,
+                    end,
+                    self.precision,
+                )
+            });
+            // a new file, never one of the sources even for a window of a single block
+            if let (Some(block), Some((store, key))) = (block.as_mut(), store) {
+                let path = store.write(key, block)?;
+                block.set_path(path);
+            }
+
+            let mut old_files = Vec::new();
+            let swapped = {
+                // the roll down order of locks, the active blocks are not changed
+                let _active = self.append_only_blocks.read().unwrap();
+                let mut closed = self.closed_blocks.write().unwrap();
+                let is_source = |b: &ClosedBlock| sources.iter().any(|s| s.same(b));
+                // a delete or another compaction changed the window meanwhile
+                let changed = self.deleted.load(Ordering::Acquire)
+                    || closed.iter().filter(|b| is_source(b)).count() != sources.len();
+                if !changed {
+                    closed.retain(|b| {
+                        if !is_source(b) {
+                            return true;
+                        }
+                        if let Some(path) = b.path() {
+                            old_files.push(path.to_path_buf());
+                        }
+                        false
+                    });
+                    if let Some(block) = block.clone() {
+                        if let Some(cache) = &self.cache {
+                            cache.admit(&block);
+                        }
+                        compacted.push(block.clone());
+                        let at = closed
+                            .iter()
+                            .position(|b| b.time_begin() > begin)
+                            .unwrap_or(closed.len());
+                        closed.insert(at, block);
+                    }
+                }
+                !changed
+            };
+
+            if !swapped {
+                // the new file is not part of the series, the write may have recreated the
+                // directory of a deleted series
+                if let (Some(path), Some((store, key))) =
+                    (block.as_ref().and_then(|b| b.path()), store)
+                {
+                    if self.deleted.load(Ordering::Acquire) {
+                        store.remove_series(key)?;
                     } else {
-                        Some(ClosedBlock::new(block))
+                        std::fs::remove_file(path).map_err(|err| {
+                            Error::Storage(format!("{}: {}", path.display(), err))
+                        })?;
                     }
                 }
-                None => None,
+                continue;
             }
-        };
+            for path in old_files {
+                std::fs::remove_file(&path)
+                    .map_err(|err| Error::Storage(format!("{}: {}", path.display(), err)))?;
+            }
+            compaction.windows += 1;
+            compaction.blocks += sources.len();
+        }
+
+        if compaction.windows > 0 {
+            self.drop_applied(&tombstones, &compacted, store)?;
+        }
+        Ok(compaction)
+    }
 
-        // write check and roll down
-        match closed_block {
-            Some(block) => {
-                self.closed_blocks.write().unwrap().push(block);
+    /// drop the tombstones of `applied` whose points could only be in the `compacted` blocks,
+    /// which were written without them
+    fn drop_applied(
+        &self,
+        applied: &[Tombstone],
+        compacted: &[ClosedBlock],
+        store: Option<(&BlockStore, &SeriesKey)>,
+    ) -> Result<(), Error> {
+        let active = self.append_only_blocks.read().unwrap();
+        let closed = self.closed_blocks.read().unwrap();
+        let mut tombstones = self.tombstones.write().unwrap();
+        let overlaps = |t: &Tombstone, begin: u64, end: u64| t.begin < end && t.end > begin;
+        let count = tombstones.len();
+        tombstones.retain(|t| {
+            !applied.contains(t)
+                || active.iter().any(|b| overlaps(t, b.time_begin, b.time_end))
+                || closed.iter().any(|b| {
+                    !compacted.iter().any(|c| c.same(b))
+                        && overlaps(t, b.time_begin(), b.time_end())
+                })
+        });
+        match store {
+            Some((store, key)) if tombstones.len() != count => {
+                store.write_tombstones(key, &tombstones)
             }
-            None => {}
+            _ => Ok(()),
         }
     }
 
-    // todo error logic
-    pub fn append_async(&self, dp: DataPoint) {
-        self.data_tx.send(dp).unwrap();
+    /// the count of append_only_blocks and of closed_blocks
+    pub fn block_counts(&self) -> (usize, usize) {
+        let active = self.append_only_blocks.read().unwrap().len();
+        (active, self.closed_blocks.read().unwrap().len())
+    }
+
+    /// write the closed_blocks of the series `key` and of its tiers which have no file yet to
+    /// `store`, returns the count of blocks written
+    pub(crate) fn persist(&self, store: &BlockStore, key: &SeriesKey) -> Result<usize, Error> {
+        let mut written = 0;
+        {
+            let mut closed_blocks = self.closed_blocks.write().unwrap();
+            // checked under the lock `delete` takes, no file is written once the series is gone
+            if self.deleted.load(Ordering::Acquire) {
+                return Ok(0);
+            }
+            for block in closed_blocks.iter_mut().filter(|b| b.path().is_none()) {
+                let path = store.write(key, block)?;
+                block.set_path(path);
+                if let Some(cache) = &self.cache {
+                    cache.persisted(block);
+                }
+                written += 1;
+            }
+        }
+        for tier in self.tiers.iter() {
+            written += tier.persist(store, key)?;
+        }
+        Ok(written)
+    }
+
+    /// delete the points in [begin, end), searches skip them at once. The tombstones of the
+    /// series `key` are written to `store` when given, so a restart does not bring the points
+    /// back before compaction drops them.
+    pub(crate) fn delete_range(
+        &self,
+        begin: u64,
+        end: u64,
+        store: Option<(&BlockStore, &SeriesKey)>,
+    ) -> Result<(), Error> {
+        if begin >= end {
+            return Ok(());
+        }
+        {
+            let mut tombstones = self.tombstones.write().unwrap();
+            tombstones.push(Tombstone { begin, end });
+            merge_tombstones(&mut tombstones);
+            if let Some((store, key)) = store {
+                store.write_tombstones(key, &tombstones)?;
+            }
+        }
+        for tier in self.tiers.iter() {
+            tier.delete_range(begin, end, store)?;
+        }
+        Ok(())
+    }
+
+    /// the deleted time ranges, ordered and disjoint
+    pub fn tombstones(&self) -> Vec<Tombstone> {
+        self.tombstones.read().unwrap().clone()
+    }
+
+    /// delete the whole series `key` with its tiers: its blocks are dropped, its files removed
+    /// from `store` when given, and points appended later are ignored
+    pub(crate) fn delete(&self, store: Option<(&BlockStore, &SeriesKey)>) -> Result<(), Error> {
+        let mut append_only_blocks = self.append_only_blocks.write().unwrap();
+        let mut closed_blocks = self.closed_blocks.write().unwrap();
+        self.deleted.store(true, Ordering::Release);
+        append_only_blocks.clear();
+        closed_blocks.clear();
+        self.tombstones.write().unwrap().clear();
+        if let Some((store, key)) = store {
+            store.remove_series(key)?;
+        }
+        for tier in self.tiers.iter() {
+            tier.delete(store)?;
+        }
+        Ok(())
     }
 
     pub fn append(&self, dp: DataPoint) {
         let mut append_only_blocks = self.append_only_blocks.write().unwrap();
-        let append_only_blocks = append_only_blocks.deref_mut();
+        if self.deleted.load(Ordering::Acquire) {
+            return;
+        }
+        self.append_to(append_only_blocks.deref_mut(), dp);
+    }
 
+    /// append `points` in time order, those older than the first active block, which `append`
+    /// skips, are encoded into closed blocks of their own, one per period
+    pub(crate) fn append_or_close(&self, points: Vec<DataPoint>) {
+        let mut append_only_blocks = self.append_only_blocks.write().unwrap();
+        if self.deleted.load(Ordering::Acquire) {
+            return;
+        }
+        let start_time = append_only_blocks.first().map(|b| b.time_begin);
+        let mut late: Vec<AppendOnlyBlock> = Vec::new();
+        for dp in points {
+            if start_time.is_none_or(|start| dp.time >= start) {
+                self.append_to(append_only_blocks.deref_mut(), dp);
+                continue;
+            }
+            let (begin_ts, end_ts) = self.time_align(dp.time, self.period);
+            match late.last_mut() {
+                Some(aob) if aob.time_begin == begin_ts => aob.encoder.encode(dp),
+                _ => {
+                    let mut aob =
+                        AppendOnlyBlock::new(begin_ts, end_ts, self.precision, self.codec);
+                    aob.encoder.encode(dp);
+                    late.push(aob);
+                }
+            }
+        }
+        if late.is_empty() {
+            return;
+        }
+
+        // both locks are held like in a roll down, the blocks are written by `persist`
+        let mut closed_blocks = self.closed_blocks.write().unwrap();
+        for aob in late {
+            info!(
+                "close late block {},{} [{}/{}]",
+                aob.time_begin,
+                aob.time_end,
+                self.precision.timestamp_to_string(aob.time_begin),
+                self.precision.timestamp_to_string(aob.time_end)
+            );
+            let block = ClosedBlock::new(&aob);
+            let at = closed_blocks
+                .iter()
+                .position(|b| b.time_begin() > block.time_begin())
+                .unwrap_or(closed_blocks.len());
+            if let Some(cache) = &self.cache {
+                cache.admit(&block);
+            }
+            closed_blocks.insert(at, block);
+        }
+    }
+
+    fn append_to(&self, append_only_blocks: &mut Vec<AppendOnlyBlock>, dp: DataPoint) {
         // no active block
         if append_only_blocks.len() == 0 {
             let (begin_ts, end_ts) = self.time_align(dp.time, self.period);
-            let mut aob = AppendOnlyBlock::new(begin_ts, end_ts);
+            let mut aob = AppendOnlyBlock::new(begin_ts, end_ts, self.precision, self.codec);
             aob.encoder.encode(dp);
             append_only_blocks.push(aob);
             info!(
                 "create AppendOnlyBlock {},{} [{}/{}]",
                 begin_ts,
                 end_ts,
-                common::timestamp_secs_to_string(begin_ts),
-                common::timestamp_secs_to_string(end_ts)
+                self.precision.timestamp_to_string(begin_ts),
+                self.precision.timestamp_to_string(end_ts)
             );
             return;
         }
@@ -129,7 +610,7 @@ impl TS {
 
         // if not find, create new block and encode DataPoint
         let (begin_ts, end_ts) = self.time_align(dp.time, self.period);
-        let mut aob = AppendOnlyBlock::new(begin_ts, end_ts);
+        let mut aob = AppendOnlyBlock::new(begin_ts, end_ts, self.precision, self.codec);
         aob.encoder.encode(dp);
 
         // find the index by time and insert block into append_only_blocks
@@ -139,58 +620,153 @@ impl TS {
                 return;
             }
         }
+        append_only_blocks.push(aob);
     }
 
+    /// call `f` with a decoder of each block which may hold points in [begin_time, end_time]
+    /// until `limit` points are collected, 0 means no limit. The first decode error is returned.
     pub fn get_decoder<F>(
         &self,
         begin_time: u64,
         end_time: u64,
         limit: usize,
         f: F,
-    ) -> Vec<DataPoint>
+    ) -> Result<Vec<DataPoint>, Error>
     where
-        F: Fn(StdDecoder<BufferedReader>, &mut Vec<DataPoint>),
+        F: Fn(StdDecoder<BlockReader>, &mut Vec<DataPoint>) -> Result<(), DecodeError>,
     {
         info!(
             "search ts: {}",
-            common::timestamp_to_interval_str(begin_time, end_time)
+            self.precision.interval_to_string(begin_time, end_time)
         );
 
-        let r = self.append_only_blocks.read().unwrap();
+        let in_range =
+            |time_begin: u64, time_end: u64| time_end > begin_time && time_begin <= end_time;
+        // the closed blocks are older than the active ones, both are locked, in the order of a
+        // roll down, so a concurrent roll down neither hides nor repeats a block. The locks are
+        // only held to take the blocks in the interval, they are read once released.
+        let (blocks, tombstones) = {
+            let active = self.append_only_blocks.read().unwrap();
+            let closed = self.closed_blocks.read().unwrap();
+            let tombstones = self.tombstones.read().unwrap();
+            let mut blocks = Vec::new();
+            for block in closed.iter() {
+                if in_range(block.time_begin(), block.time_end()) {
+                    blocks.push(Searched::Closed(block.clone()));
+                }
+            }
+            for block in active.iter() {
+                if in_range(block.time_begin, block.time_end) {
+                    // reads a snapshot of the points appended so far
+                    blocks.push(Searched::Active {
+                        time_begin: block.time_begin,
+                        time_end: block.time_end,
+                        decoder: Box::new(block.get_decoder_at(begin_time)?),
+                    });
+                }
+            }
+            (blocks, tombstones.clone())
+        };
+
         let mut dp_vec = Vec::new();
-        for block in r.iter() {
+        for block in blocks {
+            let (time_begin, time_end) = block.range();
             info!(
                 "--> block: {}",
-                common::timestamp_to_interval_str(block.time_begin, block.time_end)
+                self.precision.interval_to_string(time_begin, time_end)
             );
-            let a = block.get_decoder();
-            f(a, dp_vec.as_mut());
+            // an evicted block is read back here
+            let decoder = block.into_decoder(begin_time)?;
+            // prune blocks whose header shows no point in the interval
+            if decoder
+                .header()
+                .is_some_and(|h| !h.overlaps(begin_time, end_time))
+            {
+                continue;
+            }
+            let found = dp_vec.len();
+            f(decoder, dp_vec.as_mut())?;
+            if !tombstones.is_empty() {
+                let points = dp_vec.split_off(found);
+                dp_vec.extend(
+                    points
+                        .into_iter()
+                        .filter(|dp| !tombstones.iter().any(|t| t.covers(dp.time))),
+                );
+            }
 
             if limit > 0 && dp_vec.len() >= limit {
                 break;
             }
         }
 
-        dp_vec
+        Ok(dp_vec)
     }
 
-    /// timestamp : sec
-    /// period: sec
+    /// timestamp: in the precision of the series
+    /// period: in the precision of the series
     fn time_align(&self, timestamp: u64, period: u64) -> (u64, u64) {
         let ts = timestamp - timestamp % period;
         (ts as u64, (ts + period) as u64)
     }
 }
 
+/// a block read by a search
+enum Searched {
+    Closed(ClosedBlock),
+    Active {
+        time_begin: u64,
+        time_end: u64,
+        decoder: Box<StdDecoder<BlockReader>>,
+    },
+}
+
+impl Searched {
+    fn range(&self) -> (u64, u64) {
+        match self {
+            Searched::Closed(b) => (b.time_begin(), b.time_end()),
+            Searched::Active {
+                time_begin,
+                time_end,
+                ..
+            } => (*time_begin, *time_end),
+        }
+    }
+
+    fn into_decoder(self, time: u64) -> Result<StdDecoder<BlockReader>, Error> {
+        match self {
+            Searched::Closed(b) => b.get_decoder_at(time),
+            Searched::Active { decoder, .. } => Ok(*decoder),
+        }
+    }
+}
+
+/// sort `tombstones` and merge the overlapping or adjacent ones
+fn merge_tombstones(tombstones: &mut Vec<Tombstone>) {
+    tombstones.sort_by_key(|t| t.begin);
+    let mut merged: Vec<Tombstone> = Vec::with_capacity(tombstones.len());
+    for t in tombstones.drain(..) {
+        match merged.last_mut() {
+            Some(last) if t.begin <= last.end => last.end = last.end.max(t.end),
+            _ => merged.push(t),
+        }
+    }
+    *tombstones = merged;
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::ts::TS;
+    use crate::store::{BlockStore, SeriesKey};
+    use crate::ts::{merge_tombstones, Compaction, Tombstone, TS};
     use chrono::{DateTime, Utc};
+    use common::TimePrecision;
     use std::time::{Duration, UNIX_EPOCH};
+    use tszv1::rewrite::Duplicates;
+    use tszv1::DataPoint;
 
     #[test]
     fn time_align_test() {
-        let ts = TS::new(1000);
+        let ts = TS::new(TimePrecision::Seconds);
         {
             // Tue Jan 14 2020 08:02:26 GMT+0800
             let timestamp = 1578960146;
@@ -220,4 +796,186 @@ mod tests {
 
         //        ts.get_decoder(0, 0, |_, _| {})
     }
+
+    #[test]
+    fn time_align_precision_test() {
+        // Tue Jan 14 2020 08:02:26.789 GMT+0800
+        let ts = TS::new(TimePrecision::Millis);
+        let (b, e) = ts.time_align(1578960146789, ts.period);
+        assert_eq!(b, 1578960000000);
+        assert_eq!(e, 1578960000000 + 2 * 60 * 60 * 1000);
+
+        let ts = TS::new(TimePrecision::Nanos);
+        let (b, e) = ts.time_align(1578960146789123456, ts.period);
+        assert_eq!(b, 1578960000000000000);
+        assert_eq!(e, 1578960000000000000 + 2 * 60 * 60 * 1_000_000_000);
+    }
+
+    #[test]
+    fn search_without_locks() {
+        let ts = TS::new(TimePrecision::Seconds);
+        let start = common::now_timestamp_secs();
+        for i in 0..10 {
+            ts.append(DataPoint::new(start + i, i as f64));
+        }
+
+        // the series takes points and deletes while a search decodes, the search reads the
+        // points appended before it
+        let points = ts
+            .get_decoder(0, u64::MAX, 0, |decoder, dp_vec| {
+                ts.append(DataPoint::new(start + 10, 10.0));
+                ts.delete_range(start, start + 1, None).unwrap();
+                for dp in decoder {
+                    dp_vec.push(dp?);
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(points.len(), 10);
+        assert_eq!(points[0], DataPoint::new(start, 0.0));
+
+        let points = ts
+            .get_decoder(0, u64::MAX, 0, |decoder, dp_vec| {
+                for dp in decoder {
+                    dp_vec.push(dp?);
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(points.len(), 10);
+        assert_eq!(points[9], DataPoint::new(start + 10, 10.0));
+    }
+
+    #[test]
+    fn search_inclusive_end() {
+        let day = 24 * 60 * 60;
+        let hour = 60 * 60;
+        let ts = TS::new(TimePrecision::Seconds);
+        let begin = common::now_timestamp_secs() / day * day - day;
+        for time in (0..4 * hour).step_by(600) {
+            ts.append(DataPoint::new(begin + time, time as f64));
+        }
+        assert_eq!(ts.close_all(), 2);
+
+        // the end of a search is included, also when it is the first point of a sealed block
+        let points = ts
+            .get_decoder(begin + hour, begin + 2 * hour, 0, |decoder, dp_vec| {
+                for dp in decoder {
+                    let dp = dp?;
+                    if dp.time >= begin + hour && dp.time <= begin + 2 * hour {
+                        dp_vec.push(dp);
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        let expected: Vec<DataPoint> = (hour..=2 * hour)
+            .step_by(600)
+            .map(|time| DataPoint::new(begin + time, time as f64))
+            .collect();
+        assert_eq!(points, expected);
+    }
+
+    #[test]
+    fn tombstones() {
+        let mut tombstones = vec![
+            Tombstone { begin: 50, end: 60 },
+            Tombstone { begin: 10, end: 20 },
+            Tombstone { begin: 20, end: 30 },
+            Tombstone { begin: 15, end: 25 },
+        ];
+        merge_tombstones(&mut tombstones);
+        assert_eq!(
+            tombstones,
+            vec![
+                Tombstone { begin: 10, end: 30 },
+                Tombstone { begin: 50, end: 60 }
+            ]
+        );
+
+        let ts = TS::new(TimePrecision::Seconds);
+        let start = common::now_timestamp_secs();
+        for i in 0..100 {
+            ts.append(tszv1::DataPoint::new(start + i, i as f64));
+        }
+        ts.delete_range(start + 10, start + 90, None).unwrap();
+        let search = |limit| {
+            ts.get_decoder(start, start + 100, limit, |decoder, dp_vec| {
+                for dp in decoder {
+                    dp_vec.push(dp?);
+                }
+                Ok(())
+            })
+            .unwrap()
+        };
+        let times: Vec<u64> = search(0).iter().map(|dp| dp.time - start).collect();
+        let expected: Vec<u64> = (0..10).chain(90..100).collect();
+        assert_eq!(times, expected);
+
+        // a deleted series takes no more points
+        ts.delete(None).unwrap();
+        ts.append(tszv1::DataPoint::new(start, 0.0));
+        assert!(search(0).is_empty());
+        assert_eq!(ts.block_counts(), (0, 0));
+    }
+
+    #[test]
+    fn compact() {
+        let dir = crate::store::temp_dir("compact");
+        let store = BlockStore::open(&dir).unwrap();
+        let key = SeriesKey::new("t", "k");
+        let files = Some((&store, &key));
+        let day = 24 * 60 * 60;
+        let hour = 60 * 60;
+
+        // two days ago: 12 blocks of the first day, 2 of the second
+        let ts = TS::new(TimePrecision::Seconds);
+        let begin = common::now_timestamp_secs() / day * day - 2 * day;
+        for time in (0..day + 4 * hour).step_by(60) {
+            ts.append(DataPoint::new(begin + time, time as f64));
+        }
+        assert_eq!(ts.close_all(), 14);
+        assert_eq!(ts.persist(&store, &key).unwrap(), 14);
+        ts.delete_range(begin + hour, begin + 2 * hour, files)
+            .unwrap();
+        ts.delete_range(begin + 3 * day, begin + 4 * day, files)
+            .unwrap();
+
+        let compaction = ts.compact(day, Duplicates::KeepLast, files).unwrap();
+        assert_eq!(
+            compaction,
+            Compaction {
+                windows: 2,
+                blocks: 14
+            }
+        );
+        assert_eq!(ts.block_counts(), (0, 2));
+        // the applied tombstone is gone, the one no block overlaps too
+        assert!(ts.tombstones().is_empty());
+
+        let points = ts
+            .get_decoder(0, u64::MAX, 0, |decoder, dp_vec| {
+                for dp in decoder {
+                    dp_vec.push(dp?);
+                }
+                Ok(())
+            })
+            .unwrap();
+        let expected: Vec<DataPoint> = (0..day + 4 * hour)
+            .step_by(60)
+            .filter(|time| *time < hour || *time >= 2 * hour)
+            .map(|time| DataPoint::new(begin + time, time as f64))
+            .collect();
+        assert_eq!(points, expected);
+
+        // one file per window is left, nothing more to compact
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded[0].blocks.len(), 2);
+        assert!(loaded[0].tombstones.is_empty());
+        assert_eq!(loaded[0].blocks[0].time_end(), begin + day);
+        let compaction = ts.compact(day, Duplicates::KeepLast, files).unwrap();
+        assert_eq!(compaction, Compaction::default());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,510 @@
+use crate::ts::TS;
+use crate::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tszv1::DataPoint;
+
+/// SHARD_QUEUE_LEN is the default count of points a shard queues before it is overloaded
+pub const SHARD_QUEUE_LEN: usize = 65536;
+
+/// OverloadPolicy
+///
+/// What an append does when the queue of its shard is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverloadPolicy {
+    /// wait up to the given time for room, then fail with `Error::Overloaded`
+    Block(Duration),
+    /// make room by discarding the oldest point queued by the shard
+    DropOldest,
+    /// fail with `Error::Overloaded` at once
+    Reject,
+}
+
+/// IngestOptions
+///
+/// The settings of the shard workers which encode the appended points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngestOptions {
+    /// count of shard workers, one per available core by default
+    pub shards: usize,
+    /// points queued per shard
+    pub queue_len: usize,
+    pub policy: OverloadPolicy,
+    /// how long overloaded clients are told to wait before retrying
+    pub retry_after: Duration,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        IngestOptions {
+            shards: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4),
+            queue_len: SHARD_QUEUE_LEN,
+            policy: OverloadPolicy::Block(Duration::from_millis(100)),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+/// QueueStats
+///
+/// The state of the queue of a shard, the counters are totals since the engine started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueStats {
+    pub shard: usize,
+    pub depth: usize,
+    pub capacity: usize,
+    pub appended: u64,
+    /// points discarded by `OverloadPolicy::DropOldest`
+    pub dropped: u64,
+    /// appends which failed with `Error::Overloaded`
+    pub rejected: u64,
+}
+
+enum Job {
+    Append(TS, DataPoint),
+    /// acknowledged once the jobs queued before it are done
+    Barrier(SyncSender<()>),
+}
+
+struct Jobs {
+    jobs: VecDeque<Job>,
+    points: usize, // Append jobs in `jobs`, barriers do not take room
+    closed: bool,
+}
+
+/// Queue
+///
+/// The bounded queue of a shard, appends never block longer than their policy allows.
+struct Queue {
+    jobs: Mutex<Jobs>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    alive: AtomicBool,
+    appended: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Queue {
+    fn new(capacity: usize) -> Self {
+        Queue {
+            jobs: Mutex::new(Jobs {
+                jobs: VecDeque::new(),
+                points: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            alive: AtomicBool::new(true),
+            appended: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn unavailable(shard: usize) -> Error {
+        Error::Unavailable(format!("the worker of shard {} stopped", shard))
+    }
+
+    fn push(
+        &self,
+        shard: usize,
+        ts: &TS,
+        dp: DataPoint,
+        policy: OverloadPolicy,
+        retry_after: Duration,
+    ) -> Result<(), Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if !self.alive.load(Ordering::Acquire) {
+            return Err(Queue::unavailable(shard));
+        }
+        if jobs.closed {
+            return Err(Error::Unavailable(format!("shard {} is shut down", shard)));
+        }
+        if jobs.points >= self.capacity {
+            match policy {
+                OverloadPolicy::Block(timeout) => {
+                    let deadline = Instant::now() + timeout;
+                    while jobs.points >= self.capacity && self.alive.load(Ordering::Acquire) {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            break;
+                        }
+                        jobs = self.not_full.wait_timeout(jobs, deadline - now).unwrap().0;
+                    }
+                }
+                OverloadPolicy::DropOldest => {
+                    if let Some(i) = jobs.jobs.iter().position(|job| match job {
+                        Job::Append(..) => true,
+                        Job::Barrier(_) => false,
+                    }) {
+                        jobs.jobs.remove(i);
+                        jobs.points -= 1;
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                OverloadPolicy::Reject => {}
+            }
+        }
+
+        if !self.alive.load(Ordering::Acquire) {
+            return Err(Queue::unavailable(shard));
+        }
+        if jobs.points >= self.capacity {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::Overloaded(
+                format!("the queue of shard {} is full", shard),
+                retry_after,
+            ));
+        }
+
+        jobs.jobs.push_back(Job::Append(ts.clone(), dp));
+        jobs.points += 1;
+        self.appended.fetch_add(1, Ordering::Relaxed);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn push_barrier(&self, done: SyncSender<()>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if self.alive.load(Ordering::Acquire) {
+            jobs.jobs.push_back(Job::Barrier(done));
+            self.not_empty.notify_one();
+        }
+    }
+
+    /// the next job, `None` once the queue is closed and drained
+    fn pop(&self) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            if let Some(job) = jobs.jobs.pop_front() {
+                if let Job::Append(..) = job {
+                    jobs.points -= 1;
+                    self.not_full.notify_one();
+                }
+                return Some(job);
+            }
+            if jobs.closed {
+                return None;
+            }
+            jobs = self.not_empty.wait(jobs).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        self.jobs.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+    }
+
+    fn stats(&self, shard: usize) -> QueueStats {
+        QueueStats {
+            shard,
+            depth: self.jobs.lock().unwrap().points,
+            capacity: self.capacity,
+            appended: self.appended.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// marks the queue dead when its worker ends, even by a panic, so appends fail instead of
+/// waiting for a worker which is gone
+struct Alive(Arc<Queue>);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        let queue = &self.0;
+        queue.alive.store(false, Ordering::Release);
+        // drops the queued barriers, which wakes up their waiters
+        let mut jobs = match queue.jobs.lock() {
+            Ok(jobs) => jobs,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        jobs.jobs.clear();
+        jobs.points = 0;
+        queue.not_full.notify_all();
+    }
+}
+
+/// WorkerPool
+///
+/// WorkerPool encodes the appended points on a fixed set of shard workers. A series is hashed to
+/// a shard by its key, so the points of a series are encoded in the order they were appended by
+/// one worker and the thread count does not grow with the series count. A worker blocks on its
+/// queue and encodes a point as soon as it is queued.
+pub(crate) struct WorkerPool {
+    queues: Vec<Arc<Queue>>,
+    shut_down: AtomicBool,
+    policy: OverloadPolicy,
+    retry_after: Duration,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(options: &IngestOptions) -> Self {
+        let queues = (0..options.shards.max(1))
+            .map(|i| {
+                let queue = Arc::new(Queue::new(options.queue_len));
+                let alive = Alive(queue.clone());
+                std::thread::Builder::new()
+                    .name(format!("shard-{}", i))
+                    .spawn(move || WorkerPool::work(alive))
+                    .unwrap();
+                queue
+            })
+            .collect();
+        WorkerPool {
+            queues,
+            shut_down: AtomicBool::new(false),
+            policy: options.policy,
+            retry_after: options.retry_after,
+        }
+    }
+
+    fn work(alive: Alive) {
+        while let Some(job) = alive.0.pop() {
+            match job {
+                Job::Append(ts, dp) => ts.append(dp),
+                Job::Barrier(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn shard(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.queues.len() as u64) as usize
+    }
+
+    /// queue `dp` for the series `ts` of `key`, see `OverloadPolicy` for a full queue
+    pub(crate) fn append(&self, key: &str, ts: &TS, dp: DataPoint) -> Result<(), Error> {
+        if self.shut_down.load(Ordering::Acquire) {
+            return Err(Error::Unavailable("the engine is shut down".to_string()));
+        }
+        let shard = self.shard(key);
+        self.queues[shard].push(shard, ts, dp, self.policy, self.retry_after)
+    }
+
+    /// wait until the points queued so far are encoded
+    pub(crate) fn flush(&self) {
+        let acks: Vec<Receiver<()>> = self
+            .queues
+            .iter()
+            .map(|queue| {
+                let (tx, rx) = std::sync::mpsc::sync_channel(1);
+                queue.push_barrier(tx);
+                rx
+            })
+            .collect();
+        for ack in acks {
+            // fails at once for a stopped worker
+            let _ = ack.recv();
+        }
+    }
+
+    /// refuse further appends and wait until the queued points are encoded, returns the count
+    /// of points which were queued
+    pub(crate) fn shutdown(&self) -> u64 {
+        self.shut_down.store(true, Ordering::Release);
+        let queued = self.stats().iter().map(|s| s.depth as u64).sum();
+        for queue in &self.queues {
+            queue.close();
+        }
+        // a worker takes the barrier before it sees its queue closed and drained
+        self.flush();
+        queued
+    }
+
+    pub(crate) fn stats(&self) -> Vec<QueueStats> {
+        self.queues
+            .iter()
+            .enumerate()
+            .map(|(shard, queue)| queue.stats(shard))
+            .collect()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // the workers end once their queue is drained
+        for queue in &self.queues {
+            queue.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ts::TS;
+    use crate::worker::{IngestOptions, Job, OverloadPolicy, Queue, WorkerPool};
+    use crate::Error;
+    use common::TimePrecision;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+    use tszv1::DataPoint;
+
+    #[test]
+    fn shards() {
+        let pool = WorkerPool::new(&IngestOptions {
+            shards: 4,
+            queue_len: 16,
+            ..IngestOptions::default()
+        });
+        let series: Vec<(String, TS)> = (0..20)
+            .map(|i| (format!("key-{}", i), TS::new(TimePrecision::Seconds)))
+            .collect();
+
+        let start = common::now_timestamp_secs();
+        for i in 0..100 {
+            for (key, ts) in &series {
+                pool.append(key, ts, DataPoint::new(start + i, i as f64))
+                    .unwrap();
+            }
+        }
+        pool.flush();
+
+        for (key, ts) in &series {
+            assert_eq!(pool.shard(key), pool.shard(&key.clone()));
+            let points = ts
+                .get_decoder(start, start + 100, 0, |decoder, dp_vec| {
+                    for dp in decoder {
+                        dp_vec.push(dp?);
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            let expected: Vec<DataPoint> = (0..100)
+                .map(|i| DataPoint::new(start + i, i as f64))
+                .collect();
+            assert_eq!(points, expected);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats.iter().map(|s| s.appended).sum::<u64>(), 2000);
+        assert!(stats.iter().all(|s| s.depth == 0 && s.rejected == 0));
+    }
+
+    fn value(job: Option<Job>) -> f64 {
+        match job {
+            Some(Job::Append(_, dp)) => dp.value.as_f64(),
+            _ => panic!("expected a point"),
+        }
+    }
+
+    #[test]
+    fn overload_policies() {
+        // a queue without a worker stays full
+        let ts = TS::new(TimePrecision::Seconds);
+        let retry = Duration::from_secs(3);
+        let queue = Queue::new(2);
+        for i in 0..2 {
+            let dp = DataPoint::new(i, i as f64);
+            queue
+                .push(0, &ts, dp, OverloadPolicy::Reject, retry)
+                .unwrap();
+        }
+
+        let overloaded = || Error::Overloaded("the queue of shard 0 is full".to_string(), retry);
+        let dp = DataPoint::new(2, 2.0);
+        let err = queue.push(0, &ts, dp.clone(), OverloadPolicy::Reject, retry);
+        assert_eq!(err, Err(overloaded()));
+
+        let begin = Instant::now();
+        let timeout = OverloadPolicy::Block(Duration::from_millis(50));
+        assert_eq!(
+            queue.push(0, &ts, dp.clone(), timeout, retry),
+            Err(overloaded())
+        );
+        assert!(begin.elapsed() >= Duration::from_millis(50));
+
+        queue
+            .push(0, &ts, dp, OverloadPolicy::DropOldest, retry)
+            .unwrap();
+        let stats = queue.stats(0);
+        assert_eq!((stats.depth, stats.dropped, stats.rejected), (2, 1, 2));
+        assert_eq!(value(queue.pop()), 1.0);
+        assert_eq!(value(queue.pop()), 2.0);
+
+        // a blocked append goes on once a point is taken
+        let timeout = OverloadPolicy::Block(Duration::from_secs(10));
+        for i in 0..2 {
+            queue
+                .push(0, &ts, DataPoint::new(i, i as f64), timeout, retry)
+                .unwrap();
+        }
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                queue.pop();
+            });
+            let dp = DataPoint::new(5, 5.0);
+            queue.push(0, &ts, dp, timeout, retry).unwrap();
+        });
+        assert_eq!(queue.stats(0).depth, 2);
+    }
+
+    #[test]
+    fn shutdown() {
+        let pool = WorkerPool::new(&IngestOptions {
+            shards: 2,
+            ..IngestOptions::default()
+        });
+        let ts = TS::new(TimePrecision::Seconds);
+        let start = common::now_timestamp_secs();
+        for i in 0..1000 {
+            pool.append("k", &ts, DataPoint::new(start + i, i as f64))
+                .unwrap();
+        }
+        assert!(pool.shutdown() <= 1000);
+
+        // the queued points are encoded, later ones refused
+        let points = ts
+            .get_decoder(start, start + 1000, 0, |decoder, dp_vec| {
+                for dp in decoder {
+                    dp_vec.push(dp?);
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(points.len(), 1000);
+        assert_eq!(
+            pool.append("k", &ts, DataPoint::new(start + 1000, 0.0)),
+            Err(Error::Unavailable("the engine is shut down".to_string()))
+        );
+    }
+
+    #[test]
+    fn stopped_worker() {
+        let pool = WorkerPool::new(&IngestOptions {
+            shards: 1,
+            ..IngestOptions::default()
+        });
+        pool.queues[0].close();
+        while pool.queues[0].alive.load(Ordering::Acquire) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // returns at once
+        pool.flush();
+
+        let ts = TS::new(TimePrecision::Seconds);
+        let err = pool.append("k", &ts, DataPoint::new(1, 1.0));
+        assert_eq!(
+            err,
+            Err(Error::Unavailable(
+                "the worker of shard 0 stopped".to_string()
+            ))
+        );
+    }
+}
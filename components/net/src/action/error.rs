@@ -0,0 +1,190 @@
+use hyper::{header, Body, Response, StatusCode};
+use serde_json::json;
+use std::fmt;
+
+/// ActionError
+///
+/// ActionError is the single error type returned by the http handlers, every variant maps to
+/// one http status code and is rendered as `{"code": "<status>", "msg": "<detail>"}`.
+#[derive(Debug)]
+pub enum ActionError {
+    /// The request body is not valid json or fails validation.
+    BadRequest(String),
+    /// The route or the requested resource does not exist.
+    NotFound(String),
+    /// The request body is larger than the configured limit, in bytes.
+    PayloadTooLarge(usize),
+    /// The server refuses the request for now, the client should retry after the given
+    /// seconds.
+    TooManyRequests(String, u64),
+    /// The server can not serve the request, it may recover after the given seconds.
+    Unavailable(String, u64),
+    /// Unexpected failure inside the server.
+    Internal(String),
+}
+
+/// RETRY_UNAVAILABLE_SECS is the `Retry-After` of the requests refused because the ingestion
+/// workers stopped
+pub const RETRY_UNAVAILABLE_SECS: u64 = 30;
+
+impl ActionError {
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            ActionError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ActionError::NotFound(_) => StatusCode::NOT_FOUND,
+            ActionError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ActionError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            ActionError::Unavailable(..) => StatusCode::SERVICE_UNAVAILABLE,
+            ActionError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// seconds after which the client may retry, sent as the `Retry-After` header
+    pub fn retry_after(&self) -> Option<u64> {
+        match *self {
+            ActionError::TooManyRequests(_, secs) | ActionError::Unavailable(_, secs) => Some(secs),
+            _ => None,
+        }
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        let body = json!({
+            "code": status.as_u16().to_string(),
+            "msg": self.to_string(),
+        });
+
+        let mut builder = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(secs) = self.retry_after() {
+            builder = builder.header(header::RETRY_AFTER, secs.to_string());
+        }
+        builder
+            .body(Body::from(body.to_string()))
+            .expect("build error response")
+    }
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ActionError::BadRequest(ref msg) => write!(f, "bad request: {}", msg),
+            ActionError::NotFound(ref msg) => write!(f, "not found: {}", msg),
+            ActionError::PayloadTooLarge(limit) => {
+                write!(f, "request body exceeds the limit of {} bytes", limit)
+            }
+            ActionError::TooManyRequests(ref msg, _) => write!(f, "too many requests: {}", msg),
+            ActionError::Unavailable(ref msg, _) => write!(f, "service unavailable: {}", msg),
+            ActionError::Internal(ref msg) => write!(f, "internal error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
+
+impl From<hyper::Error> for ActionError {
+    fn from(err: hyper::Error) -> Self {
+        ActionError::BadRequest(format!("failed to read request body: {}", err))
+    }
+}
+
+impl From<serde_json::Error> for ActionError {
+    fn from(err: serde_json::Error) -> Self {
+        ActionError::BadRequest(format!("invalid json: {}", err))
+    }
+}
+
+impl From<common::date_time::DateTimeError> for ActionError {
+    fn from(err: common::date_time::DateTimeError) -> Self {
+        ActionError::BadRequest(format!("invalid interval: {}", err))
+    }
+}
+
+impl From<engine::Error> for ActionError {
+    fn from(err: engine::Error) -> Self {
+        match err {
+            engine::Error::ValueRejected(_)
+            | engine::Error::TypeMismatch(_)
+            | engine::Error::InvalidQuery(_)
+            | engine::Error::TableConflict(_) => ActionError::BadRequest(err.to_string()),
+            engine::Error::Decode(_) | engine::Error::Storage(_) => {
+                ActionError::Internal(err.to_string())
+            }
+            engine::Error::Overloaded(_, retry_after) => {
+                // Retry-After is in whole seconds, round up
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                ActionError::TooManyRequests(err.to_string(), secs.max(1))
+            }
+            engine::Error::Unavailable(_) => {
+                ActionError::Unavailable(err.to_string(), RETRY_UNAVAILABLE_SECS)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::error::{ActionError, RETRY_UNAVAILABLE_SECS};
+    use hyper::{header, StatusCode};
+    use std::time::Duration;
+
+    #[test]
+    fn engine_errors() {
+        let cases = vec![
+            (
+                engine::Error::ValueRejected("NaN".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                engine::Error::TypeMismatch("bool".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                engine::Error::InvalidQuery("overflow".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                engine::Error::TableConflict("precision".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                engine::Error::Decode(tszv1::decode::Error::EndOfStream),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                engine::Error::Storage("disk full".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (err, status) in cases {
+            let err = ActionError::from(err);
+            assert_eq!(err.status(), status, "{}", err);
+            assert_eq!(err.retry_after(), None);
+        }
+
+        let err = ActionError::from(engine::Error::Unavailable("shut down".to_string()));
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.retry_after(), Some(RETRY_UNAVAILABLE_SECS));
+    }
+
+    #[test]
+    fn retry_after() {
+        // whole seconds, rounded up and at least one
+        let secs = |retry: Duration| {
+            let err = ActionError::from(engine::Error::Overloaded("full".to_string(), retry));
+            assert_eq!(err.status(), StatusCode::TOO_MANY_REQUESTS);
+            err.retry_after().unwrap()
+        };
+        assert_eq!(secs(Duration::from_secs(3)), 3);
+        assert_eq!(secs(Duration::from_millis(1200)), 2);
+        assert_eq!(secs(Duration::from_millis(300)), 1);
+        assert_eq!(secs(Duration::from_secs(0)), 1);
+
+        let response = ActionError::TooManyRequests("full".to_string(), 2).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let response = ActionError::BadRequest("no".to_string()).into_response();
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+}
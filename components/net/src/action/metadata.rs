@@ -1,31 +1,51 @@
-use bytes::buf::BufExt;
+use crate::action::model::{
+    ApiResponse, CacheStatsResponse, CreateTableRequest, JobStatsResponse, QueueStatsResponse,
+};
+use crate::action::{json_response, read_json, ActionError};
 use engine::Engine;
-use hyper::{header, Body, Request, Response, StatusCode};
-use serde_json::json;
+use hyper::{Body, Request, Response, StatusCode};
 use std::sync::Arc;
 
 pub async fn create_table(
     req: Request<Body>,
-    _ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
-) -> Result<Response<Body>, hyper::Error> {
-    let whole_body = hyper::body::aggregate(req).await?;
+    ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
+) -> Result<Response<Body>, ActionError> {
+    let request: CreateTableRequest = read_json(req).await?;
+    request.validate()?;
 
-    let data: serde_json::Value = serde_json::from_reader(whole_body.reader()).expect("");
-    let json_map = data.as_object().unwrap();
-    let _table_name = json_map.get("table_name").unwrap().as_str().unwrap();
+    ts_engine.create_table(request.to_options())?;
 
-    //    ts_engine.create_key(table_name.to_string());
+    json_response(StatusCode::OK, &ApiResponse::empty())
+}
+
+/// the state of the ingestion queues
+pub async fn stats(
+    ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
+) -> Result<Response<Body>, ActionError> {
+    let queues: Vec<QueueStatsResponse> = ts_engine
+        .queue_stats()
+        .iter()
+        .map(QueueStatsResponse::from)
+        .collect();
+    json_response(StatusCode::OK, &ApiResponse::ok(queues))
+}
 
-    let resp_json = json!({
-        "code": "200",
-        "msg": "ok",
-    });
+/// the runs of the maintenance jobs
+pub async fn job_stats(
+    ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
+) -> Result<Response<Body>, ActionError> {
+    let jobs: Vec<JobStatsResponse> = ts_engine
+        .job_stats()
+        .iter()
+        .map(JobStatsResponse::from)
+        .collect();
+    json_response(StatusCode::OK, &ApiResponse::ok(jobs))
+}
 
-    let json = resp_json.to_string(); // serde_json::to_string("ok").expect("");
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(json))
-        .expect("");
-    Ok(response)
+/// the closed blocks in memory and their hits and misses
+pub async fn cache_stats(
+    ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
+) -> Result<Response<Body>, ActionError> {
+    let stats = CacheStatsResponse::from(&ts_engine.cache_stats());
+    json_response(StatusCode::OK, &ApiResponse::ok(stats))
 }
@@ -1,6 +1,60 @@
+pub mod error;
 pub mod metadata;
+pub mod model;
 pub mod tsdb;
 
+pub use error::ActionError;
 pub use metadata::create_table;
+pub use metadata::{cache_stats, job_stats, stats};
 pub use tsdb::append;
 pub use tsdb::search;
+pub use tsdb::{delete_range, delete_series};
+
+use bytes::BytesMut;
+use hyper::body::HttpBody;
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// max size of a request body in bytes, larger bodies are answered with 413
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// read the whole request body, bounded by `MAX_BODY_BYTES`, and parse it as json
+pub(crate) async fn read_json<T: DeserializeOwned>(req: Request<Body>) -> Result<T, ActionError> {
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if let Some(len) = content_length {
+        if len > MAX_BODY_BYTES {
+            return Err(ActionError::PayloadTooLarge(MAX_BODY_BYTES));
+        }
+    }
+
+    let mut body = req.into_body();
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(ActionError::PayloadTooLarge(MAX_BODY_BYTES));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// render `value` as a json response with the given status
+pub(crate) fn json_response<T: Serialize>(
+    status: StatusCode,
+    value: &T,
+) -> Result<Response<Body>, ActionError> {
+    let json = serde_json::to_string(value)
+        .map_err(|err| ActionError::Internal(format!("failed to serialize response: {}", err)))?;
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .map_err(|err| ActionError::Internal(format!("failed to build response: {}", err)))
+}
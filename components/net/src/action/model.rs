@@ -0,0 +1,803 @@
+use crate::action::error::ActionError;
+use common::TimePrecision;
+use engine::rollup::TIER_KEY_SEP;
+use engine::{
+    Aggregation, CacheStats, CodecId, JobStats, QueueStats, RollupRule, SeriesMatcher,
+    TableOptions, ValueAction,
+};
+use serde::Serialize;
+use std::time::Duration;
+use tszv1::{DDSketch, Value, ValueType};
+
+/// how a table treats a class of values, see `engine::ValuePolicy`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueActionParam {
+    Store,
+    Reject,
+    Drop,
+}
+
+impl From<ValueActionParam> for ValueAction {
+    fn from(param: ValueActionParam) -> Self {
+        match param {
+            ValueActionParam::Store => ValueAction::Store,
+            ValueActionParam::Reject => ValueAction::Reject,
+            ValueActionParam::Drop => ValueAction::Drop,
+        }
+    }
+}
+
+/// a rollup tier of a table, see `engine::RollupRule`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RollupParam {
+    /// seconds, a divisor of 7200
+    pub interval: u64,
+    /// seconds the rollups are kept, omitted keeps them forever
+    #[serde(default)]
+    pub retention: Option<u64>,
+}
+
+/// body of `POST /table`, omitted policies keep the engine defaults
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateTableRequest {
+    pub table_name: String,
+    #[serde(default)]
+    pub negative_values: Option<ValueActionParam>,
+    #[serde(default)]
+    pub nan_values: Option<ValueActionParam>,
+    #[serde(default)]
+    pub infinite_values: Option<ValueActionParam>,
+    /// timestamp precision of the table: s, ms, us or ns, defaults to s
+    #[serde(default)]
+    pub precision: Option<String>,
+    /// value codec of the table: gorilla, chimp, chimp128, decimal, integer, bool, string or
+    /// sketch, defaults to gorilla. The integer, bool, string and sketch codecs make a table of
+    /// their value type, the others a float table.
+    #[serde(default)]
+    pub codec: Option<String>,
+    /// the rollup tiers of the numeric series of the table, searches of count, sum, min, max,
+    /// avg and last read the coarsest tier which fits their step
+    #[serde(default)]
+    pub rollups: Vec<RollupParam>,
+}
+
+impl CreateTableRequest {
+    pub fn validate(&self) -> Result<(), ActionError> {
+        require_non_empty("table_name", &self.table_name)?;
+        if let Some(ref precision) = self.precision {
+            if TimePrecision::from_name(precision).is_none() {
+                return Err(ActionError::BadRequest(format!(
+                    "field `precision` must be one of s, ms, us, ns, got {:?}",
+                    precision
+                )));
+            }
+        }
+        if let Some(ref codec) = self.codec {
+            if CodecId::from_name(codec).is_none() {
+                return Err(ActionError::BadRequest(format!(
+                    "field `codec` must be one of gorilla, chimp, chimp128, decimal, integer, bool, string, sketch, got {:?}",
+                    codec
+                )));
+            }
+        }
+        if !self.rollups.is_empty() && !self.to_options().value_type().is_numeric() {
+            return Err(ActionError::BadRequest(
+                "field `rollups` requires a numeric codec".to_string(),
+            ));
+        }
+        for (i, rollup) in self.rollups.iter().enumerate() {
+            if !RollupRule::new(rollup.interval, None).fits_blocks() {
+                return Err(ActionError::BadRequest(format!(
+                    "field `rollups` interval must divide 7200 seconds, got {}",
+                    rollup.interval
+                )));
+            }
+            if rollup.retention == Some(0) {
+                return Err(ActionError::BadRequest(
+                    "field `rollups` retention must be positive".to_string(),
+                ));
+            }
+            if self.rollups[..i]
+                .iter()
+                .any(|r| r.interval == rollup.interval)
+            {
+                return Err(ActionError::BadRequest(format!(
+                    "field `rollups` has interval {} twice",
+                    rollup.interval
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// the table options, `validate` must have passed
+    pub fn to_options(&self) -> TableOptions {
+        let mut options = TableOptions::new(&self.table_name);
+        if let Some(precision) = self
+            .precision
+            .as_ref()
+            .and_then(|p| TimePrecision::from_name(p))
+        {
+            options.precision = precision;
+        }
+        if let Some(codec) = self.codec.as_ref().and_then(|c| CodecId::from_name(c)) {
+            options.codec = codec;
+        }
+        if let Some(action) = self.negative_values {
+            options.value_policy.negative = action.into();
+        }
+        if let Some(action) = self.nan_values {
+            options.value_policy.nan = action.into();
+        }
+        if let Some(action) = self.infinite_values {
+            options.value_policy.infinite = action.into();
+        }
+        options.rollups = self
+            .rollups
+            .iter()
+            .map(|r| RollupRule::new(r.interval, r.retention.map(Duration::from_secs)))
+            .collect();
+        options
+    }
+}
+
+/// body of `POST /search`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchRequest {
+    pub table_name: String,
+    pub key: String,
+    /// `<from>/<to>` formatted as `%Y-%m-%dT%T%z`
+    pub interval: String,
+    /// more keys of the table whose data points are aggregated together with those of `key`,
+    /// requires `function`
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// max count of data points to return, 0 means no limit
+    #[serde(default)]
+    pub limit: usize,
+    /// aggregate the data points: count, sum, min, max, avg, first, last, merge or a quantile
+    /// named after its percentile as p99
+    #[serde(default)]
+    pub function: Option<String>,
+    /// width of the aggregation buckets in seconds, 0 or omitted aggregates the whole interval
+    #[serde(default)]
+    pub step: u64,
+}
+
+impl SearchRequest {
+    pub fn validate(&self) -> Result<(), ActionError> {
+        require_non_empty("table_name", &self.table_name)?;
+        require_key("key", &self.key)?;
+        require_non_empty("interval", &self.interval)?;
+        if let Some(ref function) = self.function {
+            if Aggregation::from_name(function).is_none() {
+                return Err(ActionError::BadRequest(format!(
+                    "field `function` must be one of count, sum, min, max, avg, first, last, merge, p0 to p100, got {:?}",
+                    function
+                )));
+            }
+        } else if self.step > 0 {
+            return Err(ActionError::BadRequest(
+                "field `step` requires `function`".to_string(),
+            ));
+        } else if !self.keys.is_empty() {
+            return Err(ActionError::BadRequest(
+                "field `keys` requires `function`".to_string(),
+            ));
+        }
+        for key in &self.keys {
+            require_key("keys", key)?;
+        }
+        Ok(())
+    }
+
+    /// the aggregation to apply, `validate` must have passed
+    pub fn aggregation(&self) -> Option<Aggregation> {
+        self.function
+            .as_ref()
+            .and_then(|f| Aggregation::from_name(f))
+    }
+}
+
+/// longest string value accepted by a string table, in bytes
+pub const MAX_STRING_VALUE_LEN: usize = 1024;
+
+/// most samples accepted in the value of a sketch table
+pub const MAX_SKETCH_SAMPLES: usize = 100_000;
+
+/// a value in an append request, json has no literal for NaN and infinity so they are sent as
+/// the strings `"NaN"`, `"Infinity"` and `"-Infinity"`. Numbers without a fraction are read as
+/// integers so that integer tables keep all 64 bits. Strings are values of their own for string
+/// tables. Sketch tables take a sketch, or a number or an array of numbers which are counted
+/// into a new sketch.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ValueParam {
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    Text(String),
+    Samples(Vec<f64>),
+    Sketch(Box<DDSketch>),
+}
+
+impl ValueParam {
+    /// the value for a table of `value_type`
+    pub fn to_value(&self, value_type: ValueType) -> Result<Value, ActionError> {
+        match (value_type, self) {
+            (ValueType::Integer, ValueParam::Integer(v)) => Ok(Value::Integer(*v)),
+            (ValueType::Integer, _) => Err(ActionError::BadRequest(
+                "field `value` must be an integer for an integer table".to_string(),
+            )),
+            (ValueType::Bool, ValueParam::Bool(v)) => Ok(Value::Bool(*v)),
+            (ValueType::Bool, _) => Err(ActionError::BadRequest(
+                "field `value` must be true or false for a bool table".to_string(),
+            )),
+            (ValueType::String, ValueParam::Text(text)) => {
+                if text.len() > MAX_STRING_VALUE_LEN {
+                    return Err(ActionError::BadRequest(format!(
+                        "field `value` must be at most {} bytes, got {}",
+                        MAX_STRING_VALUE_LEN,
+                        text.len()
+                    )));
+                }
+                Ok(Value::String(text.clone()))
+            }
+            (ValueType::String, _) => Err(ActionError::BadRequest(
+                "field `value` must be a string for a string table".to_string(),
+            )),
+            (ValueType::Sketch, ValueParam::Sketch(sketch)) => {
+                if !sketch.is_consistent() {
+                    return Err(ActionError::BadRequest(
+                        "field `value` is not a valid sketch".to_string(),
+                    ));
+                }
+                Ok(Value::Sketch(sketch.clone()))
+            }
+            (ValueType::Sketch, ValueParam::Samples(samples)) => {
+                if samples.len() > MAX_SKETCH_SAMPLES {
+                    return Err(ActionError::BadRequest(format!(
+                        "field `value` must have at most {} samples, got {}",
+                        MAX_SKETCH_SAMPLES,
+                        samples.len()
+                    )));
+                }
+                DDSketch::from_values(samples)
+                    .map(Value::from)
+                    .map_err(|e| ActionError::BadRequest(format!("field `value`: {}", e)))
+            }
+            (ValueType::Sketch, ValueParam::Integer(_)) | (ValueType::Sketch, ValueParam::Number(_)) => {
+                DDSketch::from_values(&[self.to_f64()?])
+                    .map(Value::from)
+                    .map_err(|e| ActionError::BadRequest(format!("field `value`: {}", e)))
+            }
+            (ValueType::Sketch, _) => Err(ActionError::BadRequest(
+                "field `value` must be a sketch, a number or an array of numbers for a sketch table"
+                    .to_string(),
+            )),
+            (ValueType::Float, _) => self.to_f64().map(Value::Float),
+        }
+    }
+
+    pub fn to_f64(&self) -> Result<f64, ActionError> {
+        match self {
+            ValueParam::Integer(v) => Ok(*v as f64),
+            ValueParam::Number(v) => Ok(*v),
+            ValueParam::Bool(_) | ValueParam::Samples(_) | ValueParam::Sketch(_) => {
+                Err(ActionError::BadRequest(
+                    "field `value` must be a number for a float table".to_string(),
+                ))
+            }
+            ValueParam::Text(text) => match text.as_str() {
+                "NaN" => Ok(f64::NAN),
+                "Infinity" | "+Infinity" => Ok(f64::INFINITY),
+                "-Infinity" => Ok(f64::NEG_INFINITY),
+                _ => Err(ActionError::BadRequest(format!(
+                    "field `value` must be a number, \"NaN\", \"Infinity\" or \"-Infinity\", got {:?}",
+                    text
+                ))),
+            },
+        }
+    }
+}
+
+/// body of `POST /append`
+///
+/// `timestamp` is in the precision of the table. It is required unless `server_timestamp` is
+/// set, in which case the server stamps the data point with its own clock and `timestamp` must
+/// be omitted.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppendRequest {
+    pub table_name: String,
+    pub key: String,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub server_timestamp: bool,
+    pub value: ValueParam,
+}
+
+impl AppendRequest {
+    pub fn validate(&self) -> Result<(), ActionError> {
+        require_non_empty("table_name", &self.table_name)?;
+        require_key("key", &self.key)?;
+        match (self.timestamp, self.server_timestamp) {
+            (Some(_), true) => Err(ActionError::BadRequest(
+                "field `timestamp` must be omitted when `server_timestamp` is true".to_string(),
+            )),
+            (None, false) => Err(ActionError::BadRequest(
+                "field `timestamp` is required unless `server_timestamp` is true".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// the timestamp to store, `validate` must have passed
+    pub fn timestamp(&self, precision: TimePrecision) -> u64 {
+        match self.timestamp {
+            Some(timestamp) => timestamp,
+            None => precision.now(),
+        }
+    }
+}
+
+/// body of `POST /delete/series` and `POST /delete/range`
+///
+/// The series of the table are selected by their keys and by a key prefix, at least one of them
+/// is required. Series of the same keys in other tables are left alone.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteRequest {
+    pub table_name: String,
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// every series whose key starts with the prefix
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+    /// `<from>/<to>` formatted as `%Y-%m-%dT%T%z`, the points in [from, to) are deleted.
+    /// Required by `/delete/range`, refused by `/delete/series`.
+    #[serde(default)]
+    pub interval: Option<String>,
+}
+
+impl DeleteRequest {
+    /// `range`: whether the request deletes a time range rather than whole series
+    pub fn validate(&self, range: bool) -> Result<(), ActionError> {
+        require_non_empty("table_name", &self.table_name)?;
+        for key in &self.keys {
+            require_key("keys", key)?;
+        }
+        if let Some(ref prefix) = self.key_prefix {
+            require_key("key_prefix", prefix)?;
+        } else if self.keys.is_empty() {
+            return Err(ActionError::BadRequest(
+                "field `keys` or `key_prefix` is required".to_string(),
+            ));
+        }
+        match (&self.interval, range) {
+            (Some(interval), true) => require_non_empty("interval", interval),
+            (None, true) => Err(ActionError::BadRequest(
+                "field `interval` is required".to_string(),
+            )),
+            (Some(_), false) => Err(ActionError::BadRequest(
+                "field `interval` is not allowed, use /delete/range".to_string(),
+            )),
+            (None, false) => Ok(()),
+        }
+    }
+
+    pub fn matchers(&self) -> Vec<SeriesMatcher> {
+        self.keys
+            .iter()
+            .map(|key| SeriesMatcher::Key(key.clone()))
+            .chain(
+                self.key_prefix
+                    .iter()
+                    .map(|p| SeriesMatcher::Prefix(p.clone())),
+            )
+            .collect()
+    }
+}
+
+/// DeleteResponse
+///
+/// The count of series a delete request selected.
+#[derive(Debug, Serialize)]
+pub struct DeleteResponse {
+    pub deleted: usize,
+}
+
+/// QueueStatsResponse
+///
+/// The state of the ingestion queue of a shard, see `engine::QueueStats`.
+#[derive(Debug, Serialize)]
+pub struct QueueStatsResponse {
+    pub shard: usize,
+    pub depth: usize,
+    pub capacity: usize,
+    pub appended: u64,
+    pub dropped: u64,
+    pub rejected: u64,
+}
+
+impl From<&QueueStats> for QueueStatsResponse {
+    fn from(stats: &QueueStats) -> Self {
+        QueueStatsResponse {
+            shard: stats.shard,
+            depth: stats.depth,
+            capacity: stats.capacity,
+            appended: stats.appended,
+            dropped: stats.dropped,
+            rejected: stats.rejected,
+        }
+    }
+}
+
+/// JobStatsResponse
+///
+/// The runs of a maintenance job, see `engine::JobStats`. Durations are in milliseconds.
+#[derive(Debug, Serialize)]
+pub struct JobStatsResponse {
+    pub name: String,
+    pub runs: u64,
+    pub skipped: u64,
+    pub last_millis: u64,
+    pub max_millis: u64,
+    pub total_millis: u64,
+}
+
+impl From<&JobStats> for JobStatsResponse {
+    fn from(stats: &JobStats) -> Self {
+        JobStatsResponse {
+            name: stats.name.clone(),
+            runs: stats.runs,
+            skipped: stats.skipped,
+            last_millis: stats.last_duration.as_millis() as u64,
+            max_millis: stats.max_duration.as_millis() as u64,
+            total_millis: stats.total_duration.as_millis() as u64,
+        }
+    }
+}
+
+/// CacheStatsResponse
+///
+/// The closed blocks in memory, see `engine::CacheStats`. Sizes are in bytes, the budget is
+/// omitted when every block is kept in memory.
+#[derive(Debug, Serialize)]
+pub struct CacheStatsResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<u64>,
+    pub resident: u64,
+    pub blocks: usize,
+    pub evictable: usize,
+    pub mapped: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl From<&CacheStats> for CacheStatsResponse {
+    fn from(stats: &CacheStats) -> Self {
+        CacheStatsResponse {
+            budget: stats.budget,
+            resident: stats.resident,
+            blocks: stats.blocks,
+            evictable: stats.evictable,
+            mapped: stats.mapped,
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+        }
+    }
+}
+
+/// ApiResponse
+///
+/// The success envelope shared by all handlers, errors are rendered by `ActionError`.
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T: Serialize> {
+    pub code: String,
+    pub msg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn ok(data: T) -> Self {
+        ApiResponse {
+            code: "200".to_string(),
+            msg: "ok".to_string(),
+            data: Some(data),
+        }
+    }
+}
+
+impl ApiResponse<()> {
+    pub fn empty() -> Self {
+        ApiResponse::msg("ok")
+    }
+
+    pub fn msg(msg: &str) -> Self {
+        ApiResponse {
+            code: "200".to_string(),
+            msg: msg.to_string(),
+            data: None,
+        }
+    }
+}
+
+fn require_non_empty(field: &str, value: &str) -> Result<(), ActionError> {
+    if value.trim().is_empty() {
+        Err(ActionError::BadRequest(format!(
+            "field `{}` must not be empty",
+            field
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// the keys of series are not empty and do not contain the separator of the keys of rollups
+fn require_key(field: &str, value: &str) -> Result<(), ActionError> {
+    require_non_empty(field, value)?;
+    if value.contains(TIER_KEY_SEP) {
+        return Err(ActionError::BadRequest(format!(
+            "field `{}` must not contain the character U+001F",
+            field
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::action::model::{
+        AppendRequest, CreateTableRequest, DeleteRequest, SearchRequest, ValueParam,
+        MAX_STRING_VALUE_LEN,
+    };
+    use crate::action::ActionError;
+    use common::TimePrecision;
+    use engine::{CodecId, RollupRule, SeriesMatcher, ValueAction};
+    use std::time::Duration;
+    use tszv1::{Value, ValueType};
+
+    fn bad_request<T: std::fmt::Debug>(result: Result<T, ActionError>) -> String {
+        match result {
+            Err(ActionError::BadRequest(msg)) => msg,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn create_table(json: &str) -> CreateTableRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn create_table_request() {
+        let request = create_table(
+            r#"{"table_name": "t", "precision": "ms", "codec": "integer", "nan_values": "drop",
+                "rollups": [{"interval": 300, "retention": 86400}, {"interval": 3600}]}"#,
+        );
+        request.validate().unwrap();
+        let options = request.to_options();
+        assert_eq!(options.precision, TimePrecision::Millis);
+        assert_eq!(options.codec, CodecId::Integer);
+        assert_eq!(options.value_policy.nan, ValueAction::Drop);
+        assert_eq!(options.value_policy.negative, ValueAction::Store);
+        assert_eq!(
+            options.rollups,
+            vec![
+                RollupRule::new(300, Some(Duration::from_secs(86400))),
+                RollupRule::new(3600, None)
+            ]
+        );
+
+        let invalid = [
+            r#"{"table_name": " "}"#,
+            r#"{"table_name": "t", "precision": "m"}"#,
+            r#"{"table_name": "t", "codec": "zstd"}"#,
+            r#"{"table_name": "t", "codec": "string", "rollups": [{"interval": 300}]}"#,
+            r#"{"table_name": "t", "rollups": [{"interval": 7}]}"#,
+            r#"{"table_name": "t", "rollups": [{"interval": 0}]}"#,
+            r#"{"table_name": "t", "rollups": [{"interval": 300, "retention": 0}]}"#,
+            r#"{"table_name": "t", "rollups": [{"interval": 300}, {"interval": 300}]}"#,
+        ];
+        for json in invalid.iter() {
+            bad_request(create_table(json).validate());
+        }
+        assert!(
+            serde_json::from_str::<CreateTableRequest>(r#"{"table_name": "t", "ttl": 1}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn float_values() {
+        let value = |json: &str| serde_json::from_str::<ValueParam>(json).unwrap();
+        let float = |json: &str| value(json).to_value(ValueType::Float);
+
+        assert_eq!(float("1.5").unwrap(), Value::Float(1.5));
+        assert_eq!(float("-3").unwrap(), Value::Float(-3.0));
+        assert!(float(r#""NaN""#).unwrap().as_f64().is_nan());
+        assert_eq!(float(r#""Infinity""#).unwrap(), Value::Float(f64::INFINITY));
+        assert_eq!(
+            float(r#""+Infinity""#).unwrap(),
+            Value::Float(f64::INFINITY)
+        );
+        assert_eq!(
+            float(r#""-Infinity""#).unwrap(),
+            Value::Float(f64::NEG_INFINITY)
+        );
+        bad_request(float(r#""nan""#));
+        bad_request(float("true"));
+        bad_request(float("[1.0, 2.0]"));
+    }
+
+    #[test]
+    fn typed_values() {
+        let value = |json: &str| serde_json::from_str::<ValueParam>(json).unwrap();
+
+        // integers keep all 64 bits
+        assert_eq!(
+            value("9007199254740993")
+                .to_value(ValueType::Integer)
+                .unwrap(),
+            Value::Integer(9_007_199_254_740_993)
+        );
+        bad_request(value("1.5").to_value(ValueType::Integer));
+        bad_request(value(r#""NaN""#).to_value(ValueType::Integer));
+
+        assert_eq!(
+            value("true").to_value(ValueType::Bool).unwrap(),
+            Value::Bool(true)
+        );
+        bad_request(value("1").to_value(ValueType::Bool));
+
+        assert_eq!(
+            value(r#""up""#).to_value(ValueType::String).unwrap(),
+            Value::String("up".to_string())
+        );
+        let long = format!("{:?}", "x".repeat(MAX_STRING_VALUE_LEN + 1));
+        bad_request(value(&long).to_value(ValueType::String));
+        bad_request(value("1").to_value(ValueType::String));
+
+        for json in ["[1.0, 2.0, 3.0]", "4", "2.5"].iter() {
+            match value(json).to_value(ValueType::Sketch).unwrap() {
+                Value::Sketch(_) => {}
+                other => panic!("unexpected value {:?}", other),
+            }
+        }
+        bad_request(value(r#""up""#).to_value(ValueType::Sketch));
+    }
+
+    #[test]
+    fn search_request() {
+        let search = |json: &str| serde_json::from_str::<SearchRequest>(json).unwrap();
+        let interval = r#""interval": "2020-01-01T00:00:00+0000/2020-01-02T00:00:00+0000""#;
+
+        let request = search(&format!(
+            r#"{{"table_name": "t", "key": "k", {}, "function": "p99", "step": 60}}"#,
+            interval
+        ));
+        request.validate().unwrap();
+        assert!(request.aggregation().is_some());
+
+        let invalid = [
+            format!(r#"{{"table_name": "t", "key": "", {}}}"#, interval),
+            format!(
+                r#"{{"table_name": "t", "key": "k", {}, "step": 60}}"#,
+                interval
+            ),
+            format!(
+                r#"{{"table_name": "t", "key": "k", {}, "keys": ["a"]}}"#,
+                interval
+            ),
+            format!(
+                r#"{{"table_name": "t", "key": "k", {}, "function": "median"}}"#,
+                interval
+            ),
+            format!(
+                r#"{{"table_name": "t", "key": "k", {}, "function": "sum", "keys": [""]}}"#,
+                interval
+            ),
+        ];
+        for json in invalid.iter() {
+            bad_request(search(json).validate());
+        }
+    }
+
+    #[test]
+    fn append_request() {
+        let append = |json: &str| serde_json::from_str::<AppendRequest>(json).unwrap();
+
+        let request = append(r#"{"table_name": "t", "key": "k", "timestamp": 10, "value": 1}"#);
+        request.validate().unwrap();
+        assert_eq!(request.timestamp(TimePrecision::Millis), 10);
+
+        let request =
+            append(r#"{"table_name": "t", "key": "k", "server_timestamp": true, "value": 1}"#);
+        request.validate().unwrap();
+        let now = TimePrecision::Millis.now();
+        assert!(request.timestamp(TimePrecision::Millis) >= now - 1000);
+
+        bad_request(
+            append(r#"{"table_name": "t", "key": " ", "timestamp": 10, "value": 1}"#).validate(),
+        );
+        bad_request(
+            append(r#"{"table_name": "t", "key": "k", "timestamp": 10, "server_timestamp": true, "value": 1}"#)
+                .validate(),
+        );
+        bad_request(append(r#"{"table_name": "t", "key": "k", "value": 1}"#).validate());
+    }
+
+    #[test]
+    fn delete_request() {
+        let delete = |json: &str| serde_json::from_str::<DeleteRequest>(json).unwrap();
+        let interval = r#""interval": "2020-01-01T00:00:00+0000/2020-01-02T00:00:00+0000""#;
+
+        let request = delete(r#"{"table_name": "t", "keys": ["a"], "key_prefix": "cpu."}"#);
+        request.validate(false).unwrap();
+        assert_eq!(
+            request.matchers(),
+            vec![
+                SeriesMatcher::Key("a".to_string()),
+                SeriesMatcher::Prefix("cpu.".to_string())
+            ]
+        );
+        delete(&format!(
+            r#"{{"table_name": "t", "keys": ["a"], {}}}"#,
+            interval
+        ))
+        .validate(true)
+        .unwrap();
+
+        let msg = bad_request(delete(r#"{"table_name": "t"}"#).validate(false));
+        assert!(msg.contains("`keys` or `key_prefix`"));
+        bad_request(delete(r#"{"table_name": "", "keys": ["a"]}"#).validate(false));
+        bad_request(delete(r#"{"table_name": "t", "keys": [""]}"#).validate(false));
+        bad_request(delete(r#"{"table_name": "t", "key_prefix": " "}"#).validate(false));
+        // the range is required by /delete/range and refused by /delete/series
+        bad_request(delete(r#"{"table_name": "t", "keys": ["a"]}"#).validate(true));
+        bad_request(
+            delete(&format!(
+                r#"{{"table_name": "t", "keys": ["a"], {}}}"#,
+                interval
+            ))
+            .validate(false),
+        );
+    }
+
+    #[test]
+    fn rollup_key_separator() {
+        // the keys of rollups are made of the key of the series and the separator
+        let search = r#"{"table_name": "t", "key": "k\u001frollup", "interval": "i"}"#;
+        let msg = bad_request(
+            serde_json::from_str::<SearchRequest>(search)
+                .unwrap()
+                .validate(),
+        );
+        assert!(msg.contains("U+001F"));
+        let append = r#"{"table_name": "t", "key": "k\u001f", "timestamp": 10, "value": 1}"#;
+        bad_request(
+            serde_json::from_str::<AppendRequest>(append)
+                .unwrap()
+                .validate(),
+        );
+        for delete in [
+            r#"{"table_name": "t", "keys": ["k\u001f"]}"#,
+            r#"{"table_name": "t", "key_prefix": "k\u001f"}"#,
+        ]
+        .iter()
+        {
+            bad_request(
+                serde_json::from_str::<DeleteRequest>(delete)
+                    .unwrap()
+                    .validate(false),
+            );
+        }
+    }
+}
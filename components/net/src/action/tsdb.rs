@@ -1,109 +1,128 @@
-use bytes::buf::BufExt;
-use engine::{Engine, Raw};
-use hyper::{header, Body, Request, Response, StatusCode};
-use serde_json::json;
-use std::borrow::Borrow;
+use crate::action::model::{
+    ApiResponse, AppendRequest, DeleteRequest, DeleteResponse, SearchRequest,
+};
+use crate::action::{json_response, read_json, ActionError};
+use engine::{query, Appended, Engine, Raw};
+use hyper::{Body, Request, Response, StatusCode};
 use std::sync::Arc;
 use tszv1::{DataPoint, Decode};
 
 pub async fn search(
     req: Request<Body>,
     ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
-) -> Result<Response<Body>, hyper::Error> {
-    let whole_body = hyper::body::aggregate(req).await?;
+) -> Result<Response<Body>, ActionError> {
+    let request: SearchRequest = read_json(req).await?;
+    request.validate()?;
 
-    let data: serde_json::Value = serde_json::from_reader(whole_body.reader()).expect("");
-    let json_map = data.as_object().unwrap();
-    let table_name = json_map.get("table_name").unwrap().as_str().unwrap();
-    let key = json_map.get("key").unwrap().as_str().unwrap();
-    let interval = json_map.get("interval").unwrap().as_str().unwrap();
-    let limit = match json_map.get("limit") {
-        Some(v) => v.as_i64().unwrap() as usize,
-        None => 0,
-    };
+    let (from, to) = common::string_to_date_times(request.interval.as_str())?;
+    if from > to {
+        return Err(ActionError::BadRequest(format!(
+            "interval `{}` ends before it begins",
+            request.interval
+        )));
+    }
 
-    let resp_json = match common::string_to_date_times(interval) {
-        Ok((from, to)) => {
-            let resp_data =
-                match ts_engine.get(table_name.to_string().borrow(), key.to_string().borrow()) {
-                    Some(ts) => {
-                        let from = from.timestamp() as u64;
-                        let to = to.timestamp() as u64;
+    // the series of a table share its precision
+    let precision = ts_engine.table_options(&request.table_name).precision;
+    let from = precision.from_date_time(&from);
+    let to = precision.from_date_time(&to);
 
-                        let list = ts.get_decoder(from, to, limit, |mut decoder, dp_vec| {
-                            loop {
-                                match decoder.next() {
-                                    Ok(dp) => {
-                                        dp_vec.push(dp);
-                                        //                                    info!("reader => {}, {}", dp.time, dp.value);
-                                    }
-                                    Err(_) => {
-                                        break;
-                                    }
-                                }
-                            }
-                            // list
-                        });
-                        list
+    let series = std::iter::once(&request.key)
+        .chain(request.keys.iter())
+        .filter_map(|key| ts_engine.get(&request.table_name, key));
+    let resp_data = match request.aggregation() {
+        Some(aggregation) => {
+            // the limit applies to the aggregated points, which may come from a rollup tier
+            let series: Vec<_> = series.collect();
+            let step = precision.from_secs(request.step);
+            let mut dp_vec = query::aggregate_series(&series, aggregation, from, to, step)?;
+            if request.limit > 0 {
+                dp_vec.truncate(request.limit);
+            }
+            dp_vec
+        }
+        None => {
+            let mut dp_vec = Vec::new();
+            for ts in series {
+                dp_vec.extend(ts.get_decoder(from, to, request.limit, |decoder, dp_vec| {
+                    for dp in decoder.points().range(from, to) {
+                        dp_vec.push(dp?);
                     }
-                    None => Vec::new(),
-                };
-            json!({
-                "code": "200",
-                "msg": "",
-                "data": resp_data,
-            })
+                    Ok(())
+                })?);
+            }
+            dp_vec
         }
-        Err(err) => json!({
-            "code": "500",
-            "msg": err.description(),
-        }),
     };
 
-    let json = resp_json.to_string(); // serde_json::to_string("ok").expect("");
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(json))
-        .expect("");
-    Ok(response)
+    json_response(StatusCode::OK, &ApiResponse::ok(resp_data))
 }
 
 pub async fn append(
     req: Request<Body>,
     ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
-) -> Result<Response<Body>, hyper::Error> {
-    let whole_body = hyper::body::aggregate(req).await?;
+) -> Result<Response<Body>, ActionError> {
+    let request: AppendRequest = read_json(req).await?;
+    request.validate()?;
 
-    let data: serde_json::Value = serde_json::from_reader(whole_body.reader()).expect("");
-    let json_map = data.as_object().unwrap();
-    let table_name = json_map.get("table_name").unwrap().as_str().unwrap();
-    let key = json_map.get("key").unwrap().as_str().unwrap();
-    let mut timestamp = json_map.get("timestamp").unwrap().as_u64().unwrap();
-    let mut value = json_map.get("value").unwrap().as_f64().unwrap();
+    let options = ts_engine.table_options(&request.table_name);
+    let data_point = DataPoint {
+        time: request.timestamp(options.precision),
+        value: request.value.to_value(options.value_type())?,
+    };
 
-    if timestamp == 0 {
-        timestamp = common::now_timestamp_secs();
-    }
-    if value < 0f64 {
-        value = (timestamp % 100) as f64;
+    let raw = Raw {
+        table_name: request.table_name,
+        key: request.key,
+        data_point,
+    };
+    // a full queue may block the append up to the timeout of the overload policy, let the
+    // runtime move its other tasks off this thread meanwhile
+    let appended = tokio::task::block_in_place(|| ts_engine.append(raw))?;
+
+    match appended {
+        Appended::Stored => json_response(StatusCode::OK, &ApiResponse::empty()),
+        Appended::Dropped => json_response(StatusCode::OK, &ApiResponse::msg("dropped")),
     }
+}
 
-    ts_engine.append(Raw {
-        table_name: String::from(table_name),
-        key: String::from(key),
-        data_point: DataPoint::new(timestamp, value),
-    });
+/// delete whole series
+pub async fn delete_series(
+    req: Request<Body>,
+    ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
+) -> Result<Response<Body>, ActionError> {
+    let request: DeleteRequest = read_json(req).await?;
+    request.validate(false)?;
+
+    let matchers = request.matchers();
+    let deleted =
+        tokio::task::block_in_place(|| ts_engine.delete_series(&request.table_name, &matchers))?;
+    json_response(StatusCode::OK, &ApiResponse::ok(DeleteResponse { deleted }))
+}
+
+/// delete a time range of series, searches skip the points at once
+pub async fn delete_range(
+    req: Request<Body>,
+    ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
+) -> Result<Response<Body>, ActionError> {
+    let request: DeleteRequest = read_json(req).await?;
+    request.validate(true)?;
+
+    let interval = request.interval.as_deref().unwrap_or_default();
+    let (from, to) = common::string_to_date_times(interval)?;
+    if from > to {
+        return Err(ActionError::BadRequest(format!(
+            "interval `{}` ends before it begins",
+            interval
+        )));
+    }
+    let precision = ts_engine.table_options(&request.table_name).precision;
+    let from = precision.from_date_time(&from);
+    let to = precision.from_date_time(&to);
 
-    let resp_json = json!({
-        "code": "200",
-        "msg": "ok",
-    });
-    let json = resp_json.to_string();
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(json))
-        .expect("");
-    Ok(response)
+    let matchers = request.matchers();
+    let deleted = tokio::task::block_in_place(|| {
+        ts_engine.delete_range(&request.table_name, &matchers, from, to)
+    })?;
+    json_response(StatusCode::OK, &ApiResponse::ok(DeleteResponse { deleted }))
 }
@@ -6,15 +6,18 @@ extern crate log4rs;
 extern crate bytes;
 extern crate futures;
 extern crate serde;
+#[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate tokio;
 
 mod action;
 
+pub use crate::action::ActionError;
+
 use engine::Engine;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::{Body, Method, Request, Response, Server};
 use std::sync::Arc;
 
 /// This is our service handler. It receives a Request, routes on its
@@ -23,18 +26,26 @@ async fn action(
     req: Request<Body>,
     ts_engine: Arc<Box<dyn Engine + Send + Sync>>,
 ) -> Result<Response<Body>, hyper::Error> {
-    match (req.method(), req.uri().path()) {
+    let result = match (req.method(), req.uri().path()) {
         // Serve some instructions at /
         (&Method::GET, "/") => Ok(Response::new(Body::from("ok"))),
 
         (&Method::POST, "/search") => action::search(req, ts_engine).await,
 
-        // Simply echo the body back to the client.
         (&Method::POST, "/append") => action::append(req, ts_engine).await,
 
-        // Simply echo the body back to the client.
+        (&Method::POST, "/delete/series") => action::delete_series(req, ts_engine).await,
+
+        (&Method::POST, "/delete/range") => action::delete_range(req, ts_engine).await,
+
         (&Method::POST, "/table") => action::create_table(req, ts_engine).await,
 
+        (&Method::GET, "/stats") => action::stats(ts_engine).await,
+
+        (&Method::GET, "/stats/jobs") => action::job_stats(ts_engine).await,
+
+        (&Method::GET, "/stats/cache") => action::cache_stats(ts_engine).await,
+
         (&Method::POST, "/echo/reversed") => {
             let whole_body = hyper::body::to_bytes(req.into_body()).await?;
 
@@ -43,12 +54,15 @@ async fn action(
         }
 
         // Return the 404 Not Found for other routes.
-        _ => {
-            let mut not_found = Response::default();
-            *not_found.status_mut() = StatusCode::NOT_FOUND;
-            Ok(not_found)
+        (method, path) => Err(ActionError::NotFound(format!("{} {}", method, path))),
+    };
+
+    Ok(result.unwrap_or_else(|err| {
+        if err.status().is_server_error() {
+            error!("request failed: {}", err);
         }
-    }
+        err.into_response()
+    }))
 }
 
 //#[tokio::main]
@@ -68,12 +82,54 @@ async fn serve0(
         }
     });
 
-    let server = Server::bind(&addr).serve(service);
+    let server = Server::bind(&addr)
+        .serve(service)
+        .with_graceful_shutdown(shutdown_signal());
     info!("Listening on http://{}", addr);
     server.await?;
+
+    // the server answered its last request, nothing appends any more
+    info!("shutting down");
+    let summary = tokio::task::block_in_place(|| ts_engine.shutdown())?;
+    info!(
+        "shut down: {} series, {} queued points drained, {} blocks rolled down, {} block files written",
+        summary.series, summary.drained, summary.blocks_closed, summary.blocks_persisted
+    );
     Ok(())
 }
 
+/// resolves on SIGINT or SIGTERM, the server then stops accepting connections and finishes
+/// the requests in flight
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for SIGINT: {}", err);
+            futures::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                term.recv().await;
+            }
+            Err(err) => {
+                error!("failed to listen for SIGTERM: {}", err);
+                futures::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received SIGINT"),
+        _ = terminate => info!("received SIGTERM"),
+    }
+}
+
 pub fn serve(ts_engine: Box<dyn Engine + Send + Sync>) {
     let _ = tokio::runtime::Runtime::new()
         .unwrap()
@@ -1,6 +0,0 @@
-mod block;
-mod ts;
-mod engine;
-
-pub use engine::BTreeEngine;
-pub use engine::Raw;
\ No newline at end of file
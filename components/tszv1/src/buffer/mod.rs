@@ -1,48 +1,26 @@
-use serde::export::fmt::{Debug, Error};
-use serde::export::Formatter;
-use std::borrow::BorrowMut;
-use std::collections::linked_list::Iter;
-use std::collections::LinkedList;
+use std::fmt::{Debug, Error, Formatter};
 use std::ops::{Index, IndexMut};
-
-pub struct Block {
-    pub data: Vec<u8>,
-    pub start_index: usize,
-    pub end_index: usize,
-}
-
-impl Block {
-    fn new(capacity: usize, start_index: usize) -> Self {
-        Block {
-            data: Vec::with_capacity(capacity),
-            start_index,
-            end_index: start_index + capacity,
-        }
-    }
-
-    fn with_array(array: Box<[u8]>, start_index: usize) -> Self {
-        let len = array.len();
-        let data = array.into_vec();
-        Block {
-            data,
-            start_index,
-            end_index: start_index + len,
-        }
-    }
-}
-
-impl Clone for Block {
-    fn clone(&self) -> Self {
-        Block {
-            data: self.data.clone(),
-            start_index: self.start_index,
-            end_index: self.end_index,
-        }
-    }
-}
-
+use std::slice;
+use std::sync::Arc;
+
+/// GRANULE is the unit of the directory from byte index to chunk. Every chunk but the first
+/// holds at least a granule, so a granule spans at most two chunks.
+const GRANULE: usize = 1024;
+
+/// MAX_CHUNK_LEN bounds the growth of the chunks
+const MAX_CHUNK_LEN: usize = 10 * 1024 * 1024;
+
+/// Buffer
+///
+/// Buffer is a growable byte store made of chunks which are never moved once allocated, so it
+/// grows without copying the bytes written so far. A new chunk holds `incr_factor` of the
+/// bytes written so far, rounded up to a whole number of granules. A directory with the chunk
+/// of each granule makes indexed access constant time.
+#[derive(Clone)]
 pub struct Buffer {
-    blocks: LinkedList<Block>,
+    chunks: Vec<Vec<u8>>,
+    starts: Vec<usize>,  // index of the first byte of each chunk
+    directory: Vec<u32>, // chunk holding the first byte of each granule
     init_capacity: usize,
     incr_factor: f32,
     len: usize,
@@ -51,7 +29,9 @@ pub struct Buffer {
 impl Buffer {
     pub fn new(init_capacity: usize, incr_factor: f32) -> Self {
         Buffer {
-            blocks: LinkedList::new(),
+            chunks: Vec::new(),
+            starts: Vec::new(),
+            directory: Vec::new(),
             init_capacity,
             incr_factor,
             len: 0,
@@ -60,134 +40,103 @@ impl Buffer {
 
     pub fn with_array(array: Box<[u8]>, incr_factor: f32) -> Self {
         let len = array.len();
-        let mut blocks = LinkedList::new();
-        blocks.push_back(Block::with_array(array, 0));
-
-        Buffer {
-            blocks,
-            init_capacity: len,
-            incr_factor,
-            len,
+        let mut buffer = Buffer::new(len, incr_factor);
+        if len > 0 {
+            buffer.chunks.push(array.into_vec());
+            buffer.starts.push(0);
+            buffer.directory = vec![0; len.div_ceil(GRANULE)];
+            buffer.len = len;
         }
+        buffer
     }
 
-    //    #[inline]
-    //    fn push_value(&mut self, block: &mut Block, value: u8) {
-    //        block.data.push(value);
-    //        self.len = self.len + 1;
-    //    }
-
-    fn push_with_incr_block(&mut self, first_value: u8) {
+    fn push_chunk(&mut self) {
         let latest_capacity = if self.len == 0 {
             self.init_capacity
         } else {
             self.len
         };
 
-        let mut incr_capacity = (latest_capacity as f32 * self.incr_factor) as usize;
-        if incr_capacity < 1024 {
-            incr_capacity = 1024;
-        } else if incr_capacity > 10 * 1024 * 1024 {
-            incr_capacity = 10 * 1024 * 1024;
-        }
-
-        let mut block = Block::new(incr_capacity, self.len);
-        //        println!("new block start:{}, end:{}", block.start_index, block.end_index);
-        block.data.push(first_value);
-
-        self.blocks.push_back(block);
+        let capacity = ((latest_capacity as f32 * self.incr_factor) as usize)
+            .clamp(GRANULE, MAX_CHUNK_LEN)
+            .div_ceil(GRANULE)
+            * GRANULE;
+        self.chunks.push(Vec::with_capacity(capacity));
+        self.starts.push(self.len);
     }
 
     pub fn push(&mut self, value: u8) {
-        match self.blocks.back_mut() {
-            Some(block) => {
-                if block.data.len() == block.data.capacity() {
-                    self.push_with_incr_block(value);
-                } else {
-                    block.data.push(value);
-                }
-            }
-            None => {
-                self.push_with_incr_block(value);
-            }
+        match self.chunks.last() {
+            Some(chunk) if chunk.len() < chunk.capacity() => {}
+            _ => self.push_chunk(),
         }
-        self.len = self.len + 1;
+        if self.len.is_multiple_of(GRANULE) {
+            self.directory.push(self.chunks.len() as u32 - 1);
+        }
+        self.chunks.last_mut().unwrap().push(value);
+        self.len += 1;
     }
 
     pub fn len(&self) -> usize {
         self.len
     }
 
-    pub fn get(&self, index: usize) -> Option<&u8> {
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the chunk holding byte `index` and the offset of the byte in it
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
         if index >= self.len {
             return None;
         }
 
-        // try tail cell search prior
-        match self.blocks.back() {
-            Some(block) => {
-                if index >= block.start_index {
-                    return if index < block.end_index {
-                        block.data.get(index - block.start_index)
-                    } else {
-                        None
-                    };
-                }
-            }
-            None => {}
+        let mut chunk = self.directory[index / GRANULE] as usize;
+        if chunk + 1 < self.chunks.len() && index >= self.starts[chunk + 1] {
+            chunk += 1;
         }
+        Some((chunk, index - self.starts[chunk]))
+    }
 
-        // walk linked list
-        let mut iter = self.blocks.iter();
-        loop {
-            match iter.next() {
-                Some(block) => {
-                    if index >= block.start_index && index < block.end_index {
-                        return block.data.get(index - block.start_index);
-                    }
-                }
-                None => {
-                    return None;
-                }
-            }
-        }
+    pub fn get(&self, index: usize) -> Option<&u8> {
+        self.locate(index)
+            .and_then(|(chunk, offset)| self.chunks[chunk].get(offset))
     }
 
     pub fn get_mut(&mut self, index: usize) -> Option<&mut u8> {
-        if index >= self.len {
-            return None;
-        }
-
-        // walk linked list
-        let mut iter = self.blocks.iter_mut();
-        loop {
-            match iter.next_back() {
-                Some(block) => {
-                    if index >= block.start_index && index < block.end_index {
-                        return block.data.get_mut(index - block.start_index);
-                    }
-                }
-                None => {
-                    return None;
-                }
-            }
+        match self.locate(index) {
+            Some((chunk, offset)) => self.chunks[chunk].get_mut(offset),
+            None => None,
         }
     }
 
     pub fn into_boxed_slice(mut self) -> Box<[u8]> {
-        let mut vec = Vec::with_capacity(self.len);
-        loop {
-            match self.blocks.pop_front() {
-                Some(mut block) => vec.append(block.data.borrow_mut()),
-                None => break,
-            }
+        if self.chunks.len() == 1 {
+            return self.chunks.pop().unwrap().into_boxed_slice();
         }
 
+        let mut vec = Vec::with_capacity(self.len);
+        for chunk in self.chunks.iter() {
+            vec.extend_from_slice(chunk);
+        }
         vec.into_boxed_slice()
     }
 
-    pub fn iter(&self) -> BufferIter {
-        BufferIter::new(self.blocks.iter())
+    /// the bytes in one immutable slice, to be shared by the readers of a closed block
+    pub fn freeze(self) -> Arc<[u8]> {
+        Arc::from(self.into_boxed_slice())
+    }
+
+    /// the bytes as consecutive slices, one per chunk
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.chunks.iter().map(|chunk| chunk.as_slice())
+    }
+
+    pub fn iter(&self) -> BufferIter<'_> {
+        BufferIter {
+            chunks: self.chunks.iter(),
+            chunk: [].iter(),
+        }
     }
 }
 
@@ -202,8 +151,6 @@ impl Index<usize> for Buffer {
 impl IndexMut<usize> for Buffer {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         self.get_mut(index).unwrap()
-        //        let n = self.get(index).as_mut();
-        //        IndexMut::index_mut(&mut **self, index)
     }
 }
 
@@ -219,71 +166,28 @@ impl Default for Buffer {
     }
 }
 
-impl Clone for Buffer {
-    fn clone(&self) -> Self {
-        let mut blocks = LinkedList::new();
-        let mut iter = self.blocks.iter();
-        loop {
-            match iter.next() {
-                Some(block) => blocks.push_back(block.clone()),
-                None => break,
-            }
-        }
-
-        Buffer {
-            blocks,
-            init_capacity: self.init_capacity,
-            incr_factor: self.incr_factor,
-            len: self.len,
-        }
-    }
-}
-
 pub struct BufferIter<'a> {
-    inner: Iter<'a, Block>,
-    block: Option<&'a Block>,
-    walked: usize,
-}
-
-impl<'a> BufferIter<'a> {
-    fn new(inner: Iter<'a, Block>) -> Self {
-        BufferIter {
-            inner,
-            block: None,
-            walked: 0,
-        }
-    }
+    chunks: slice::Iter<'a, Vec<u8>>,
+    chunk: slice::Iter<'a, u8>,
 }
 
 impl<'a> Iterator for BufferIter<'a> {
     type Item = u8;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.block {
-            Some(block) => {
-                if self.walked < block.end_index {
-                    let n = block.data.get(self.walked - block.start_index);
-                    self.walked = self.walked + 1;
-                    return n.map(|value| *value);
-                }
+        loop {
+            if let Some(value) = self.chunk.next() {
+                return Some(*value);
             }
-            None => {}
+            self.chunk = self.chunks.next()?.iter();
         }
-        return match self.inner.next() {
-            Some(block) => {
-                self.block = Some(block);
-                let n = block.data.get(0);
-                self.walked = self.walked + 1;
-                n.map(|value| *value)
-            }
-            None => None,
-        };
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::buffer::Buffer;
+    use crate::stream::ByteSource;
     use rand::prelude::*;
 
     #[test]
@@ -341,4 +245,28 @@ mod tests {
 
         println!("end");
     }
+
+    #[test]
+    fn chunks() {
+        let mut buffer = Buffer::with_array(vec![1, 2, 3].into_boxed_slice(), 0.5);
+        for i in 0..5000 {
+            buffer.push((i % 251) as u8);
+        }
+        assert_eq!(buffer.len(), 5003);
+        assert_eq!(buffer[2], 3);
+        assert_eq!(buffer[3], 0);
+        assert_eq!(buffer[5002], (4999 % 251) as u8);
+        assert_eq!(buffer.get(5003), None);
+
+        // the chunks grow with the bytes written and together hold every byte in order
+        let lens: Vec<usize> = buffer.chunks().map(|chunk| chunk.len()).collect();
+        assert_eq!(lens, vec![3, 1024, 1024, 2048, 904]);
+        let bytes: Vec<u8> = buffer.chunks().flatten().cloned().collect();
+        assert_eq!(bytes, buffer.iter().collect::<Vec<u8>>());
+        for offset in [0, 5, 2100, 5003].iter() {
+            let checksum = crate::format::checksum(&bytes[*offset..]);
+            assert_eq!(ByteSource::checksum(&buffer, *offset), checksum);
+        }
+        assert_eq!(&bytes[..], &buffer.freeze()[..]);
+    }
 }
@@ -0,0 +1,67 @@
+//! Bool values, run length encoded one point at a time.
+//!
+//! Timestamps and values are interleaved in a block, so a run can not be written as a length
+//! up front without buffering the points of the run. Each point stores a single bit instead:
+//! `0` continues the run of the previous value, `1` starts a run of the other value. A series
+//! which changes state rarely, the usual up/down series, costs one bit per point plus the
+//! timestamps.
+
+use crate::codec::Codec;
+use crate::decode::Error;
+use crate::stream::{Read, Write};
+use crate::{Bit, Value};
+
+/// Boolean
+///
+/// Boolean stores bool values, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Boolean {
+    value: bool,
+    first: bool,
+}
+
+impl Boolean {
+    pub fn new() -> Self {
+        Boolean {
+            value: false,
+            first: true,
+        }
+    }
+}
+
+impl Default for Boolean {
+    fn default() -> Self {
+        Boolean::new()
+    }
+}
+
+impl Codec for Boolean {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = value.as_i64() != 0;
+        if self.first {
+            // the first value is the run of its own
+            self.first = false;
+            self.value = value;
+            w.write_bit(if value { Bit::One } else { Bit::Zero });
+            return;
+        }
+
+        if value == self.value {
+            w.write_bit(Bit::Zero);
+        } else {
+            w.write_bit(Bit::One);
+            self.value = value;
+        }
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        let bit = r.read_bit()? == Bit::One;
+        if self.first {
+            self.first = false;
+            self.value = bit;
+        } else if bit {
+            self.value = !self.value;
+        }
+        Ok(Value::Bool(self.value))
+    }
+}
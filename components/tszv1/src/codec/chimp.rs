@@ -0,0 +1,304 @@
+//! Chimp and Chimp128, "Chimp: Efficient Lossless Floating Point Compression for Time Series
+//! Databases" (Liakos, Papakonstantinopoulou, Kotidis, VLDB 2022).
+//!
+//! Both XOR a value with a reference value. The number of leading zeroes of the XOR is rounded
+//! down to one of eight values stored in 3 bits, and the trailing zeroes are only trimmed when
+//! there are enough of them to pay for the 6 bits of the length. Chimp128 picks the reference
+//! among the 128 previous values, looked up by their lowest bits.
+
+use crate::codec::Codec;
+use crate::decode::Error;
+use crate::stream::{Read, Write};
+use crate::Value;
+
+/// the leading zeroes counts which can be stored, indexed by their 3 bit code
+const LEADING_REPRESENTATION: [u32; 8] = [0, 8, 12, 16, 18, 20, 22, 24];
+
+/// a stored leading zeroes count which never matches, forces the next XOR to write its own
+const NO_LEADING: u32 = 65;
+
+/// round `leading_zeroes` down to a representable count, returns the count and its code
+fn round_leading(leading_zeroes: u32) -> (u32, u64) {
+    let code = LEADING_REPRESENTATION
+        .iter()
+        .rposition(|n| *n <= leading_zeroes)
+        .unwrap_or(0);
+    (LEADING_REPRESENTATION[code], code as u64)
+}
+
+/// write the control bits 01: the leading zeroes code, the count of center bits and the center
+/// bits of `xor`
+fn write_center<W: Write>(w: &mut W, xor: u64, code: u64, leading: u32) {
+    let trailing_zeroes = xor.trailing_zeros();
+    // at least one center bit, at most 64 - 7 since there are more than 6 trailing zeroes
+    let center_bits = 64 - leading - trailing_zeroes;
+    w.write_bits(code, 3);
+    w.write_bits(u64::from(center_bits), 6);
+    w.write_bits(xor >> trailing_zeroes, center_bits);
+}
+
+fn read_center<R: Read>(r: &mut R) -> Result<u64, Error> {
+    let leading = LEADING_REPRESENTATION[r.read_bits(3)? as usize];
+    let center_bits = r.read_bits(6)? as u32;
+    if center_bits == 0 || leading + center_bits > 64 {
+        return Err(Error::InvalidValue);
+    }
+    let trailing_zeroes = 64 - leading - center_bits;
+    Ok(r.read_bits(center_bits)? << trailing_zeroes)
+}
+
+/// Chimp
+///
+/// Chimp XORs each value with the previous one.
+#[derive(Debug, Clone)]
+pub struct Chimp {
+    value_bits: u64,     // previous float value as bits
+    leading_zeroes: u32, // rounded leading zeroes of the previous XOR stored with 10 or 11
+    first: bool,
+}
+
+impl Chimp {
+    /// trailing zeroes above this threshold are trimmed
+    const TRAILING_THRESHOLD: u32 = 6;
+
+    pub fn new() -> Self {
+        Chimp {
+            value_bits: 0,
+            leading_zeroes: NO_LEADING,
+            first: true,
+        }
+    }
+}
+
+impl Default for Chimp {
+    fn default() -> Self {
+        Chimp::new()
+    }
+}
+
+impl Codec for Chimp {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = value.as_f64();
+        let value_bits = value.to_bits();
+        if self.first {
+            self.first = false;
+            self.value_bits = value_bits;
+            w.write_bits(value_bits, 64);
+            return;
+        }
+
+        let xor = value_bits ^ self.value_bits;
+        self.value_bits = value_bits;
+
+        if xor == 0 {
+            w.write_bits(0b00, 2);
+            self.leading_zeroes = NO_LEADING;
+            return;
+        }
+
+        let (leading, code) = round_leading(xor.leading_zeros());
+        if xor.trailing_zeros() > Self::TRAILING_THRESHOLD {
+            w.write_bits(0b01, 2);
+            write_center(w, xor, code, leading);
+            self.leading_zeroes = NO_LEADING;
+        } else if leading == self.leading_zeroes {
+            w.write_bits(0b10, 2);
+            w.write_bits(xor, 64 - leading);
+        } else {
+            w.write_bits(0b11, 2);
+            w.write_bits(code, 3);
+            w.write_bits(xor, 64 - leading);
+            self.leading_zeroes = leading;
+        }
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        if self.first {
+            self.first = false;
+            self.value_bits = r.read_bits(64)?;
+            return Ok(Value::Float(f64::from_bits(self.value_bits)));
+        }
+
+        let xor = match r.read_bits(2)? {
+            0b00 => {
+                self.leading_zeroes = NO_LEADING;
+                0
+            }
+            0b01 => {
+                self.leading_zeroes = NO_LEADING;
+                read_center(r)?
+            }
+            0b10 => {
+                if self.leading_zeroes == NO_LEADING {
+                    return Err(Error::InvalidValue);
+                }
+                r.read_bits(64 - self.leading_zeroes)?
+            }
+            _ => {
+                self.leading_zeroes = LEADING_REPRESENTATION[r.read_bits(3)? as usize];
+                r.read_bits(64 - self.leading_zeroes)?
+            }
+        };
+
+        self.value_bits ^= xor;
+        Ok(Value::Float(f64::from_bits(self.value_bits)))
+    }
+}
+
+/// number of previous values Chimp128 can refer to
+const PREVIOUS_VALUES: usize = 128;
+
+/// bits needed to store an index into the previous values
+const PREVIOUS_VALUES_LOG2: u32 = 7;
+
+/// Chimp128
+///
+/// Chimp128 XORs each value with one of the 128 previous values. The encoder remembers the
+/// position of the last value with the same lowest `KEY_BITS` bits, if XORing with that value
+/// leaves enough trailing zeroes it is used as the reference and its index is stored, otherwise
+/// the previous value is used as Chimp does. This pays off for series which cycle through a
+/// small set of values.
+#[derive(Debug, Clone)]
+pub struct Chimp128 {
+    values: Vec<u64>,    // ring of the previous values as bits
+    index: usize,        // count of values seen so far
+    positions: Vec<u32>, // per key, the index of the last value with that key, encoder only
+    leading_zeroes: u32,
+}
+
+impl Chimp128 {
+    /// trailing zeroes above this threshold make another value a better reference than the
+    /// previous one, 7 more than for Chimp to pay for the index
+    const TRAILING_THRESHOLD: u32 = 6 + PREVIOUS_VALUES_LOG2;
+
+    /// count of lowest bits used to look up a reference
+    const KEY_BITS: u32 = Self::TRAILING_THRESHOLD + 1;
+
+    pub fn new() -> Self {
+        Chimp128 {
+            values: vec![0; PREVIOUS_VALUES],
+            index: 0,
+            positions: Vec::new(),
+            leading_zeroes: NO_LEADING,
+        }
+    }
+
+    /// a copy of the state needed to decode the next value, without the position table of the
+    /// encoder
+    pub fn snapshot(&self) -> Self {
+        Chimp128 {
+            values: self.values.clone(),
+            index: self.index,
+            positions: Vec::new(),
+            leading_zeroes: self.leading_zeroes,
+        }
+    }
+
+    fn push(&mut self, value_bits: u64) {
+        self.values[self.index % PREVIOUS_VALUES] = value_bits;
+        self.index += 1;
+    }
+
+    fn previous_slot(&self) -> usize {
+        (self.index - 1) % PREVIOUS_VALUES
+    }
+}
+
+impl Default for Chimp128 {
+    fn default() -> Self {
+        Chimp128::new()
+    }
+}
+
+impl Codec for Chimp128 {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = value.as_f64();
+        let value_bits = value.to_bits();
+        let key = (value_bits & ((1 << Self::KEY_BITS) - 1)) as usize;
+        if self.positions.is_empty() {
+            self.positions = vec![0; 1 << Self::KEY_BITS];
+        }
+
+        if self.index == 0 {
+            w.write_bits(value_bits, 64);
+            self.positions[key] = 0;
+            self.push(value_bits);
+            return;
+        }
+
+        // prefer the last value with the same lowest bits when it is still in the ring and
+        // leaves enough trailing zeroes, the position table starts zeroed which points at the
+        // first value of the stream
+        let candidate = self.positions[key] as usize;
+        let mut slot = self.previous_slot();
+        let mut use_candidate = false;
+        if self.index - candidate < PREVIOUS_VALUES {
+            let candidate_slot = candidate % PREVIOUS_VALUES;
+            let xor = value_bits ^ self.values[candidate_slot];
+            if xor.trailing_zeros() > Self::TRAILING_THRESHOLD {
+                slot = candidate_slot;
+                use_candidate = true;
+            }
+        }
+        let xor = value_bits ^ self.values[slot];
+
+        if xor == 0 {
+            w.write_bits(0b00, 2);
+            w.write_bits(slot as u64, PREVIOUS_VALUES_LOG2);
+            self.leading_zeroes = NO_LEADING;
+        } else {
+            let (leading, code) = round_leading(xor.leading_zeros());
+            if use_candidate {
+                w.write_bits(0b01, 2);
+                w.write_bits(slot as u64, PREVIOUS_VALUES_LOG2);
+                write_center(w, xor, code, leading);
+                self.leading_zeroes = NO_LEADING;
+            } else if leading == self.leading_zeroes {
+                w.write_bits(0b10, 2);
+                w.write_bits(xor, 64 - leading);
+            } else {
+                w.write_bits(0b11, 2);
+                w.write_bits(code, 3);
+                w.write_bits(xor, 64 - leading);
+                self.leading_zeroes = leading;
+            }
+        }
+
+        self.positions[key] = self.index as u32;
+        self.push(value_bits);
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        if self.index == 0 {
+            let value_bits = r.read_bits(64)?;
+            self.push(value_bits);
+            return Ok(Value::Float(f64::from_bits(value_bits)));
+        }
+
+        let value_bits = match r.read_bits(2)? {
+            0b00 => {
+                self.leading_zeroes = NO_LEADING;
+                let slot = r.read_bits(PREVIOUS_VALUES_LOG2)? as usize;
+                self.values[slot]
+            }
+            0b01 => {
+                self.leading_zeroes = NO_LEADING;
+                let slot = r.read_bits(PREVIOUS_VALUES_LOG2)? as usize;
+                self.values[slot] ^ read_center(r)?
+            }
+            0b10 => {
+                if self.leading_zeroes == NO_LEADING {
+                    return Err(Error::InvalidValue);
+                }
+                self.values[self.previous_slot()] ^ r.read_bits(64 - self.leading_zeroes)?
+            }
+            _ => {
+                self.leading_zeroes = LEADING_REPRESENTATION[r.read_bits(3)? as usize];
+                self.values[self.previous_slot()] ^ r.read_bits(64 - self.leading_zeroes)?
+            }
+        };
+
+        self.push(value_bits);
+        Ok(Value::Float(f64::from_bits(value_bits)))
+    }
+}
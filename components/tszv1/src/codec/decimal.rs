@@ -0,0 +1,115 @@
+//! A decimal aware codec in the spirit of ALP, "ALP: Adaptive Lossless floating-Point
+//! Compression" (Afroozeh, Kuffó, Boncz, SIGMOD 2024).
+//!
+//! Most metrics are decimals with a few digits after the point which XOR based codecs compress
+//! poorly since their mantissas share few bits. A value `v` is stored as the integer `d` such
+//! that `d / 10^e == v` exactly, for an exponent `e` shared with the previous values, and `d`
+//! is written as the zigzag varint of its difference with the previous integer. ALP picks the
+//! exponent per vector of 1024 values, blocks here are written one value at a time so the
+//! exponent is sticky instead: it only changes when a value can not be represented with it.
+//!
+//! Control bits:
+//!
+//! - `0`: varint(zigzag(d - previous d)) with the current exponent
+//! - `10`: the new exponent in 4 bits, then varint(zigzag(d))
+//! - `11`: 64 bits of the value as is, for values without a short decimal form, NaN and
+//!   infinities, the state is not changed
+
+use crate::codec::Codec;
+use crate::decode::Error;
+use crate::format::{zigzag_decode, zigzag_encode};
+use crate::stream::{Read, Write};
+use crate::Value;
+use crate::Bit;
+
+/// the largest exponent, powers of ten up to 10^22 are exact in a f64
+const MAX_EXPONENT: usize = 15;
+
+const POW10: [f64; MAX_EXPONENT + 1] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15,
+];
+
+/// integers beyond 2^53 can not all be represented by a f64
+const MAX_DIGITS: f64 = 9_007_199_254_740_992.0;
+
+/// the integer `d` such that `d / 10^exponent` is `value`, bit for bit
+fn to_digits(value: f64, exponent: usize) -> Option<i64> {
+    let digits = (value * POW10[exponent]).round();
+    if digits.is_nan() || digits.abs() >= MAX_DIGITS {
+        // also refuses infinities
+        return None;
+    }
+    let digits = digits as i64;
+    if from_digits(digits, exponent).to_bits() == value.to_bits() {
+        Some(digits)
+    } else {
+        None
+    }
+}
+
+fn from_digits(digits: i64, exponent: usize) -> f64 {
+    digits as f64 / POW10[exponent]
+}
+
+/// Decimal
+///
+/// Decimal stores values as scaled integers, see the module documentation.
+#[derive(Debug, Clone, Default)]
+pub struct Decimal {
+    exponent: usize, // current exponent
+    digits: i64,     // previous value scaled by the current exponent
+}
+
+impl Decimal {
+    pub fn new() -> Self {
+        Decimal::default()
+    }
+}
+
+impl Codec for Decimal {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = value.as_f64();
+        if let Some(digits) = to_digits(value, self.exponent) {
+            w.write_bit(Bit::Zero);
+            w.write_varint(zigzag_encode(digits - self.digits));
+            self.digits = digits;
+            return;
+        }
+
+        // the smallest exponent which represents the value
+        let found = (0..=MAX_EXPONENT).find_map(|e| to_digits(value, e).map(|d| (e, d)));
+        match found {
+            Some((exponent, digits)) => {
+                w.write_bits(0b10, 2);
+                w.write_bits(exponent as u64, 4);
+                w.write_varint(zigzag_encode(digits));
+                self.exponent = exponent;
+                self.digits = digits;
+            }
+            None => {
+                w.write_bits(0b11, 2);
+                w.write_bits(value.to_bits(), 64);
+            }
+        }
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        if r.read_bit()? == Bit::Zero {
+            let delta = zigzag_decode(r.read_varint()?);
+            self.digits = self.digits.wrapping_add(delta);
+            return Ok(Value::Float(from_digits(self.digits, self.exponent)));
+        }
+
+        if r.read_bit()? == Bit::One {
+            return Ok(Value::Float(f64::from_bits(r.read_bits(64)?)));
+        }
+
+        let exponent = r.read_bits(4)? as usize;
+        if exponent > MAX_EXPONENT {
+            return Err(Error::InvalidValue);
+        }
+        self.exponent = exponent;
+        self.digits = zigzag_decode(r.read_varint()?);
+        Ok(Value::Float(from_digits(self.digits, self.exponent)))
+    }
+}
@@ -0,0 +1,96 @@
+//! Low cardinality string values, dictionary and run length encoded one point at a time.
+//!
+//! The dictionary is built inline: the first occurrence of a string stores its bytes and gives
+//! it the next index, later occurrences store the index only. Control bits:
+//!
+//! - `0`: the value equals the previous one
+//! - `10`: varint index of a string already in the dictionary
+//! - `11`: varint length then the bytes of a new string, which is added to the dictionary
+//!
+//! Every distinct string of a block is kept in memory by the encoder and the decoder, high
+//! cardinality strings belong elsewhere.
+
+use crate::codec::Codec;
+use crate::decode::Error;
+use crate::stream::{Read, Write};
+use crate::{Bit, Value};
+use std::collections::HashMap;
+
+/// Dictionary
+///
+/// Dictionary stores string values, see the module documentation.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    entries: Vec<String>,
+    indexes: HashMap<String, u64>, // encoder only
+    previous: Option<u64>,         // index of the previous value
+}
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Dictionary::default()
+    }
+
+    /// a copy of the state needed to decode the next value, without the lookup table of the
+    /// encoder
+    pub fn snapshot(&self) -> Self {
+        Dictionary {
+            entries: self.entries.clone(),
+            indexes: HashMap::new(),
+            previous: self.previous,
+        }
+    }
+}
+
+impl Codec for Dictionary {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = match value {
+            Value::String(v) => v,
+            other => other.to_string(),
+        };
+
+        match self.indexes.get(&value) {
+            Some(index) if Some(*index) == self.previous => w.write_bit(Bit::Zero),
+            Some(index) => {
+                w.write_bits(0b10, 2);
+                w.write_varint(*index);
+                self.previous = Some(*index);
+            }
+            None => {
+                let index = self.entries.len() as u64;
+                w.write_bits(0b11, 2);
+                w.write_varint(value.len() as u64);
+                for byte in value.as_bytes() {
+                    w.write_byte(*byte);
+                }
+                self.indexes.insert(value.clone(), index);
+                self.entries.push(value);
+                self.previous = Some(index);
+            }
+        }
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        let index = if r.read_bit()? == Bit::Zero {
+            self.previous.ok_or(Error::InvalidValue)?
+        } else if r.read_bit()? == Bit::Zero {
+            r.read_varint()?
+        } else {
+            let len = r.read_varint()?;
+            let mut bytes = Vec::new();
+            for _ in 0..len {
+                bytes.push(r.read_byte()?);
+            }
+            let value = String::from_utf8(bytes).map_err(|_| Error::InvalidValue)?;
+            self.entries.push(value);
+            (self.entries.len() - 1) as u64
+        };
+
+        let value = self
+            .entries
+            .get(index as usize)
+            .ok_or(Error::InvalidValue)?;
+        self.previous = Some(index);
+        Ok(Value::String(value.clone()))
+    }
+}
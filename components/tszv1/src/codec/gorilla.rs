@@ -0,0 +1,124 @@
+use crate::codec::Codec;
+use crate::decode::Error;
+use crate::stream::{Read, Write};
+use crate::Value;
+use crate::Bit;
+
+/// Gorilla
+///
+/// The XOR scheme of the Gorilla paper: each value is XORed with the previous one and only the
+/// meaningful bits of the XOR are stored.
+#[derive(Debug, Clone)]
+pub struct Gorilla {
+    value_bits: u64, // previous float value as bits
+
+    // store the number of leading and trailing zeroes in the current xor as u32 so we
+    // don't have to do any conversions after calling `leading_zeros` and `trailing_zeros`
+    leading_zeroes: u32,
+    trailing_zeroes: u32,
+
+    first: bool, // will the next value be the first value of the stream
+}
+
+impl Gorilla {
+    pub fn new() -> Self {
+        Gorilla {
+            value_bits: 0,
+            leading_zeroes: 64,  // 64 is an initial sentinel value
+            trailing_zeroes: 64, // 64 is an intitial sentinel value
+            first: true,
+        }
+    }
+}
+
+impl Default for Gorilla {
+    fn default() -> Self {
+        Gorilla::new()
+    }
+}
+
+impl Codec for Gorilla {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = value.as_f64();
+        let value_bits = value.to_bits();
+
+        if self.first {
+            // store the first value exactly
+            self.first = false;
+            self.value_bits = value_bits;
+            w.write_bits(value_bits, 64);
+            return;
+        }
+
+        let xor = value_bits ^ self.value_bits;
+        self.value_bits = value_bits;
+
+        if xor == 0 {
+            // if xor with previous value is zero just store single zero bit
+            w.write_bit(Bit::Zero);
+            return;
+        }
+
+        w.write_bit(Bit::One);
+
+        let leading_zeroes = xor.leading_zeros();
+        let trailing_zeroes = xor.trailing_zeros();
+
+        if leading_zeroes >= self.leading_zeroes && trailing_zeroes >= self.trailing_zeroes {
+            // if the number of leading and trailing zeroes in this xor are >= the leading and
+            // trailing zeroes in the previous xor then we only need to store a control bit and
+            // the significant digits of this xor
+            w.write_bit(Bit::Zero);
+            w.write_bits(
+                xor.wrapping_shr(self.trailing_zeroes),
+                64 - self.leading_zeroes - self.trailing_zeroes,
+            );
+        } else {
+            // otherwise we store a control bit and use 6 bits to store the number of leading
+            // zeroes and 6 bits to store the number of significant digits before storing the
+            // significant digits themselves
+            w.write_bit(Bit::One);
+            w.write_bits(u64::from(leading_zeroes), 6);
+
+            // if significant_digits is 64 we cannot encode it using 6 bits, however since
+            // significant_digits is guaranteed to be at least 1 we can subtract 1 to ensure
+            // significant_digits can always be expressed with 6 bits or less
+            let significant_digits = 64 - leading_zeroes - trailing_zeroes;
+            w.write_bits(u64::from(significant_digits - 1), 6);
+            w.write_bits(xor.wrapping_shr(trailing_zeroes), significant_digits);
+
+            // finally we need to update the number of leading and trailing zeroes
+            self.leading_zeroes = leading_zeroes;
+            self.trailing_zeroes = trailing_zeroes;
+        }
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        if self.first {
+            self.first = false;
+            self.value_bits = r.read_bits(64)?;
+            return Ok(Value::Float(f64::from_bits(self.value_bits)));
+        }
+
+        if r.read_bit()? == Bit::Zero {
+            return Ok(Value::Float(f64::from_bits(self.value_bits)));
+        }
+
+        if r.read_bit()? == Bit::One {
+            self.leading_zeroes = r.read_bits(6)? as u32;
+            let significant_digits = r.read_bits(6)? as u32 + 1;
+            if self.leading_zeroes + significant_digits > 64 {
+                return Err(Error::InvalidValue);
+            }
+            self.trailing_zeroes = 64 - self.leading_zeroes - significant_digits;
+        }
+
+        // a corrupt stream may use the previous xor before any was read
+        let size = 64u32
+            .checked_sub(self.leading_zeroes + self.trailing_zeroes)
+            .ok_or(Error::InvalidValue)?;
+        let bits = r.read_bits(size)?;
+        self.value_bits ^= bits.wrapping_shl(self.trailing_zeroes);
+        Ok(Value::Float(f64::from_bits(self.value_bits)))
+    }
+}
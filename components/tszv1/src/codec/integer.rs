@@ -0,0 +1,102 @@
+//! Integer values: the difference with the previous value is zigzag encoded and bit packed with
+//! an adaptive width.
+//!
+//! Simple8b packs several values in a 64 bit word, which needs the values of a whole word up
+//! front while blocks are written one data point at a time with the timestamps interleaved. The
+//! width is carried from one value to the next instead, which keeps the per value cost of a
+//! steady counter at a few bits.
+//!
+//! Control bits:
+//!
+//! - `0`: the value equals the previous one
+//! - `10`: the zigzag delta in the current width
+//! - `11`: a new width in 6 bits, stored minus one, then the zigzag delta in that width
+//!
+//! The first value is stored as its 64 bits.
+
+use crate::codec::Codec;
+use crate::decode::Error;
+use crate::format::{zigzag_decode, zigzag_encode};
+use crate::stream::{Read, Write};
+use crate::{Bit, Value};
+
+/// a narrower width is only switched to when it saves more than the 8 bits of the switch
+const SHRINK_BITS: u32 = 8;
+
+/// Integer
+///
+/// Integer stores `i64` values exactly, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Integer {
+    value: i64,
+    width: u32, // width of the last written delta, 0 before the first delta
+    first: bool,
+}
+
+impl Integer {
+    pub fn new() -> Self {
+        Integer {
+            value: 0,
+            width: 0,
+            first: true,
+        }
+    }
+}
+
+impl Default for Integer {
+    fn default() -> Self {
+        Integer::new()
+    }
+}
+
+impl Codec for Integer {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        let value = value.as_i64();
+        if self.first {
+            self.first = false;
+            self.value = value;
+            w.write_bits(value as u64, 64);
+            return;
+        }
+
+        let delta = zigzag_encode(value.wrapping_sub(self.value));
+        self.value = value;
+
+        if delta == 0 {
+            w.write_bit(Bit::Zero);
+            return;
+        }
+
+        let width = 64 - delta.leading_zeros();
+        if width <= self.width && width + SHRINK_BITS > self.width {
+            w.write_bits(0b10, 2);
+        } else {
+            w.write_bits(0b11, 2);
+            w.write_bits(u64::from(width - 1), 6);
+            self.width = width;
+        }
+        w.write_bits(delta, self.width);
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        if self.first {
+            self.first = false;
+            self.value = r.read_bits(64)? as i64;
+            return Ok(Value::Integer(self.value));
+        }
+
+        if r.read_bit()? == Bit::Zero {
+            return Ok(Value::Integer(self.value));
+        }
+
+        if r.read_bit()? == Bit::One {
+            self.width = r.read_bits(6)? as u32 + 1;
+        } else if self.width == 0 {
+            return Err(Error::InvalidValue);
+        }
+
+        let delta = zigzag_decode(r.read_bits(self.width)?);
+        self.value = self.value.wrapping_add(delta);
+        Ok(Value::Integer(self.value))
+    }
+}
@@ -0,0 +1,291 @@
+//! Value codecs.
+//!
+//! The timestamps of a block are always delta of delta encoded, the values are compressed by
+//! one of the codecs below. The codec of a version 3 block is recorded in its `BlockHeader`,
+//! older blocks always use Gorilla XOR. The codec also decides the `ValueType` of the block,
+//! values of the other type are converted with `Value::as_f64` or `Value::as_i64`, callers
+//! which must not lose precision check the type before encoding.
+
+use crate::decode::Error;
+use crate::format::CodecId;
+use crate::stream::{Read, Write};
+use crate::Value;
+
+pub mod boolean;
+pub mod chimp;
+pub mod decimal;
+pub mod dictionary;
+pub mod gorilla;
+pub mod integer;
+pub mod sketch;
+
+pub use self::boolean::Boolean;
+pub use self::chimp::{Chimp, Chimp128};
+pub use self::decimal::Decimal;
+pub use self::dictionary::Dictionary;
+pub use self::gorilla::Gorilla;
+pub use self::integer::Integer;
+pub use self::sketch::Sketch;
+
+/// Codec
+///
+/// Codec compresses a stream of values. A codec keeps the state needed to encode or decode
+/// the next value, the same instance must not be used for both.
+pub trait Codec {
+    /// write `value` to `w`
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value);
+
+    /// read the next value from `r`
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error>;
+}
+
+/// ValueCodec
+///
+/// The codec selected by a `CodecId`, used by `StdEncoder` and `StdDecoder`.
+#[derive(Debug, Clone)]
+pub enum ValueCodec {
+    Gorilla(Gorilla),
+    Chimp(Chimp),
+    Chimp128(Chimp128),
+    Decimal(Decimal),
+    Integer(Integer),
+    Boolean(Boolean),
+    Dictionary(Dictionary),
+    Sketch(Sketch),
+}
+
+impl ValueCodec {
+    pub fn new(id: CodecId) -> Self {
+        match id {
+            CodecId::GorillaXor => ValueCodec::Gorilla(Gorilla::new()),
+            CodecId::Chimp => ValueCodec::Chimp(Chimp::new()),
+            CodecId::Chimp128 => ValueCodec::Chimp128(Chimp128::new()),
+            CodecId::Decimal => ValueCodec::Decimal(Decimal::new()),
+            CodecId::Integer => ValueCodec::Integer(Integer::new()),
+            CodecId::Bool => ValueCodec::Boolean(Boolean::new()),
+            CodecId::String => ValueCodec::Dictionary(Dictionary::new()),
+            CodecId::Sketch => ValueCodec::Sketch(Sketch::new()),
+        }
+    }
+
+    pub fn id(&self) -> CodecId {
+        match self {
+            ValueCodec::Gorilla(_) => CodecId::GorillaXor,
+            ValueCodec::Chimp(_) => CodecId::Chimp,
+            ValueCodec::Chimp128(_) => CodecId::Chimp128,
+            ValueCodec::Decimal(_) => CodecId::Decimal,
+            ValueCodec::Integer(_) => CodecId::Integer,
+            ValueCodec::Boolean(_) => CodecId::Bool,
+            ValueCodec::Dictionary(_) => CodecId::String,
+            ValueCodec::Sketch(_) => CodecId::Sketch,
+        }
+    }
+
+    /// a copy of the state needed to decode the next value, the lookup tables only used for
+    /// encoding are left out
+    pub fn snapshot(&self) -> Self {
+        match self {
+            ValueCodec::Chimp128(c) => ValueCodec::Chimp128(c.snapshot()),
+            ValueCodec::Dictionary(c) => ValueCodec::Dictionary(c.snapshot()),
+            other => other.clone(),
+        }
+    }
+}
+
+impl Codec for ValueCodec {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        match self {
+            ValueCodec::Gorilla(c) => c.encode_value(w, value),
+            ValueCodec::Chimp(c) => c.encode_value(w, value),
+            ValueCodec::Chimp128(c) => c.encode_value(w, value),
+            ValueCodec::Decimal(c) => c.encode_value(w, value),
+            ValueCodec::Integer(c) => c.encode_value(w, value),
+            ValueCodec::Boolean(c) => c.encode_value(w, value),
+            ValueCodec::Dictionary(c) => c.encode_value(w, value),
+            ValueCodec::Sketch(c) => c.encode_value(w, value),
+        }
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        match self {
+            ValueCodec::Gorilla(c) => c.decode_value(r),
+            ValueCodec::Chimp(c) => c.decode_value(r),
+            ValueCodec::Chimp128(c) => c.decode_value(r),
+            ValueCodec::Decimal(c) => c.decode_value(r),
+            ValueCodec::Integer(c) => c.decode_value(r),
+            ValueCodec::Boolean(c) => c.decode_value(r),
+            ValueCodec::Dictionary(c) => c.decode_value(r),
+            ValueCodec::Sketch(c) => c.decode_value(r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{Codec, ValueCodec};
+    use crate::format::CodecId;
+    use crate::stream::{BufferedReader, BufferedWriter, Write};
+    use crate::{DDSketch, Value};
+
+    const CODECS: [CodecId; 4] = [
+        CodecId::GorillaXor,
+        CodecId::Chimp,
+        CodecId::Chimp128,
+        CodecId::Decimal,
+    ];
+
+    fn round_trip(id: CodecId, values: &[f64]) -> usize {
+        let mut w = BufferedWriter::new();
+        let mut encoder = ValueCodec::new(id);
+        for v in values {
+            encoder.encode_value(&mut w, Value::Float(*v));
+        }
+        let bytes = w.close();
+        let len = bytes.len();
+
+        let mut r = BufferedReader::new(bytes);
+        let mut decoder = ValueCodec::new(id);
+        for v in values {
+            let decoded = decoder.decode_value(&mut r).unwrap().as_f64();
+            assert_eq!(decoded.to_bits(), v.to_bits(), "{:?} {}", id, v);
+        }
+        len
+    }
+
+    #[test]
+    fn special_values() {
+        let values = [
+            1.0,
+            -0.0,
+            0.0,
+            f64::NAN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            f64::MIN,
+            1e-300,
+            0.1,
+            0.1,
+            12.345,
+            -7.41,
+        ];
+        for id in CODECS.iter() {
+            round_trip(*id, &values);
+        }
+    }
+
+    #[test]
+    fn decimal_series() {
+        // a price like series with two decimal digits
+        let values: Vec<f64> = (0..1000)
+            .map(|i| ((i * 37 % 1000) as f64 + 10000.0) / 100.0)
+            .collect();
+        let mut sizes = Vec::new();
+        for id in CODECS.iter() {
+            sizes.push(round_trip(*id, &values));
+        }
+
+        // the decimal codec beats XOR based codecs on decimal data
+        assert!(sizes[3] < sizes[0]);
+        assert!(sizes[3] < sizes[1]);
+    }
+
+    #[test]
+    fn repeating_series() {
+        // values cycling through a small set are what Chimp128 looks back for
+        let values: Vec<f64> = (0..1000)
+            .map(|i| [12.5071, 48.3329, 7.1093, 0.6637][i % 4])
+            .collect();
+        let gorilla = round_trip(CodecId::GorillaXor, &values);
+        let chimp128 = round_trip(CodecId::Chimp128, &values);
+        assert!(chimp128 < gorilla);
+    }
+
+    #[test]
+    fn integer_series() {
+        let values = [
+            0,
+            1,
+            1,
+            i64::MAX,
+            i64::MIN,
+            -1,
+            9_007_199_254_740_993,
+            9_007_199_254_740_994,
+            42,
+        ];
+        let mut w = BufferedWriter::new();
+        let mut encoder = ValueCodec::new(CodecId::Integer);
+        for v in values.iter() {
+            encoder.encode_value(&mut w, Value::Integer(*v));
+        }
+
+        let mut r = BufferedReader::new(w.close());
+        let mut decoder = ValueCodec::new(CodecId::Integer);
+        for v in values.iter() {
+            assert_eq!(decoder.decode_value(&mut r).unwrap(), Value::Integer(*v));
+        }
+    }
+
+    #[test]
+    fn integer_counter() {
+        // a counter growing by a small amount packs into a few bits per value
+        let mut w = BufferedWriter::new();
+        let mut encoder = ValueCodec::new(CodecId::Integer);
+        let mut counter = 1_000_000_000_000i64;
+        for i in 0..1000 {
+            counter += i % 7;
+            encoder.encode_value(&mut w, Value::Integer(counter));
+        }
+        assert!(w.close().len() < 1000);
+    }
+
+    fn round_trip_values(id: CodecId, values: &[Value]) -> usize {
+        let mut w = BufferedWriter::new();
+        let mut encoder = ValueCodec::new(id);
+        for v in values {
+            encoder.encode_value(&mut w, v.clone());
+        }
+        let bytes = w.close();
+        let len = bytes.len();
+
+        let mut r = BufferedReader::new(bytes);
+        let mut decoder = ValueCodec::new(id);
+        for v in values {
+            assert_eq!(decoder.decode_value(&mut r).unwrap(), *v);
+        }
+        len
+    }
+
+    #[test]
+    fn bool_series() {
+        let values: Vec<Value> = (0..800).map(|i| Value::Bool(i / 100 % 2 == 1)).collect();
+        // one bit per value
+        assert_eq!(round_trip_values(CodecId::Bool, &values), 100);
+    }
+
+    #[test]
+    fn string_series() {
+        let states = ["up", "down", "degraded", "", "ünïcode"];
+        let values: Vec<Value> = (0..1000)
+            .map(|i| Value::from(states[i / 10 % states.len()]))
+            .collect();
+        // a bit per repeated value, ten per switch and the strings once
+        let len = round_trip_values(CodecId::String, &values);
+        assert!(len < 300);
+    }
+
+    #[test]
+    fn sketch_series() {
+        let values: Vec<Value> = (0..100)
+            .map(|i| {
+                let latencies: Vec<f64> = (0..50).map(|j| 1.0 + (i * j % 700) as f64).collect();
+                Value::from(DDSketch::from_values(&latencies).unwrap())
+            })
+            .collect();
+        let len = round_trip_values(CodecId::Sketch, &values);
+        // far below the 8 bytes per latency of the raw values
+        assert!(len < 100 * 50 * 8 / 4);
+    }
+}
@@ -0,0 +1,131 @@
+//! Sketch values: a `DDSketch` per point.
+//!
+//! The relative accuracy is written in full when it changes, a `0` bit repeats the previous
+//! one. Sum, min and max go through their own Gorilla XOR streams since consecutive sketches of
+//! a series usually have close statistics. The zero count follows as a varint, then the
+//! positive and the negative bins, each as a varint count of bins and per bin the varint gap
+//! to the previous index, zigzag encoded, and the varint count of the bin. The total count is
+//! the sum of the bins.
+
+use crate::codec::{Codec, Gorilla};
+use crate::decode::Error;
+use crate::format::{zigzag_decode, zigzag_encode};
+use crate::sketch::{DDSketch, MAX_BINS};
+use crate::stream::{Read, Write};
+use crate::{Bit, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Sketch
+///
+/// Sketch stores `DDSketch` values, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Sketch {
+    alpha: Option<f64>,
+    sum: Gorilla,
+    min: Gorilla,
+    max: Gorilla,
+}
+
+impl Sketch {
+    pub fn new() -> Self {
+        Sketch {
+            alpha: None,
+            sum: Gorilla::new(),
+            min: Gorilla::new(),
+            max: Gorilla::new(),
+        }
+    }
+}
+
+impl Default for Sketch {
+    fn default() -> Self {
+        Sketch::new()
+    }
+}
+
+fn write_bins<W: Write>(w: &mut W, bins: &BTreeMap<i32, u64>) {
+    w.write_varint(bins.len() as u64);
+    let mut previous = 0i64;
+    for (index, count) in bins {
+        w.write_varint(zigzag_encode(i64::from(*index) - previous));
+        w.write_varint(*count);
+        previous = i64::from(*index);
+    }
+}
+
+fn read_bins<R: Read>(r: &mut R) -> Result<BTreeMap<i32, u64>, Error> {
+    let len = r.read_varint()?;
+    if len > MAX_BINS as u64 {
+        return Err(Error::InvalidValue);
+    }
+
+    let mut bins = BTreeMap::new();
+    let mut index = 0i64;
+    for _ in 0..len {
+        index = index.wrapping_add(zigzag_decode(r.read_varint()?));
+        let key = i32::try_from(index).map_err(|_| Error::InvalidValue)?;
+        bins.insert(key, r.read_varint()?);
+    }
+    Ok(bins)
+}
+
+impl Codec for Sketch {
+    fn encode_value<W: Write>(&mut self, w: &mut W, value: Value) {
+        // values of other types are encoded as a sketch of their float value
+        let sketch = match value {
+            Value::Sketch(sketch) => *sketch,
+            other => DDSketch::from_values(&[other.as_f64()])
+                .or_else(|_| DDSketch::from_values(&[]))
+                .unwrap(),
+        };
+
+        if self.alpha == Some(sketch.alpha) {
+            w.write_bit(Bit::Zero);
+        } else {
+            w.write_bit(Bit::One);
+            w.write_bits(sketch.alpha.to_bits(), 64);
+            self.alpha = Some(sketch.alpha);
+        }
+        self.sum.encode_value(w, Value::Float(sketch.sum));
+        self.min.encode_value(w, Value::Float(sketch.min));
+        self.max.encode_value(w, Value::Float(sketch.max));
+        w.write_varint(sketch.zero);
+        write_bins(w, &sketch.positive);
+        write_bins(w, &sketch.negative);
+    }
+
+    fn decode_value<R: Read>(&mut self, r: &mut R) -> Result<Value, Error> {
+        if r.read_bit()? == Bit::One {
+            self.alpha = Some(f64::from_bits(r.read_bits(64)?));
+        }
+        let alpha = self.alpha.ok_or(Error::InvalidValue)?;
+
+        let sum = self.sum.decode_value(r)?.as_f64();
+        let min = self.min.decode_value(r)?.as_f64();
+        let max = self.max.decode_value(r)?.as_f64();
+        let zero = r.read_varint()?;
+        let positive = read_bins(r)?;
+        let negative = read_bins(r)?;
+        let count = positive
+            .values()
+            .chain(negative.values())
+            .try_fold(zero, |a, b| a.checked_add(*b))
+            .ok_or(Error::InvalidValue)?;
+
+        let sketch = DDSketch {
+            alpha,
+            count,
+            sum,
+            min,
+            max,
+            zero,
+            positive,
+            negative,
+        };
+        if !sketch.is_consistent() {
+            return Err(Error::InvalidValue);
+        }
+        Ok(Value::Sketch(Box::new(sketch)))
+    }
+}
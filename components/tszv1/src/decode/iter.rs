@@ -0,0 +1,161 @@
+//! Iterators over the points of a decoder.
+
+use crate::decode::{Decode, Error};
+use crate::DataPoint;
+use std::iter::FusedIterator;
+
+/// Points
+///
+/// The points of a decoder, see `Decode::points`. The iterator ends at the end of the stream,
+/// any other error is yielded once and ends it as well.
+#[derive(Debug)]
+pub struct Points<D: Decode> {
+    decoder: D,
+    done: bool,
+}
+
+impl<D: Decode> Points<D> {
+    pub fn new(decoder: D) -> Self {
+        Points {
+            decoder,
+            done: false,
+        }
+    }
+
+    /// only the points in [begin_time, end_time]
+    pub fn range(self, begin_time: u64, end_time: u64) -> Range<Self> {
+        Range::new(self, begin_time, end_time)
+    }
+
+    pub fn into_inner(self) -> D {
+        self.decoder
+    }
+}
+
+impl<D: Decode> Iterator for Points<D> {
+    type Item = Result<DataPoint, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.decoder.next() {
+            Ok(dp) => Some(Ok(dp)),
+            Err(Error::EndOfStream) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<D: Decode> FusedIterator for Points<D> {}
+
+/// Range
+///
+/// The points of an iterator of decoded points which fall in [begin_time, end_time], errors
+/// are passed through. Blocks may hold out of order points so the whole stream is read.
+#[derive(Debug)]
+pub struct Range<I> {
+    iter: I,
+    begin_time: u64,
+    end_time: u64,
+}
+
+impl<I> Range<I>
+where
+    I: Iterator<Item = Result<DataPoint, Error>>,
+{
+    pub fn new(iter: I, begin_time: u64, end_time: u64) -> Self {
+        Range {
+            iter,
+            begin_time,
+            end_time,
+        }
+    }
+}
+
+impl<I> Iterator for Range<I>
+where
+    I: Iterator<Item = Result<DataPoint, Error>>,
+{
+    type Item = Result<DataPoint, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (begin_time, end_time) = (self.begin_time, self.end_time);
+        self.iter.find(|item| match item {
+            Ok(dp) => dp.time >= begin_time && dp.time <= end_time,
+            Err(_) => true,
+        })
+    }
+}
+
+impl<I> FusedIterator for Range<I> where I: FusedIterator<Item = Result<DataPoint, Error>> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::{Decode, Error};
+    use crate::format::FormatVersion;
+    use crate::stream::{self, BufferedReader, BufferedWriter};
+    use crate::{DataPoint, Encode, StdDecoder, StdEncoder};
+    use common::TimePrecision;
+
+    fn encode(datapoints: &[DataPoint]) -> Box<[u8]> {
+        let mut e = StdEncoder::new(1482268055, BufferedWriter::new());
+        for dp in datapoints {
+            e.encode(dp.clone());
+        }
+        e.close()
+    }
+
+    #[test]
+    fn points() {
+        let datapoints: Vec<DataPoint> = (0..10)
+            .map(|i| DataPoint::new(1482268055 + i * 10, i as f64))
+            .collect();
+        let bytes = encode(&datapoints);
+
+        let decoder = StdDecoder::new(BufferedReader::new(bytes.clone())).unwrap();
+        let decoded: Result<Vec<DataPoint>, Error> = decoder.points().collect();
+        assert_eq!(decoded.unwrap(), datapoints);
+
+        let decoder = StdDecoder::new(BufferedReader::new(bytes)).unwrap();
+        let decoded: Vec<DataPoint> = decoder
+            .points()
+            .range(1482268055 + 20, 1482268055 + 40)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded, datapoints[2..5].to_vec());
+    }
+
+    #[test]
+    fn truncated() {
+        // version 2 streams have no checksum to reject the truncation up front
+        let mut e = StdEncoder::with_version(
+            1482268055,
+            TimePrecision::Seconds,
+            FormatVersion::V2,
+            BufferedWriter::new(),
+        );
+        for i in 0..10 {
+            e.encode(DataPoint::new(1482268055 + i * 10, i as f64 * 1.5));
+        }
+        let mut bytes = e.close().into_vec();
+        bytes.truncate(bytes.len() - 4);
+
+        let decoder = StdDecoder::new(BufferedReader::new(bytes.into_boxed_slice())).unwrap();
+        let mut points = decoder.points();
+        let decoded: Vec<_> = points.by_ref().collect();
+        assert!(decoded.len() < 10);
+        assert_eq!(
+            decoded.last().unwrap().as_ref().err(),
+            Some(&Error::Stream(stream::Error::EOF))
+        );
+        assert!(points.next().is_none());
+    }
+}
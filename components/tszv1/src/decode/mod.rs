@@ -1,6 +1,7 @@
-use std::{error, fmt};
+use crate::format::HeaderError;
 use crate::stream;
 use crate::DataPoint;
+use std::{error, fmt};
 
 /// Error
 ///
@@ -11,6 +12,11 @@ pub enum Error {
     InvalidInitialTimestamp,
     InvalidEndOfStream,
     EndOfStream,
+    /// the block header carries a format version this decoder does not know
+    UnsupportedVersion(u8),
+    /// the block header is malformed or the payload does not match its checksum
+    InvalidHeader(HeaderError),
+    InvalidValue,
 }
 
 impl fmt::Display for Error {
@@ -20,6 +26,11 @@ impl fmt::Display for Error {
             Error::InvalidInitialTimestamp => write!(f, "Failed to parse intitial timestamp"),
             Error::InvalidEndOfStream => write!(f, "Encountered invalid end of steam marker"),
             Error::EndOfStream => write!(f, "Encountered end of the stream"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "Unsupported block format version {}", version)
+            }
+            Error::InvalidHeader(ref err) => write!(f, "Encountered invalid block header: {}", err),
+            Error::InvalidValue => write!(f, "Encountered invalid encoded value"),
         }
     }
 }
@@ -31,6 +42,9 @@ impl error::Error for Error {
             Error::InvalidInitialTimestamp => "Failed to parse initial timestamp",
             Error::InvalidEndOfStream => "Encountered invalid end of steam marker",
             Error::EndOfStream => "Encountered end of the stream",
+            Error::UnsupportedVersion(_) => "Unsupported block format version",
+            Error::InvalidHeader(_) => "Encountered invalid block header",
+            Error::InvalidValue => "Encountered invalid encoded value",
         }
     }
 }
@@ -46,6 +60,18 @@ impl From<stream::Error> for Error {
 /// Decode is the trait used to encapsulate decoding `DataPoint`s
 pub trait Decode {
     fn next(&mut self) -> Result<DataPoint, Error>;
+
+    /// the remaining points as an iterator, which tells the end of the stream apart from a
+    /// corrupted stream
+    fn points(self) -> Points<Self>
+    where
+        Self: Sized,
+    {
+        Points::new(self)
+    }
 }
 
+pub mod iter;
+pub use self::iter::{Points, Range};
+
 pub mod std_decoder;
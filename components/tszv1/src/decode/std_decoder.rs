@@ -1,23 +1,32 @@
-use crate::decode::{Decode, Error};
-use crate::encode::std_encoder::{END_MARKER, END_MARKER_LEN};
+use crate::codec::{Codec, ValueCodec};
+use crate::decode::{Decode, Error, Points};
+use crate::encode::std_encoder::TimestampLayout;
+use crate::format::{
+    precision_from_u8, zigzag_decode, BlockHeader, CodecId, FormatVersion, HeaderError,
+    BLOCK_MAGIC, BLOCK_MAGIC_LEN, HEADER_LEN,
+};
+use crate::index::{BlockIndex, Checkpoint};
 use crate::stream::Read;
 use crate::{Bit, DataPoint};
+use common::TimePrecision;
 
 /// StdDecoder
 ///
 /// StdDecoder is used to decode `DataPoint`s
 #[derive(Debug)]
 pub struct StdDecoder<T: Read> {
-    time: u64,       // current time
-    delta: u64,      // current time delta
-    value_bits: u64, // current float value as bits
-    xor: u64,        // current xor
-
-    leading_zeroes: u32,  // leading zeroes
-    trailing_zeroes: u32, // trailing zeroes
+    start: u64,        // initial timestamp of the stream
+    time: u64,         // current time
+    delta: u64,        // current time delta
+    codec: ValueCodec, // decompresses the values, chosen by the header of the stream
 
     first: bool, // will next DataPoint be the first DataPoint decoded
     done: bool,
+    count: u32, // DataPoints decoded so far
+
+    version: FormatVersion, // detected from the header of the stream
+    layout: TimestampLayout,
+    header: Option<BlockHeader>, // header of a version 3 stream
 
     r: T,
 }
@@ -26,61 +35,207 @@ impl<T> StdDecoder<T>
 where
     T: Read,
 {
-    /// new creates a new StdDecoder which will read bytes from r
-    pub fn new(r: T) -> Self {
-        StdDecoder {
+    /// new creates a new StdDecoder which will read bytes from r, the header of the stream is
+    /// read and validated before returning. The checksum of a sealed block is not checked, see
+    /// `verify`.
+    pub fn new(r: T) -> Result<Self, Error> {
+        StdDecoder::with_precision(r, TimePrecision::Seconds)
+    }
+
+    /// with_precision creates a new StdDecoder, `precision` is only used for version 1 streams
+    /// which do not record their precision
+    pub fn with_precision(r: T, precision: TimePrecision) -> Result<Self, Error> {
+        let mut decoder = StdDecoder {
+            start: 0,
             time: 0,
             delta: 0,
-            value_bits: 0,
-            xor: 0,
-            leading_zeroes: 0,
-            trailing_zeroes: 0,
+            codec: ValueCodec::new(CodecId::GorillaXor),
             first: true,
             done: false,
+            count: 0,
+            version: FormatVersion::V1,
+            layout: TimestampLayout::new(precision),
+            header: None,
             r,
+        };
+        decoder.read_header()?;
+        Ok(decoder)
+    }
+
+    /// the format version of the stream
+    pub fn version(&self) -> FormatVersion {
+        self.version
+    }
+
+    /// the initial timestamp of the stream, the first point is at or after it
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// the header of a version 3 stream
+    pub fn header(&self) -> Option<&BlockHeader> {
+        self.header.as_ref()
+    }
+
+    /// check the payload of a sealed version 3 stream against the checksum in its header. This
+    /// reads the whole stream, so blocks are verified once when they are read back from storage
+    /// rather than by every decoder. Other streams have no checksum and always pass.
+    pub fn verify(&self) -> Result<(), Error> {
+        match self.header {
+            Some(ref header) if header.sealed => {
+                let actual = self.r.checksum(HEADER_LEN);
+                if actual != header.crc {
+                    return Err(Error::InvalidHeader(HeaderError::Checksum {
+                        expected: header.crc,
+                        actual,
+                    }));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
         }
     }
 
-    fn read_initial_timestamp(&mut self) -> Result<u64, Error> {
+    /// skip ahead to the last checkpoint of `index` after which every point at or after `time`
+    /// lies, the points returned next may still be before `time`. `index` must belong to the
+    /// stream. Nothing is skipped when no checkpoint qualifies or the decoder is already past
+    /// it.
+    pub fn seek(&mut self, index: &BlockIndex, time: u64) -> Result<(), Error> {
+        let checkpoint = match index.find(time) {
+            Some(checkpoint) if checkpoint.count > self.count && !self.done => checkpoint,
+            _ => return Ok(()),
+        };
+
+        self.r.seek_bit(checkpoint.offset)?;
+        self.time = checkpoint.time;
+        self.delta = checkpoint.delta;
+        self.codec = checkpoint.codec.clone();
+        self.first = false;
+        self.count = checkpoint.count;
+        Ok(())
+    }
+
+    /// decode the rest of the stream and record a checkpoint every `interval` points as the
+    /// encoder does, for blocks whose index was not kept such as blocks read back from storage
+    /// or rewritten
+    pub fn build_index(mut self, interval: u32) -> Result<BlockIndex, Error> {
+        let mut index = BlockIndex::new(interval);
+        let mut max_time = None;
+        loop {
+            let dp = match self.next() {
+                Ok(dp) => dp,
+                Err(Error::EndOfStream) => return Ok(index),
+                Err(err) => return Err(err),
+            };
+            let max = max_time.map_or(dp.time, |max: u64| max.max(dp.time));
+            max_time = Some(max);
+            // the first point has no checkpoint
+            if self.count > 1 && index.is_due(self.count) {
+                index.push(Checkpoint {
+                    offset: self.r.bit_offset(),
+                    count: self.count,
+                    max_time: max,
+                    time: self.time,
+                    delta: self.delta,
+                    codec: self.codec.snapshot(),
+                });
+            }
+        }
+    }
+
+    fn read_header(&mut self) -> Result<(), Error> {
+        let magic = self
+            .r
+            .peak_bits(BLOCK_MAGIC_LEN)
+            .map_err(|_| Error::InvalidInitialTimestamp)?;
+        if magic == BLOCK_MAGIC {
+            let version = (self.r.peak_bits(BLOCK_MAGIC_LEN + 8)? & 0xff) as u8;
+            self.version =
+                FormatVersion::from_u8(version).ok_or(Error::UnsupportedVersion(version))?;
+
+            match self.version {
+                FormatVersion::V1 => return Err(Error::InvalidHeader(HeaderError::Version(1))),
+                FormatVersion::V2 => {
+                    self.r.read_bits(BLOCK_MAGIC_LEN + 8)?;
+                    let precision = self.r.read_bits(8)? as u8;
+                    let precision = precision_from_u8(precision)
+                        .ok_or(Error::InvalidHeader(HeaderError::Precision(precision)))?;
+                    self.layout = TimestampLayout::new(precision);
+                }
+                FormatVersion::V3 => return self.read_block_header(),
+            }
+        }
+
+        // version 1 and 2 streams go on with the initial timestamp
         self.r
             .read_bits(64)
             .map_err(|_| Error::InvalidInitialTimestamp)
             .map(|time| {
+                self.start = time;
                 self.time = time;
-                time
             })
     }
 
-    fn read_first_timestamp(&mut self) -> Result<u64, Error> {
-        self.read_initial_timestamp()?;
+    fn read_block_header(&mut self) -> Result<(), Error> {
+        let mut bytes = [0u8; HEADER_LEN];
+        for byte in bytes.iter_mut() {
+            *byte = self
+                .r
+                .read_byte()
+                .map_err(|_| Error::InvalidHeader(HeaderError::Truncated))?;
+        }
+        let header = BlockHeader::from_bytes(&bytes).map_err(Error::InvalidHeader)?;
+
+        self.layout = TimestampLayout::new(header.precision);
+        self.codec = ValueCodec::new(header.codec);
+        self.start = header.start;
+        self.time = header.start;
+        self.header = Some(header);
+        Ok(())
+    }
 
+    fn read_first_timestamp(&mut self) -> Result<u64, Error> {
         // sanity check to confirm that the stream contains more than just the initial timestamp
         let control_bit = self.r.peak_bits(1)?;
         if control_bit == 1 {
-            return self
-                .r
-                .read_bits(END_MARKER_LEN)
-                .map_err(Error::Stream)
-                .and_then(|marker| {
-                    if marker == END_MARKER {
-                        Err(Error::EndOfStream)
-                    } else {
-                        Err(Error::InvalidEndOfStream)
-                    }
-                });
+            let control_bits = self.r.read_bits(4)?;
+            let dod = self.read_fallback_dod()?;
+            return if control_bits == 0b1111 && dod == 0 {
+                Err(Error::EndOfStream)
+            } else {
+                Err(Error::InvalidEndOfStream)
+            };
         }
 
         // stream contains datapoints so we can throw away the control bit
         self.r.read_bit()?;
 
-        self.r.read_bits(14).map(|delta| {
-            self.delta = delta;
-            self.time += delta;
-        })?;
+        let delta = match self.version {
+            FormatVersion::V1 => self.r.read_bits(self.layout.first_delta_bits)?,
+            _ => self.r.read_varint()?,
+        };
+        self.delta = delta;
+        self.time = self.time.wrapping_add(delta);
 
         Ok(self.time)
     }
 
+    /// read the delta of delta following the control bits 1111, zero marks the end of stream
+    fn read_fallback_dod(&mut self) -> Result<u64, Error> {
+        match self.version {
+            FormatVersion::V1 => {
+                // the fallback delta of delta is stored in two's complement
+                let size = self.layout.fallback_bits;
+                let mut dod = self.r.read_bits(size)?;
+                if size < 64 && dod >> (size - 1) == 1 {
+                    dod |= u64::MAX << size;
+                }
+                Ok(dod)
+            }
+            _ => Ok(zigzag_decode(self.r.read_varint()?) as u64),
+        }
+    }
+
     fn read_next_timestamp(&mut self) -> Result<u64, Error> {
         let mut control_bits = 0;
         for _ in 0..4 {
@@ -98,17 +253,18 @@ where
                 self.time += self.delta;
                 return Ok(self.time);
             }
-            1 => 7,
-            2 => 9,
-            3 => 12,
+            1 => self.layout.dod_bits[0],
+            2 => self.layout.dod_bits[1],
+            3 => self.layout.dod_bits[2],
             4 => {
-                return self.r.read_bits(32).map_err(Error::Stream).and_then(|dod| {
-                    if dod == 0 {
-                        Err(Error::EndOfStream)
-                    } else {
-                        Ok(dod)
-                    }
-                });
+                let dod = self.read_fallback_dod()?;
+                if dod == 0 {
+                    return Err(Error::EndOfStream);
+                }
+
+                self.delta = self.delta.wrapping_add(dod);
+                self.time = self.time.wrapping_add(self.delta);
+                return Ok(self.time);
             }
             _ => unreachable!(),
         };
@@ -116,7 +272,7 @@ where
         let mut dod = self.r.read_bits(size)?;
 
         // need to sign extend negative numbers
-        if dod > (1 << (size - 1)) as u64 {
+        if dod > 1u64 << (size - 1) {
             let mask = u64::max_value() << size as u64;
             dod |= mask;
         }
@@ -127,35 +283,6 @@ where
 
         Ok(self.time)
     }
-
-    fn read_first_value(&mut self) -> Result<u64, Error> {
-        self.r.read_bits(64).map_err(Error::Stream).map(|bits| {
-            self.value_bits = bits;
-            self.value_bits
-        })
-    }
-
-    fn read_next_value(&mut self) -> Result<u64, Error> {
-        let contol_bit = self.r.read_bit()?;
-
-        if contol_bit == Bit::Zero {
-            return Ok(self.value_bits);
-        }
-
-        let zeroes_bit = self.r.read_bit()?;
-
-        if zeroes_bit == Bit::One {
-            self.leading_zeroes = self.r.read_bits(6).map(|n| n as u32)?;
-            let significant_digits = self.r.read_bits(6).map(|n| (n + 1) as u32)?;
-            self.trailing_zeroes = 64 - self.leading_zeroes - significant_digits;
-        }
-
-        let size = 64 - self.leading_zeroes - self.trailing_zeroes;
-        self.r.read_bits(size).map_err(Error::Stream).map(|bits| {
-            self.value_bits ^= bits << self.trailing_zeroes as u64;
-            self.value_bits
-        })
-    }
 }
 
 impl<T> Decode for StdDecoder<T>
@@ -167,44 +294,51 @@ where
             return Err(Error::EndOfStream);
         }
 
-        let time;
-        let value_bits = if self.first {
+        let time = if self.first {
             self.first = false;
-            time = self.read_first_timestamp().map_err(|err| {
-                if err == Error::EndOfStream {
-                    self.done = true;
-                }
-                err
-            })?;
-            self.read_first_value()?
+            self.read_first_timestamp()
         } else {
-            time = self.read_next_timestamp().map_err(|err| {
-                if err == Error::EndOfStream {
-                    self.done = true;
-                }
-                err
-            })?;
-            self.read_next_value()?
-        };
+            self.read_next_timestamp()
+        }
+        .map_err(|err| {
+            if err == Error::EndOfStream {
+                self.done = true;
+            }
+            err
+        })?;
+        let value = self.codec.decode_value(&mut self.r)?;
+        self.count += 1;
+
+        Ok(DataPoint { time, value })
+    }
+}
 
-        let value = f64::from_bits(value_bits);
+impl<T> IntoIterator for StdDecoder<T>
+where
+    T: Read,
+{
+    type Item = Result<DataPoint, Error>;
+    type IntoIter = Points<Self>;
 
-        Ok(DataPoint::new(time, value))
+    fn into_iter(self) -> Points<Self> {
+        Points::new(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::StdDecoder;
     use crate::decode::Error;
-    use crate::stream::BufferedReader;
-    use crate::{DataPoint, Decode};
+    use crate::format::{CodecId, FormatVersion, HeaderError, HEADER_LEN};
+    use crate::stream::{BufferedReader, BufferedWriter, SharedWriter};
+    use crate::{DataPoint, Decode, Encode, Value};
+    use crate::{StdDecoder, StdEncoder};
+    use common::TimePrecision;
 
     #[test]
     fn create_new_decoder() {
         let bytes = vec![0, 0, 0, 0, 88, 89, 157, 151, 240, 0, 0, 0, 0];
         let r = BufferedReader::new(bytes.into_boxed_slice());
-        let mut decoder = StdDecoder::new(r);
+        let mut decoder = StdDecoder::new(r).unwrap();
 
         assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
     }
@@ -216,7 +350,7 @@ mod tests {
             0, 0,
         ];
         let r = BufferedReader::new(bytes.into_boxed_slice());
-        let mut decoder = StdDecoder::new(r);
+        let mut decoder = StdDecoder::new(r).unwrap();
 
         let expected_datapoint = DataPoint::new(1482268055 + 10, 1.24);
 
@@ -232,7 +366,7 @@ mod tests {
             245, 97, 88, 86, 21, 133, 55, 202, 1, 17, 15, 92, 40, 245, 194, 151, 128, 0, 0, 0, 0,
         ];
         let r = BufferedReader::new(bytes.into_boxed_slice());
-        let mut decoder = StdDecoder::new(r);
+        let mut decoder = StdDecoder::new(r).unwrap();
 
         let first_expected_datapoint = DataPoint::new(1482268055 + 10, 1.24);
         let second_expected_datapoint = DataPoint::new(1482268055 + 20, 1.98);
@@ -247,4 +381,264 @@ mod tests {
         assert_eq!(decoder.next().unwrap(), fifth_expected_datapoint);
         assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
     }
+
+    #[test]
+    fn decode_v2_sparse_datapoints() {
+        // a first delta of one day and a gap of 60 days in millisecond precision overflow the
+        // fixed width fields of version 1
+        let start = 1482268055000;
+        let day = 24 * 60 * 60 * 1000;
+        let datapoints = vec![
+            DataPoint::new(start + day, 1.0),
+            DataPoint::new(start + day + 10, 2.0),
+            DataPoint::new(start + 61 * day, 3.0),
+            DataPoint::new(start + 61 * day + 10, 4.0),
+        ];
+
+        let w = BufferedWriter::new();
+        let mut e = StdEncoder::with_version(start, TimePrecision::Millis, FormatVersion::V2, w);
+        for dp in &datapoints {
+            e.encode(dp.clone());
+        }
+
+        // the precision is read from the header, the argument only applies to version 1
+        let r = BufferedReader::new(e.close());
+        let mut decoder = StdDecoder::with_precision(r, TimePrecision::Seconds).unwrap();
+        for dp in &datapoints {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+        assert_eq!(decoder.version(), FormatVersion::V2);
+    }
+
+    #[test]
+    fn decode_v1_with_precision() {
+        let start = 1482268055000;
+        let datapoints = vec![
+            DataPoint::new(start + 1000, 1.0),
+            DataPoint::new(start + 2003, 2.0),
+            DataPoint::new(start + 3001, 3.0),
+        ];
+
+        let w = BufferedWriter::new();
+        let mut e = StdEncoder::with_version(start, TimePrecision::Millis, FormatVersion::V1, w);
+        for dp in &datapoints {
+            e.encode(dp.clone());
+        }
+
+        let r = BufferedReader::new(e.close());
+        let mut decoder = StdDecoder::with_precision(r, TimePrecision::Millis).unwrap();
+        for dp in &datapoints {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+        assert_eq!(decoder.version(), FormatVersion::V1);
+    }
+
+    #[test]
+    fn decode_negative_jitter() {
+        // one second intervals with a late point, the delta of delta of the late point is
+        // negative and lands in the widest bucket of the precision
+        let cases = [
+            (TimePrecision::Micros, 1_000_000, 100_000),
+            (TimePrecision::Nanos, 1_000_000_000, 1_000_000),
+        ];
+        for (precision, second, jitter) in cases {
+            let start = 1482268055 * second;
+            let datapoints = vec![
+                DataPoint::new(start + second, 1.0),
+                DataPoint::new(start + 2 * second, 2.0),
+                DataPoint::new(start + 3 * second - jitter, 3.0),
+                DataPoint::new(start + 4 * second, 4.0),
+                DataPoint::new(start + 5 * second - jitter, 5.0),
+            ];
+
+            for version in [FormatVersion::V1, FormatVersion::V3] {
+                let w = BufferedWriter::new();
+                let mut e = StdEncoder::with_version(start, precision, version, w);
+                for dp in &datapoints {
+                    e.encode(dp.clone());
+                }
+
+                let r = BufferedReader::new(e.close());
+                let mut decoder = StdDecoder::with_precision(r, precision).unwrap();
+                for dp in &datapoints {
+                    assert_eq!(decoder.next().unwrap(), *dp);
+                }
+                assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_unsupported_version() {
+        let bytes = vec![254, 90, 9, 0, 0, 0, 0, 0, 88, 89, 157, 151, 240, 0];
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let decoder = StdDecoder::new(r);
+
+        assert_eq!(decoder.err().unwrap(), Error::UnsupportedVersion(9));
+    }
+
+    fn v3_block(datapoints: &[DataPoint]) -> Box<[u8]> {
+        let w = BufferedWriter::new();
+        let mut e = StdEncoder::with_precision(1482268055, TimePrecision::Seconds, w);
+        for dp in datapoints {
+            e.encode(dp.clone());
+        }
+        e.close()
+    }
+
+    #[test]
+    fn decode_v3_header() {
+        let datapoints = vec![
+            DataPoint::new(1482268055 + 10, 1.24),
+            DataPoint::new(1482268055 + 20, f64::NAN),
+            DataPoint::new(1482268055 + 32, -7.41),
+            DataPoint::new(1482268055 + 44, 103.5),
+        ];
+
+        let r = BufferedReader::new(v3_block(&datapoints));
+        let mut decoder = StdDecoder::new(r).unwrap();
+        assert_eq!(decoder.version(), FormatVersion::V3);
+
+        let header = decoder.header().unwrap().clone();
+        assert!(header.sealed);
+        assert_eq!(header.count, 4);
+        assert_eq!(header.start, 1482268055);
+        assert_eq!(header.min_time, 1482268055 + 10);
+        assert_eq!(header.max_time, 1482268055 + 44);
+        assert_eq!(header.min_value, Some(Value::Float(-7.41)));
+        assert_eq!(header.max_value, Some(Value::Float(103.5)));
+
+        assert_eq!(decoder.next().unwrap(), datapoints[0]);
+        assert!(decoder.next().unwrap().value.is_nan());
+        assert_eq!(decoder.next().unwrap(), datapoints[2]);
+        assert_eq!(decoder.next().unwrap(), datapoints[3]);
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn decode_v3_corrupted_payload() {
+        let datapoints = vec![DataPoint::new(1482268055 + 10, 1.24)];
+        let mut bytes = v3_block(&datapoints);
+        bytes[HEADER_LEN + 2] ^= 0b0000_0100;
+
+        let r = BufferedReader::new(bytes);
+        let decoder = StdDecoder::new(r).unwrap();
+        match decoder.verify().err().unwrap() {
+            Error::InvalidHeader(HeaderError::Checksum { .. }) => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        let r = BufferedReader::new(v3_block(&datapoints));
+        assert_eq!(StdDecoder::new(r).unwrap().verify(), Ok(()));
+    }
+
+    #[test]
+    fn decode_v3_unsealed() {
+        // a block which is still being written has an unsealed header and no end marker
+        let w = BufferedWriter::new();
+        let mut e = StdEncoder::new(1482268055, w);
+        let dp = DataPoint::new(1482268055 + 10, 1.24);
+        e.encode(dp.clone());
+
+        let mut bytes = e.clone().close().into_vec();
+        bytes[..HEADER_LEN].copy_from_slice(&e.header().to_bytes()[..]);
+        assert!(!e.header().sealed);
+
+        let r = BufferedReader::new(bytes.into_boxed_slice());
+        let mut decoder = StdDecoder::new(r).unwrap();
+        assert!(!decoder.header().unwrap().sealed);
+        assert_eq!(decoder.verify(), Ok(()));
+        assert_eq!(decoder.next().unwrap(), dp);
+    }
+
+    #[test]
+    fn seek() {
+        let start = 1482268055;
+        let mut datapoints: Vec<DataPoint> = (0..2000)
+            .map(|i| DataPoint::new(start + 10 * i, (i % 17) as f64 * 0.25))
+            .collect();
+        // an out of order point holds back the checkpoints after it
+        datapoints[150].time = start + 10 * 1950;
+
+        for codec in [CodecId::GorillaXor, CodecId::Chimp128, CodecId::Decimal].iter() {
+            let w = BufferedWriter::new();
+            let mut e = StdEncoder::with_codec(start, TimePrecision::Seconds, *codec, w);
+            e.set_checkpoint_interval(100);
+            for dp in &datapoints {
+                e.encode(dp.clone());
+            }
+            let index = e.index().clone();
+            assert_eq!(index.checkpoints().len(), 20);
+            let bytes = e.close();
+
+            // the index rebuilt from the bytes matches the one of the encoder
+            let decoder = StdDecoder::new(BufferedReader::new(bytes.clone())).unwrap();
+            let rebuilt = decoder.build_index(100).unwrap();
+            assert_eq!(rebuilt.checkpoints().len(), 20);
+            for (a, b) in index.checkpoints().iter().zip(rebuilt.checkpoints()) {
+                assert_eq!(
+                    (a.offset, a.count, a.max_time, a.time, a.delta),
+                    (b.offset, b.count, b.max_time, b.time, b.delta)
+                );
+            }
+            let mut decoder = StdDecoder::new(BufferedReader::new(bytes.clone())).unwrap();
+            decoder.seek(&rebuilt, start + 10 * 1960).unwrap();
+            assert_eq!(decoder.next().unwrap(), datapoints[1900]);
+
+            let mut decoder = StdDecoder::new(BufferedReader::new(bytes.clone())).unwrap();
+            decoder.seek(&index, start + 10 * 1234).unwrap();
+            for dp in &datapoints[100..] {
+                assert_eq!(decoder.next().unwrap(), *dp);
+            }
+            assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+
+            let mut decoder = StdDecoder::new(BufferedReader::new(bytes.clone())).unwrap();
+            decoder.seek(&index, start + 10 * 1960).unwrap();
+            assert_eq!(decoder.next().unwrap(), datapoints[1900]);
+
+            // a time before the first checkpoint reads from the start
+            let mut decoder = StdDecoder::new(BufferedReader::new(bytes)).unwrap();
+            decoder.seek(&index, start).unwrap();
+            assert_eq!(decoder.next().unwrap(), datapoints[0]);
+        }
+    }
+
+    #[test]
+    fn decode_snapshot() {
+        let start = 1482268055;
+        let datapoints: Vec<DataPoint> = (0..3000)
+            .map(|i| DataPoint::new(start + 10 * i, (i % 29) as f64 * 1.5))
+            .collect();
+
+        for version in [FormatVersion::V1, FormatVersion::V2, FormatVersion::V3].iter() {
+            let w = SharedWriter::new();
+            let mut e = StdEncoder::with_version(start, TimePrecision::Seconds, *version, w);
+            let empty = e.snapshot();
+            for dp in &datapoints[..1000] {
+                e.encode(dp.clone());
+            }
+            let snapshot = e.snapshot();
+            for dp in &datapoints[1000..] {
+                e.encode(dp.clone());
+            }
+
+            // a snapshot only sees the points encoded before it was taken
+            let mut decoder = StdDecoder::new(empty).unwrap();
+            assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+            let mut decoder = StdDecoder::new(snapshot).unwrap();
+            for dp in &datapoints[..1000] {
+                assert_eq!(decoder.next().unwrap(), *dp);
+            }
+            assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+
+            let mut decoder = StdDecoder::new(e.snapshot()).unwrap();
+            decoder.seek(e.index(), start + 10 * 2500).unwrap();
+            assert_eq!(decoder.next().unwrap(), datapoints[2048]);
+
+            let r = BufferedReader::new(e.close());
+            assert_eq!(StdDecoder::new(r).unwrap().points().count(), 3000);
+        }
+    }
 }
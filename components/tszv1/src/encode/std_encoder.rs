@@ -1,20 +1,89 @@
-use std::mem;
-
+use crate::codec::{Codec, ValueCodec};
 use crate::encode::Encode;
-use crate::stream::Write;
-use crate::{Bit, DataPoint};
+use crate::format::{
+    checksum, precision_to_u8, zigzag_encode, BlockHeader, CodecId, FormatVersion, BLOCK_MAGIC,
+    BLOCK_MAGIC_LEN, HEADER_LEN,
+};
+use crate::index::{BlockIndex, Checkpoint};
+use crate::stream::{SharedReader, SharedWriter, Write};
+use crate::{Bit, DataPoint, Value};
+use common::TimePrecision;
 
 // END_MARKER relies on the fact that when we encode the delta of delta for a number that requires
 // more than 12 bits we write four control bits 1111 followed by the 32 bits of the value. Since
 // encoding assumes the value is greater than 12 bits, we can store the value 0 to signal the end
 // of the stream
 
-/// END_MARKER is a special bit sequence used to indicate the end of the stream
+/// END_MARKER is a special bit sequence used to indicate the end of a version 1 stream of second
+/// precision
 pub const END_MARKER: u64 = 0b1111_0000_0000_0000_0000_0000_0000_0000_0000;
 
 /// END_MARKER_LEN is the length, in bits, of END_MARKER
 pub const END_MARKER_LEN: u32 = 36;
 
+/// V2_END_MARKER ends a version 2 stream: the control bits 1111 followed by a zero delta of
+/// delta written as a one byte varint
+pub const V2_END_MARKER: u64 = 0b1111_0000_0000;
+
+/// V2_END_MARKER_LEN is the length, in bits, of V2_END_MARKER
+pub const V2_END_MARKER_LEN: u32 = 12;
+
+/// TimestampLayout
+///
+/// The bit widths used to store timestamps of a given precision. Second precision keeps the
+/// original Gorilla layout, finer precisions widen the first delta so that it still spans a
+/// block window and shift the delta of delta buckets so that the usual jitter of sub-second
+/// clocks stays in the short buckets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimestampLayout {
+    /// width of the delta between the header timestamp and the first data point, version 1 only
+    pub first_delta_bits: u32,
+    /// widths of the delta of delta buckets selected by the control bits 10, 110 and 1110
+    pub dod_bits: [u32; 3],
+    /// width of the delta of delta selected by the control bits 1111, version 1 only
+    pub fallback_bits: u32,
+}
+
+impl TimestampLayout {
+    pub fn new(precision: TimePrecision) -> Self {
+        match precision {
+            TimePrecision::Seconds => TimestampLayout {
+                first_delta_bits: 14,
+                dod_bits: [7, 9, 12],
+                fallback_bits: 32,
+            },
+            TimePrecision::Millis => TimestampLayout {
+                first_delta_bits: 24,
+                dod_bits: [7, 12, 20],
+                fallback_bits: 32,
+            },
+            TimePrecision::Micros => TimestampLayout {
+                first_delta_bits: 34,
+                dod_bits: [10, 16, 24],
+                fallback_bits: 64,
+            },
+            TimePrecision::Nanos => TimestampLayout {
+                first_delta_bits: 44,
+                dod_bits: [14, 20, 32],
+                fallback_bits: 64,
+            },
+        }
+    }
+
+    /// the end of stream marker is the control bits 1111 followed by a zero fallback delta of
+    /// delta, for second precision this is `END_MARKER`
+    pub fn end_marker_len(&self) -> u32 {
+        4 + self.fallback_bits
+    }
+
+    /// whether `dod` can be stored in a bucket of `bits` bits, a bucket of n bits holds the
+    /// range [-(2^(n-1) - 1), 2^(n-1)]
+    pub fn fits(dod: i64, bits: u32) -> bool {
+        let bound = 1i64 << (bits - 1);
+        dod > -bound && dod <= bound
+    }
+}
+
 /// StdEncoder
 ///
 /// StdEncoder is used to encode `DataPoint`s
@@ -24,15 +93,15 @@ pub struct StdEncoder<T: Write> {
     // current time
     delta: u64,
     // current time delta
-    value_bits: u64, // current float value as bits
-
-    // store the number of leading and trailing zeroes in the current xor as u32 so we
-    // don't have to do any conversions after calling `leading_zeros` and `trailing_zeros`
-    leading_zeroes: u32,
-    trailing_zeroes: u32,
+    codec: ValueCodec, // compresses the values
 
     first: bool, // will next DataPoint be the first DataPoint encoded
 
+    version: FormatVersion,
+    layout: TimestampLayout,
+    header: BlockHeader, // statistics written to the header of a version 3 block
+    index: BlockIndex,   // checkpoints for `StdDecoder::seek`
+
     w: T,
     size: u64,
 }
@@ -44,123 +113,176 @@ where
     /// new creates a new StdEncoder whose starting timestamp is `start` and writes its encoded
     /// bytes to `w`
     pub fn new(start: u64, w: T) -> Self {
+        StdEncoder::with_precision(start, TimePrecision::Seconds, w)
+    }
+
+    /// with_precision creates a new StdEncoder for timestamps of the given precision
+    pub fn with_precision(start: u64, precision: TimePrecision, w: T) -> Self {
+        StdEncoder::with_version(start, precision, FormatVersion::CURRENT, w)
+    }
+
+    /// with_codec creates a new StdEncoder whose values are compressed by `codec`, the codec
+    /// is recorded in the block header
+    pub fn with_codec(start: u64, precision: TimePrecision, codec: CodecId, w: T) -> Self {
+        StdEncoder::build(start, precision, FormatVersion::CURRENT, codec, w)
+    }
+
+    /// with_version creates a new StdEncoder writing the given block format version. Version 1
+    /// is only kept to produce blocks for old readers, it silently corrupts first deltas and
+    /// delta of deltas which do not fit its fixed width fields, and its decoder must be told
+    /// the precision. Versions 1 and 2 always use Gorilla XOR for values.
+    pub fn with_version(
+        start: u64,
+        precision: TimePrecision,
+        version: FormatVersion,
+        w: T,
+    ) -> Self {
+        StdEncoder::build(start, precision, version, CodecId::GorillaXor, w)
+    }
+
+    fn build(
+        start: u64,
+        precision: TimePrecision,
+        version: FormatVersion,
+        codec: CodecId,
+        w: T,
+    ) -> Self {
         let mut e = StdEncoder {
             time: start,
             delta: 0,
-            value_bits: 0,
-            leading_zeroes: 64,  // 64 is an initial sentinel value
-            trailing_zeroes: 64, // 64 is an intitial sentinel value
+            codec: ValueCodec::new(codec),
             first: true,
+            version,
+            layout: TimestampLayout::new(precision),
+            header: BlockHeader::new(codec, precision, start),
+            index: BlockIndex::default(),
             w,
             size: 0,
         };
 
-        // write timestamp header
-        e.w.write_bits(start, 64);
+        match version {
+            // version 1 blocks have no header
+            FormatVersion::V1 => e.w.write_bits(start, 64),
+            FormatVersion::V2 => {
+                e.w.write_bits(BLOCK_MAGIC, BLOCK_MAGIC_LEN);
+                e.w.write_bits(u64::from(version.to_u8()), 8);
+                e.w.write_bits(u64::from(precision_to_u8(precision)), 8);
+                e.w.write_bits(start, 64);
+            }
+            // the header is written unsealed and patched in place by `close`, it holds the
+            // start time
+            FormatVersion::V3 => {
+                for byte in e.header.to_bytes().iter() {
+                    e.w.write_byte(*byte);
+                }
+            }
+        }
+        e.w.commit();
 
         e
     }
 
+    /// the header of a version 3 block with the statistics of the points encoded so far
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// record a checkpoint every `interval` points from now on, 0 stops recording them
+    pub fn set_checkpoint_interval(&mut self, interval: u32) {
+        let mut index = BlockIndex::new(interval);
+        for checkpoint in self.index.checkpoints() {
+            index.push(checkpoint.clone());
+        }
+        self.index = index;
+    }
+
+    /// the checkpoints recorded so far, the offsets stay valid in the bytes returned by
+    /// `close`
+    pub fn index(&self) -> &BlockIndex {
+        &self.index
+    }
+
+    fn update_header(&mut self, dp: &DataPoint) {
+        let header = &mut self.header;
+        if header.count == 0 {
+            header.min_time = dp.time;
+            header.max_time = dp.time;
+        } else {
+            header.min_time = header.min_time.min(dp.time);
+            header.max_time = header.max_time.max(dp.time);
+        }
+        // strings and sketches have no value range in the header
+        if let Value::String(_) | Value::Sketch(_) = dp.value {
+            header.min_value = None;
+            header.max_value = None;
+        } else if header.count == 0 {
+            header.min_value = Some(dp.value.clone());
+            header.max_value = Some(dp.value.clone());
+        } else if let (Some(min), Some(max)) = (&header.min_value, &header.max_value) {
+            // NaN is ignored as by f64::min and f64::max, the bounds stay NaN until a number is
+            // seen
+            let (min, max) = (min.min(&dp.value), max.max(&dp.value));
+            header.min_value = Some(min);
+            header.max_value = Some(max);
+        }
+        header.count += 1;
+    }
+
     pub fn get_size(&self) -> u64 {
         self.size
     }
 
-    fn write_first(&mut self, time: u64, value_bits: u64) {
-        self.delta = time - self.time;
+    fn write_first_timestamp(&mut self, time: u64) {
+        self.delta = time.wrapping_sub(self.time);
         self.time = time;
-        self.value_bits = value_bits;
 
         // write one control bit so we can distinguish a stream which contains only an initial
         // timestamp, this assumes the first bit of the END_MARKER is 1
         self.w.write_bit(Bit::Zero);
 
-        // store the first delta with 14 bits which is enough to span just over 4 hours
-        // if one wanted to use a window larger than 4 hours this size would increase
-        self.w.write_bits(self.delta, 14);
-
-        // store the first value exactly
-        self.w.write_bits(self.value_bits, 64);
-
-        self.first = true
+        match self.version {
+            // store the first delta with 14 bits for second precision which is enough to span
+            // just over 4 hours, finer precisions use proportionally wider fields
+            FormatVersion::V1 => self.w.write_bits(self.delta, self.layout.first_delta_bits),
+            _ => self.w.write_varint(self.delta),
+        }
     }
 
     fn write_next_timestamp(&mut self, time: u64) {
-        let delta = time - self.time; // current delta
-        let dod = delta.wrapping_sub(self.delta) as i32; // delta of delta
+        let delta = time.wrapping_sub(self.time); // current delta
+        let dod = delta.wrapping_sub(self.delta) as i64; // delta of delta
 
         // store the delta of delta using variable length encoding
-        #[cfg_attr(feature = "cargo-clippy", allow(match_overlapping_arm))]
-        match dod {
-            0 => {
-                self.w.write_bit(Bit::Zero);
-            }
-            -63..=64 => {
-                self.w.write_bits(0b10, 2);
-                self.w.write_bits(dod as u64, 7);
-            }
-            -255..=256 => {
-                self.w.write_bits(0b110, 3);
-                self.w.write_bits(dod as u64, 9);
-            }
-            -2047..=2048 => {
-                self.w.write_bits(0b1110, 4);
-                self.w.write_bits(dod as u64, 12);
-            }
-            _ => {
-                self.w.write_bits(0b1111, 4);
-                self.w.write_bits(dod as u64, 32);
+        if dod == 0 {
+            self.w.write_bit(Bit::Zero);
+        } else {
+            let layout = self.layout;
+            let bucket = layout
+                .dod_bits
+                .iter()
+                .position(|bits| TimestampLayout::fits(dod, *bits));
+            match bucket {
+                Some(i) => {
+                    // control bits are i + 1 ones followed by a zero: 10, 110, 1110
+                    let control = (1u64 << (i + 2)) - 2;
+                    self.w.write_bits(control, i as u32 + 2);
+                    self.w.write_bits(dod as u64, layout.dod_bits[i]);
+                }
+                None => {
+                    self.w.write_bits(0b1111, 4);
+                    match self.version {
+                        FormatVersion::V1 => self.w.write_bits(dod as u64, layout.fallback_bits),
+                        // dod is not zero here so the varint can not be confused with the end
+                        // marker
+                        _ => self.w.write_varint(zigzag_encode(dod)),
+                    }
+                }
             }
         }
 
         self.delta = delta;
         self.time = time;
     }
-
-    fn write_next_value(&mut self, value_bits: u64) {
-        let xor = value_bits ^ self.value_bits;
-        self.value_bits = value_bits;
-
-        if xor == 0 {
-            // if xor with previous value is zero just store single zero bit
-            self.w.write_bit(Bit::Zero);
-        } else {
-            self.w.write_bit(Bit::One);
-
-            let leading_zeroes = xor.leading_zeros();
-            let trailing_zeroes = xor.trailing_zeros();
-
-            if leading_zeroes >= self.leading_zeroes && trailing_zeroes >= self.trailing_zeroes {
-                // if the number of leading and trailing zeroes in this xor are >= the leading and
-                // trailing zeroes in the previous xor then we only need to store a control bit and
-                // the significant digits of this xor
-                self.w.write_bit(Bit::Zero);
-                self.w.write_bits(
-                    xor.wrapping_shr(self.trailing_zeroes),
-                    64 - self.leading_zeroes - self.trailing_zeroes,
-                );
-            } else {
-                // if the number of leading and trailing zeroes in this xor are not less than the
-                // leading and trailing zeroes in the previous xor then we store a control bit and
-                // use 6 bits to store the number of leading zeroes and 6 bits to store the number
-                // of significant digits before storing the significant digits themselves
-
-                self.w.write_bit(Bit::One);
-                self.w.write_bits(u64::from(leading_zeroes), 6);
-
-                // if significant_digits is 64 we cannot encode it using 6 bits, however since
-                // significant_digits is guaranteed to be at least 1 we can subtract 1 to ensure
-                // significant_digits can always be expressed with 6 bits or less
-                let significant_digits = 64 - leading_zeroes - trailing_zeroes;
-                self.w.write_bits(u64::from(significant_digits - 1), 6);
-                self.w
-                    .write_bits(xor.wrapping_shr(trailing_zeroes), significant_digits);
-
-                // finally we need to update the number of leading and trailing zeroes
-                self.leading_zeroes = leading_zeroes;
-                self.trailing_zeroes = trailing_zeroes;
-            }
-        }
-    }
 }
 
 impl<T> Encode for StdEncoder<T>
@@ -168,26 +290,73 @@ where
     T: Write,
 {
     fn encode(&mut self, dp: DataPoint) {
-        let value_bits = unsafe { mem::transmute::<f64, u64>(dp.value) };
+        self.update_header(&dp);
 
         if self.first {
-            self.write_first(dp.time, value_bits);
+            self.write_first_timestamp(dp.time);
+            self.codec.encode_value(&mut self.w, dp.value);
             self.first = false;
+            self.w.commit();
             return;
         }
 
         self.write_next_timestamp(dp.time);
-        self.write_next_value(value_bits);
+        self.codec.encode_value(&mut self.w, dp.value);
 
         self.size = self.size + 1;
+
+        if self.index.is_due(self.header.count) {
+            self.index.push(Checkpoint {
+                offset: self.w.bit_len(),
+                count: self.header.count,
+                max_time: self.header.max_time,
+                time: self.time,
+                delta: self.delta,
+                codec: self.codec.snapshot(),
+            });
+        }
+        self.w.commit();
     }
 
     fn close(mut self) -> Box<[u8]> {
-        self.w.write_bits(END_MARKER, END_MARKER_LEN);
+        match self.version {
+            FormatVersion::V1 => {
+                self.w.write_bits(0b1111, 4);
+                self.w.write_bits(0, self.layout.fallback_bits);
+            }
+            FormatVersion::V2 => self.w.write_bits(V2_END_MARKER, V2_END_MARKER_LEN),
+            FormatVersion::V3 => {
+                self.w.write_bits(V2_END_MARKER, V2_END_MARKER_LEN);
+                let mut bytes = self.w.close();
+
+                // seal the header now that the payload is complete
+                self.header.sealed = true;
+                self.header.crc = checksum(&bytes[HEADER_LEN..]);
+                bytes[..HEADER_LEN].copy_from_slice(&self.header.to_bytes());
+                return bytes;
+            }
+        }
         self.w.close()
     }
 }
 
+impl StdEncoder<SharedWriter> {
+    /// a reader of the points encoded so far which ends with an end of stream marker, it reads
+    /// the encoder's buffer in place while more points are encoded. The header of a version 3
+    /// block is read unsealed.
+    pub fn snapshot(&self) -> SharedReader {
+        match self.version {
+            FormatVersion::V1 => self.w.snapshot(
+                0b1111 << self.layout.fallback_bits,
+                self.layout.end_marker_len(),
+            ),
+            _ => self
+                .w
+                .snapshot(u128::from(V2_END_MARKER), V2_END_MARKER_LEN),
+        }
+    }
+}
+
 // TODO clone always
 impl<T> Clone for StdEncoder<T>
 where
@@ -197,10 +366,12 @@ where
         StdEncoder {
             time: self.time,
             delta: self.delta,
-            value_bits: self.value_bits,
-            leading_zeroes: self.leading_zeroes, // 64 is an initial sentinel value
-            trailing_zeroes: self.trailing_zeroes, // 64 is an intitial sentinel value
+            codec: self.codec.clone(),
             first: self.first,
+            version: self.version,
+            layout: self.layout,
+            header: self.header.clone(),
+            index: self.index.clone(),
             w: self.w.clone(),
             size: self.size,
         }
@@ -210,15 +381,21 @@ where
 #[cfg(test)]
 mod tests {
     use crate::encode::Encode;
+    use crate::format::FormatVersion;
     use crate::stream::BufferedWriter;
     use crate::DataPoint;
     use crate::StdEncoder;
+    use common::TimePrecision;
+
+    fn v1_encoder(start_time: u64) -> StdEncoder<BufferedWriter> {
+        let w = BufferedWriter::new();
+        StdEncoder::with_version(start_time, TimePrecision::Seconds, FormatVersion::V1, w)
+    }
 
     #[test]
     fn create_new_encoder() {
-        let w = BufferedWriter::new();
         let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
-        let e = StdEncoder::new(start_time, w);
+        let e = v1_encoder(start_time);
 
         let bytes = e.close();
         let expected_bytes: [u8; 13] = [0, 0, 0, 0, 88, 89, 157, 151, 240, 0, 0, 0, 0];
@@ -227,10 +404,22 @@ mod tests {
     }
 
     #[test]
-    fn encode_datapoint() {
+    fn create_new_v2_encoder() {
         let w = BufferedWriter::new();
         let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
-        let mut e = StdEncoder::new(start_time, w);
+        let e = StdEncoder::with_version(start_time, TimePrecision::Seconds, FormatVersion::V2, w);
+
+        let bytes = e.close();
+        // magic, version, precision, start time, end marker
+        let expected_bytes: [u8; 14] = [254, 90, 2, 0, 0, 0, 0, 0, 88, 89, 157, 151, 240, 0];
+
+        assert_eq!(bytes[..], expected_bytes[..]);
+    }
+
+    #[test]
+    fn encode_datapoint() {
+        let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
+        let mut e = v1_encoder(start_time);
 
         let d1 = DataPoint::new(1482268055 + 10, 1.24);
 
@@ -247,9 +436,8 @@ mod tests {
 
     #[test]
     fn encode_multiple_datapoints() {
-        let w = BufferedWriter::new();
         let start_time = 1482268055; // 2016-12-20T21:07:35+00:00
-        let mut e = StdEncoder::new(start_time, w);
+        let mut e = v1_encoder(start_time);
 
         let d1 = DataPoint::new(1482268055 + 10, 1.24);
 
@@ -0,0 +1,418 @@
+//! Block format versions.
+//!
+//! A version 1 block starts directly with the 64 bits of its start time and stores the first
+//! delta and the largest delta of deltas in fixed width fields. Every later version starts with
+//! `BLOCK_MAGIC` followed by the version byte, which can not be mistaken for the high bits of a
+//! start time, so a decoder can tell the formats apart by peeking at the first 16 bits.
+//!
+//! Version 3 blocks start with a fixed size `BlockHeader` which describes the payload: codec,
+//! precision, point count, time and value ranges and a CRC-32 of the payload. The header is
+//! written with zeroed statistics when the encoder is created and sealed when it is closed.
+
+use crate::{Value, ValueType};
+use common::TimePrecision;
+use std::fmt;
+
+/// BLOCK_MAGIC is the first 16 bits of every versioned block
+pub const BLOCK_MAGIC: u64 = 0xFE5A;
+
+/// BLOCK_MAGIC_LEN is the length, in bits, of BLOCK_MAGIC
+pub const BLOCK_MAGIC_LEN: u32 = 16;
+
+/// FormatVersion
+///
+/// The layout of an encoded block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatVersion {
+    /// headerless blocks, the first delta and the fallback delta of delta have fixed widths
+    V1,
+    /// the first delta and the fallback delta of delta are variable length integers
+    V2,
+    /// version 2 payload preceded by a `BlockHeader`
+    V3,
+}
+
+impl FormatVersion {
+    /// the version written by encoders which are not told otherwise
+    pub const CURRENT: FormatVersion = FormatVersion::V3;
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            FormatVersion::V1 => 1,
+            FormatVersion::V2 => 2,
+            FormatVersion::V3 => 3,
+        }
+    }
+
+    pub fn from_u8(version: u8) -> Option<FormatVersion> {
+        match version {
+            1 => Some(FormatVersion::V1),
+            2 => Some(FormatVersion::V2),
+            3 => Some(FormatVersion::V3),
+            _ => None,
+        }
+    }
+}
+
+/// CodecId
+///
+/// The scheme used to encode the values of a block, see `crate::codec`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CodecId {
+    /// Gorilla XOR of consecutive float values
+    #[default]
+    GorillaXor,
+    /// Chimp XOR with rounded leading zeroes
+    Chimp,
+    /// Chimp XOR against one of the 128 previous values
+    Chimp128,
+    /// values stored as scaled decimal integers
+    Decimal,
+    /// integer values, zigzag delta bit packed
+    Integer,
+    /// bool values, run length encoded
+    Bool,
+    /// string values, dictionary and run length encoded
+    String,
+    /// DDSketch values
+    Sketch,
+}
+
+impl CodecId {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CodecId::GorillaXor => 0,
+            CodecId::Chimp => 1,
+            CodecId::Chimp128 => 2,
+            CodecId::Decimal => 3,
+            CodecId::Integer => 4,
+            CodecId::Bool => 5,
+            CodecId::String => 6,
+            CodecId::Sketch => 7,
+        }
+    }
+
+    pub fn from_u8(codec: u8) -> Option<CodecId> {
+        match codec {
+            0 => Some(CodecId::GorillaXor),
+            1 => Some(CodecId::Chimp),
+            2 => Some(CodecId::Chimp128),
+            3 => Some(CodecId::Decimal),
+            4 => Some(CodecId::Integer),
+            5 => Some(CodecId::Bool),
+            6 => Some(CodecId::String),
+            7 => Some(CodecId::Sketch),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CodecId::GorillaXor => "gorilla",
+            CodecId::Chimp => "chimp",
+            CodecId::Chimp128 => "chimp128",
+            CodecId::Decimal => "decimal",
+            CodecId::Integer => "integer",
+            CodecId::Bool => "bool",
+            CodecId::String => "string",
+            CodecId::Sketch => "sketch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CodecId> {
+        match name {
+            "gorilla" => Some(CodecId::GorillaXor),
+            "chimp" => Some(CodecId::Chimp),
+            "chimp128" => Some(CodecId::Chimp128),
+            "decimal" => Some(CodecId::Decimal),
+            "integer" => Some(CodecId::Integer),
+            "bool" => Some(CodecId::Bool),
+            "string" => Some(CodecId::String),
+            "sketch" => Some(CodecId::Sketch),
+            _ => None,
+        }
+    }
+
+    /// the type of the values the codec stores
+    pub fn value_type(self) -> ValueType {
+        match self {
+            CodecId::Integer => ValueType::Integer,
+            CodecId::Bool => ValueType::Bool,
+            CodecId::String => ValueType::String,
+            CodecId::Sketch => ValueType::Sketch,
+            _ => ValueType::Float,
+        }
+    }
+}
+
+/// HEADER_LEN is the length, in bytes, of a version 3 `BlockHeader`
+pub const HEADER_LEN: usize = 56;
+
+/// HEADER_FLAG_SEALED marks a header whose statistics and checksum are valid
+pub const HEADER_FLAG_SEALED: u8 = 0b0000_0001;
+
+/// BlockHeader
+///
+/// The self describing header of a version 3 block, all fields are big endian:
+///
+/// | bytes | field                                   |
+/// |-------|-----------------------------------------|
+/// | 2     | magic, `BLOCK_MAGIC`                    |
+/// | 1     | format version                          |
+/// | 1     | header length in bytes                  |
+/// | 1     | codec id                                |
+/// | 1     | timestamp precision                     |
+/// | 1     | flags, `HEADER_FLAG_SEALED`             |
+/// | 1     | reserved                                |
+/// | 4     | point count                             |
+/// | 8     | start time                              |
+/// | 8     | min time                                |
+/// | 8     | max time                                |
+/// | 8     | min value, see `Value::to_bits`         |
+/// | 8     | max value, see `Value::to_bits`         |
+/// | 4     | CRC-32 of the payload after the header  |
+///
+/// The statistics of a block without points, or of an unsealed block, are meaningless. Blocks of
+/// strings and sketches have no value range, their min and max value are zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub codec: CodecId,
+    pub precision: TimePrecision,
+    pub sealed: bool,
+    pub count: u32,
+    pub start: u64,
+    pub min_time: u64,
+    pub max_time: u64,
+    /// of the value type of the codec, `None` for strings and sketches
+    pub min_value: Option<Value>,
+    pub max_value: Option<Value>,
+    pub crc: u32,
+}
+
+impl BlockHeader {
+    /// new creates an unsealed header
+    pub fn new(codec: CodecId, precision: TimePrecision, start: u64) -> Self {
+        BlockHeader {
+            codec,
+            precision,
+            sealed: false,
+            count: 0,
+            start,
+            min_time: 0,
+            max_time: 0,
+            min_value: Value::from_bits(0, codec.value_type()),
+            max_value: Value::from_bits(0, codec.value_type()),
+            crc: 0,
+        }
+    }
+
+    /// whether the block may contain points in [begin_time, end_time], unsealed headers
+    /// always overlap since their statistics are not known
+    pub fn overlaps(&self, begin_time: u64, end_time: u64) -> bool {
+        if !self.sealed {
+            return true;
+        }
+        self.count > 0 && self.min_time <= end_time && self.max_time >= begin_time
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..2].copy_from_slice(&(BLOCK_MAGIC as u16).to_be_bytes());
+        bytes[2] = FormatVersion::V3.to_u8();
+        bytes[3] = HEADER_LEN as u8;
+        bytes[4] = self.codec.to_u8();
+        bytes[5] = precision_to_u8(self.precision);
+        bytes[6] = if self.sealed { HEADER_FLAG_SEALED } else { 0 };
+        bytes[8..12].copy_from_slice(&self.count.to_be_bytes());
+        bytes[12..20].copy_from_slice(&self.start.to_be_bytes());
+        bytes[20..28].copy_from_slice(&self.min_time.to_be_bytes());
+        bytes[28..36].copy_from_slice(&self.max_time.to_be_bytes());
+        let bits = |v: &Option<Value>| v.as_ref().map_or(0, |v| v.to_bits());
+        bytes[36..44].copy_from_slice(&bits(&self.min_value).to_be_bytes());
+        bytes[44..52].copy_from_slice(&bits(&self.max_value).to_be_bytes());
+        bytes[52..56].copy_from_slice(&self.crc.to_be_bytes());
+        bytes
+    }
+
+    /// parse and sanity check a version 3 header, the checksum is not verified
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HeaderError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(HeaderError::Truncated);
+        }
+        if u64::from(u16::from_be_bytes([bytes[0], bytes[1]])) != BLOCK_MAGIC {
+            return Err(HeaderError::Magic);
+        }
+        if bytes[2] != FormatVersion::V3.to_u8() {
+            return Err(HeaderError::Version(bytes[2]));
+        }
+        if bytes[3] as usize != HEADER_LEN {
+            return Err(HeaderError::Length(bytes[3]));
+        }
+
+        let codec = CodecId::from_u8(bytes[4]).ok_or(HeaderError::Codec(bytes[4]))?;
+        let precision = precision_from_u8(bytes[5]).ok_or(HeaderError::Precision(bytes[5]))?;
+        let u64_at = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_be_bytes(b)
+        };
+
+        let header = BlockHeader {
+            codec,
+            precision,
+            sealed: bytes[6] & HEADER_FLAG_SEALED != 0,
+            count: u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            start: u64_at(12),
+            min_time: u64_at(20),
+            max_time: u64_at(28),
+            min_value: Value::from_bits(u64_at(36), codec.value_type()),
+            max_value: Value::from_bits(u64_at(44), codec.value_type()),
+            crc: u32::from_be_bytes([bytes[52], bytes[53], bytes[54], bytes[55]]),
+        };
+
+        if header.sealed && header.count > 0 {
+            if header.min_time > header.max_time || header.min_time < header.start {
+                return Err(HeaderError::Statistics);
+            }
+            // NaN values are not part of the value range, a block of only NaN has NaN bounds
+            if let (Some(min), Some(max)) = (&header.min_value, &header.max_value) {
+                if min.gt(max) {
+                    return Err(HeaderError::Statistics);
+                }
+            }
+        }
+
+        Ok(header)
+    }
+}
+
+/// HeaderError
+///
+/// The reasons a block header is refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderError {
+    Truncated,
+    Magic,
+    Version(u8),
+    Length(u8),
+    Codec(u8),
+    Precision(u8),
+    Statistics,
+    Checksum { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderError::Truncated => write!(f, "header is truncated"),
+            HeaderError::Magic => write!(f, "header does not start with the block magic"),
+            HeaderError::Version(v) => write!(f, "unexpected header version {}", v),
+            HeaderError::Length(len) => write!(f, "unexpected header length {}", len),
+            HeaderError::Codec(codec) => write!(f, "unknown codec id {}", codec),
+            HeaderError::Precision(p) => write!(f, "unknown timestamp precision {}", p),
+            HeaderError::Statistics => write!(f, "inconsistent block statistics"),
+            HeaderError::Checksum { expected, actual } => write!(
+                f,
+                "payload checksum mismatch, expected {:08x} got {:08x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// read the header of an encoded block, `Ok(None)` for version 1 and 2 blocks which have none
+pub fn read_header(bytes: &[u8]) -> Result<Option<BlockHeader>, HeaderError> {
+    if bytes.len() < 3 || u64::from(u16::from_be_bytes([bytes[0], bytes[1]])) != BLOCK_MAGIC {
+        return Ok(None);
+    }
+    if bytes[2] != FormatVersion::V3.to_u8() {
+        return Ok(None);
+    }
+    BlockHeader::from_bytes(bytes).map(Some)
+}
+
+/// CRC-32 of a block payload
+pub fn checksum(payload: &[u8]) -> u32 {
+    crc32fast::hash(payload)
+}
+
+pub fn precision_to_u8(precision: TimePrecision) -> u8 {
+    match precision {
+        TimePrecision::Seconds => 0,
+        TimePrecision::Millis => 1,
+        TimePrecision::Micros => 2,
+        TimePrecision::Nanos => 3,
+    }
+}
+
+pub fn precision_from_u8(precision: u8) -> Option<TimePrecision> {
+    match precision {
+        0 => Some(TimePrecision::Seconds),
+        1 => Some(TimePrecision::Millis),
+        2 => Some(TimePrecision::Micros),
+        3 => Some(TimePrecision::Nanos),
+        _ => None,
+    }
+}
+
+/// map a signed integer to an unsigned one so that numbers close to zero stay small:
+/// 0 => 0, -1 => 1, 1 => 2, -2 => 3, ...
+pub fn zigzag_encode(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn zigzag_decode(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_header, zigzag_decode, zigzag_encode, BlockHeader, CodecId, HeaderError};
+    use crate::Value;
+    use common::TimePrecision;
+
+    #[test]
+    fn zigzag() {
+        assert_eq!(zigzag_encode(0), 0);
+        assert_eq!(zigzag_encode(-1), 1);
+        assert_eq!(zigzag_encode(1), 2);
+        assert_eq!(zigzag_encode(-2), 3);
+
+        for v in [0, 1, -1, 64, -64, i64::MAX, i64::MIN].iter() {
+            assert_eq!(zigzag_decode(zigzag_encode(*v)), *v);
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut header = BlockHeader::new(CodecId::GorillaXor, TimePrecision::Millis, 1000);
+        header.sealed = true;
+        header.count = 3;
+        header.min_time = 1010;
+        header.max_time = 1030;
+        header.min_value = Some(Value::Float(-1.5));
+        header.max_value = Some(Value::Float(2.5));
+        header.crc = 0xdead_beef;
+
+        let bytes = header.to_bytes();
+        assert_eq!(read_header(&bytes).unwrap(), Some(header.clone()));
+        assert!(header.overlaps(1030, 2000));
+        assert!(!header.overlaps(1031, 2000));
+        assert!(header.overlaps(0, 1010));
+        assert!(!header.overlaps(0, 1009));
+
+        // a sealed header whose time range starts before the block is refused
+        header.min_time = 999;
+        assert_eq!(
+            BlockHeader::from_bytes(&header.to_bytes()),
+            Err(HeaderError::Statistics)
+        );
+        assert_eq!(
+            BlockHeader::from_bytes(&bytes[..10]),
+            Err(HeaderError::Truncated)
+        );
+
+        // version 2 blocks have no header
+        assert_eq!(read_header(&[254, 90, 2, 0]).unwrap(), None);
+    }
+}
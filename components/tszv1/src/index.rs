@@ -0,0 +1,78 @@
+//! Seek index of a block.
+//!
+//! Decoding a point needs the state left by all the points before it, so reading the end of a
+//! block normally means decoding it from the start. The encoder records a `Checkpoint` every
+//! `interval` points: the bit offset of the next point and the decoder state at that offset.
+//! `StdDecoder::seek` restores the last checkpoint before a time and decodes from there.
+//!
+//! The index lives beside the block bytes and the stream format is unchanged. The index of a
+//! block whose index was not kept is rebuilt by decoding it once with `StdDecoder::build_index`.
+
+use crate::codec::ValueCodec;
+
+/// points between two checkpoints of a new encoder
+pub const DEFAULT_CHECKPOINT_INTERVAL: u32 = 512;
+
+/// Checkpoint
+///
+/// The state of a decoder which has just read the first `count` points of a block.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// offset of the next point in bits from the start of the stream
+    pub offset: u64,
+    pub count: u32,
+    /// greatest time of the points before the checkpoint, points may be out of order
+    pub max_time: u64,
+    pub time: u64,
+    pub delta: u64,
+    pub codec: ValueCodec,
+}
+
+/// BlockIndex
+///
+/// The checkpoints of a block, in stream order.
+#[derive(Debug, Clone)]
+pub struct BlockIndex {
+    interval: u32,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl BlockIndex {
+    /// an empty index with a checkpoint every `interval` points, 0 records none
+    pub fn new(interval: u32) -> Self {
+        BlockIndex {
+            interval,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// whether a checkpoint is due once `count` points are encoded
+    pub fn is_due(&self, count: u32) -> bool {
+        self.interval > 0 && count.is_multiple_of(self.interval)
+    }
+
+    pub fn push(&mut self, checkpoint: Checkpoint) {
+        self.checkpoints.push(checkpoint);
+    }
+
+    /// the last checkpoint after which every point at or after `time` still lies
+    pub fn find(&self, time: u64) -> Option<&Checkpoint> {
+        // max_time only grows along the stream
+        let i = self.checkpoints.partition_point(|c| c.max_time < time);
+        i.checked_sub(1).map(|i| &self.checkpoints[i])
+    }
+}
+
+impl Default for BlockIndex {
+    fn default() -> Self {
+        BlockIndex::new(DEFAULT_CHECKPOINT_INTERVAL)
+    }
+}
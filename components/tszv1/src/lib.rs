@@ -1,11 +1,15 @@
 //! A crate for time series compression based upon Facebook's white paper
 //! [Gorilla: A Fast, Scalable, In-Memory Time Series Database](http://www.vldb.org/pvldb/vol8/p1816-teller.pdf).
-//! `tsz` provides functionality for compressing a stream of `DataPoint`s, which are composed of a
+//! `tszv1` provides functionality for compressing a stream of `DataPoint`s, which are composed of a
 //! time and value, into bytes, and decompressing a stream of bytes into `DataPoint`s.
 //!
+//! Streams are written through the `stream::Write` trait and read through `stream::Read`. A
+//! `BufferedWriter` owns a growable `Buffer`; a `BufferedReader` reads any `ByteSource`, owned
+//! bytes as well as a borrowed `&[u8]` or the `Buffer` of a writer still being written.
+//!
 //! ## Example
 //!
-//! Below is a simple example of how to interact with `tsz` to encode and decode `DataPoint`s.
+//! Below is a simple example of how to interact with `tszv1` to encode and decode `DataPoint`s.
 //!
 //! ```rust,no_run
 //! extern crate tszv1;
@@ -50,12 +54,12 @@
 //!     }
 //!
 //!     for dp in &actual_datapoints {
-//!         encoder.encode(*dp);
+//!         encoder.encode(dp.clone());
 //!     }
 //!
 //!     let bytes = encoder.close();
 //!     let r = BufferedReader::new(bytes);
-//!     let mut decoder = StdDecoder::new(r);
+//!     let mut decoder = StdDecoder::new(r).unwrap();
 //!
 //!     let mut expected_datapoints = Vec::new();
 //!
@@ -125,27 +129,57 @@ impl Bit {
 /// DataPoint
 ///
 /// Struct used to represent a single datapoint. Consists of a time and value.
-#[derive(Debug, PartialEq, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DataPoint {
     pub time: u64,
-    pub value: f64,
-}
-
-impl Clone for DataPoint {
-    fn clone(&self) -> DataPoint {
-        *self
-    }
+    pub value: Value,
 }
 
 impl DataPoint {
-    // Create a new DataPoint from a time and value.
+    // Create a new DataPoint from a time and float value.
     pub fn new(time: u64, value: f64) -> Self {
-        DataPoint { time, value }
+        DataPoint {
+            time,
+            value: Value::Float(value),
+        }
+    }
+
+    // Create a new DataPoint from a time and integer value.
+    pub fn integer(time: u64, value: i64) -> Self {
+        DataPoint {
+            time,
+            value: Value::Integer(value),
+        }
+    }
+
+    // Create a new DataPoint from a time and a value of any type.
+    pub fn with_value<V: Into<Value>>(time: u64, value: V) -> Self {
+        DataPoint {
+            time,
+            value: value.into(),
+        }
     }
 }
 
 pub mod buffer;
 
+pub mod value;
+pub use self::value::{Value, ValueType};
+
+pub mod sketch;
+pub use self::sketch::DDSketch;
+
+pub mod format;
+
+pub mod index;
+pub use self::index::BlockIndex;
+
+pub mod rewrite;
+pub use self::rewrite::Duplicates;
+
+pub mod codec;
+pub use self::codec::Codec;
+
 pub mod stream;
 
 pub mod encode;
@@ -161,8 +195,10 @@ mod tests {
     use std::vec::Vec;
 
     use super::decode::Error;
+    use super::format::CodecId;
     use super::stream::{BufferedReader, BufferedWriter};
-    use super::{DataPoint, Decode, Encode, StdDecoder, StdEncoder};
+    use super::{DataPoint, Decode, Encode, StdDecoder, StdEncoder, Value};
+    use common::TimePrecision;
 
     const DATA: &'static str = "1482892270,1.76
 1482892280,7.78
@@ -198,12 +234,12 @@ mod tests {
         }
 
         for dp in &original_datapoints {
-            encoder.encode(*dp);
+            encoder.encode(dp.clone());
         }
 
         let bytes = encoder.close();
         let r = BufferedReader::new(bytes);
-        let mut decoder = StdDecoder::new(r);
+        let mut decoder = StdDecoder::new(r).unwrap();
 
         let mut new_datapoints = Vec::new();
 
@@ -227,4 +263,163 @@ mod tests {
 
         assert_eq!(original_datapoints, new_datapoints);
     }
+
+    fn round_trip(precision: TimePrecision, start: u64, datapoints: &[DataPoint]) {
+        let w = BufferedWriter::new();
+        let mut encoder = StdEncoder::with_precision(start, precision, w);
+        for dp in datapoints {
+            encoder.encode(dp.clone());
+        }
+
+        let r = BufferedReader::new(encoder.close());
+        let mut decoder = StdDecoder::with_precision(r, precision).unwrap();
+        for dp in datapoints {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn precision_round_trip() {
+        let precisions = [
+            TimePrecision::Seconds,
+            TimePrecision::Millis,
+            TimePrecision::Micros,
+            TimePrecision::Nanos,
+        ];
+        for precision in precisions.iter() {
+            let unit = precision.units_per_sec();
+            let start = 1482892260 * unit;
+
+            // a 10 second interval with a jitter of a few units, plus a few gaps which need the
+            // larger buckets and the fallback
+            let mut datapoints = Vec::new();
+            let mut time = start;
+            for i in 0..500u64 {
+                time += 10 * unit + (i * 7) % 13;
+                if i % 97 == 0 {
+                    time += 1800 * unit;
+                }
+                datapoints.push(DataPoint::new(time, i as f64 * 0.25));
+            }
+
+            round_trip(*precision, start, &datapoints);
+        }
+    }
+
+    #[test]
+    fn large_delta_of_delta() {
+        // a gap of one hour followed by a regular interval needs a negative fallback delta of
+        // delta
+        let datapoints = vec![
+            DataPoint::new(1000, 1.0),
+            DataPoint::new(1010, 2.0),
+            DataPoint::new(4610, 3.0),
+            DataPoint::new(4620, 4.0),
+            DataPoint::new(4630, 5.0),
+        ];
+        round_trip(TimePrecision::Seconds, 1000, &datapoints);
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let codecs = [
+            CodecId::GorillaXor,
+            CodecId::Chimp,
+            CodecId::Chimp128,
+            CodecId::Decimal,
+        ];
+        let datapoints: Vec<DataPoint> = DATA
+            .lines()
+            .map(|line| {
+                let substrings: Vec<&str> = line.split(',').collect();
+                DataPoint::new(
+                    substrings[0].parse().unwrap(),
+                    substrings[1].parse().unwrap(),
+                )
+            })
+            .collect();
+
+        for codec in codecs.iter() {
+            let w = BufferedWriter::new();
+            let mut encoder = StdEncoder::with_codec(1482892260, TimePrecision::Seconds, *codec, w);
+            for dp in &datapoints {
+                encoder.encode(dp.clone());
+            }
+
+            let r = BufferedReader::new(encoder.close());
+            let mut decoder = StdDecoder::new(r).unwrap();
+            assert_eq!(decoder.header().unwrap().codec, *codec);
+            for dp in &datapoints {
+                assert_eq!(decoder.next().unwrap(), *dp);
+            }
+            assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+        }
+    }
+
+    #[test]
+    fn integer_round_trip() {
+        let datapoints = vec![
+            DataPoint::integer(1482892270, 9_007_199_254_740_993),
+            DataPoint::integer(1482892280, 9_007_199_254_740_995),
+            DataPoint::integer(1482892290, -3),
+            DataPoint::integer(1482892300, -3),
+        ];
+
+        let w = BufferedWriter::new();
+        let mut encoder =
+            StdEncoder::with_codec(1482892260, TimePrecision::Seconds, CodecId::Integer, w);
+        for dp in &datapoints {
+            encoder.encode(dp.clone());
+        }
+
+        let r = BufferedReader::new(encoder.close());
+        let mut decoder = StdDecoder::new(r).unwrap();
+        let header = decoder.header().unwrap().clone();
+        assert_eq!(header.min_value, Some(Value::Integer(-3)));
+        assert_eq!(
+            header.max_value,
+            Some(Value::Integer(9_007_199_254_740_995))
+        );
+        for dp in &datapoints {
+            assert_eq!(decoder.next().unwrap(), *dp);
+        }
+        assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+    }
+
+    #[test]
+    fn bool_and_string_round_trip() {
+        let flags = vec![
+            DataPoint::with_value(1482892270, true),
+            DataPoint::with_value(1482892280, true),
+            DataPoint::with_value(1482892290, false),
+        ];
+        let states = vec![
+            DataPoint::with_value(1482892270, "up"),
+            DataPoint::with_value(1482892280, "down"),
+            DataPoint::with_value(1482892290, "up"),
+        ];
+
+        for (codec, datapoints) in [(CodecId::Bool, flags), (CodecId::String, states)].iter() {
+            let w = BufferedWriter::new();
+            let mut encoder = StdEncoder::with_codec(1482892260, TimePrecision::Seconds, *codec, w);
+            for dp in datapoints {
+                encoder.encode(dp.clone());
+            }
+
+            let r = BufferedReader::new(encoder.close());
+            let mut decoder = StdDecoder::new(r).unwrap();
+            let header = decoder.header().unwrap().clone();
+            assert_eq!(header.codec, *codec);
+            if *codec == CodecId::Bool {
+                assert_eq!(header.min_value, Some(Value::Bool(false)));
+            } else {
+                assert_eq!(header.min_value, None);
+            }
+            for dp in datapoints {
+                assert_eq!(decoder.next().unwrap(), *dp);
+            }
+            assert_eq!(decoder.next().err().unwrap(), Error::EndOfStream);
+        }
+    }
 }
@@ -0,0 +1,454 @@
+//! Rewriting encoded blocks: merging several blocks into one, splitting a block at a
+//! timestamp and re-encoding a block from another start time.
+//!
+//! The blocks read may be of any format version, the blocks written are version 3 blocks with
+//! the given codec. `precision` is the precision of every block read and written, version 1
+//! blocks do not record theirs. Points come out in time order, points of the same time in the
+//! order of the blocks and then of the stream.
+
+use crate::decode::Error;
+use crate::format::CodecId;
+use crate::stream::{BufferedReader, BufferedWriter};
+use crate::{DataPoint, Decode, Encode, StdDecoder, StdEncoder, Value, ValueType};
+use common::TimePrecision;
+use std::{error, fmt};
+
+/// Duplicates
+///
+/// What to keep of the points which share a timestamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Duplicates {
+    KeepFirst,
+    KeepLast,
+    /// the sum of the values, sketches are merged
+    Sum,
+}
+
+/// RewriteError
+///
+/// The errors of rewriting blocks.
+#[derive(Debug, PartialEq)]
+pub enum RewriteError {
+    /// a block read is corrupted
+    Decode(Error),
+    /// the start time is after the point at `time`
+    Start { start: u64, time: u64 },
+    /// values of these types can not be summed
+    Sum(ValueType, ValueType),
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RewriteError::Decode(ref err) => write!(f, "{}", err),
+            RewriteError::Start { start, time } => {
+                write!(f, "start time {} is after the point at {}", start, time)
+            }
+            RewriteError::Sum(a, b) => {
+                write!(f, "{} and {} values can not be summed", a.name(), b.name())
+            }
+        }
+    }
+}
+
+impl error::Error for RewriteError {}
+
+impl From<Error> for RewriteError {
+    fn from(err: Error) -> RewriteError {
+        RewriteError::Decode(err)
+    }
+}
+
+/// the initial timestamp and the points of `block`
+fn read(block: &[u8], precision: TimePrecision) -> Result<(u64, Vec<DataPoint>), Error> {
+    let decoder = StdDecoder::with_precision(BufferedReader::from_bytes(block), precision)?;
+    decoder.verify()?;
+    let start = decoder.start();
+    let points = decoder.points().collect::<Result<Vec<_>, _>>()?;
+    Ok((start, points))
+}
+
+/// `start` or the time of the earliest point if before it, out of order points may precede the
+/// start of their block
+fn earliest(start: u64, points: &[DataPoint]) -> u64 {
+    points.iter().map(|dp| dp.time).fold(start, u64::min)
+}
+
+fn write(
+    start: u64,
+    precision: TimePrecision,
+    codec: CodecId,
+    points: Vec<DataPoint>,
+) -> Box<[u8]> {
+    let mut encoder = StdEncoder::with_codec(start, precision, codec, BufferedWriter::new());
+    for dp in points {
+        encoder.encode(dp);
+    }
+    encoder.close()
+}
+
+fn sum(a: Value, b: Value) -> Result<Value, RewriteError> {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a.wrapping_add(b))),
+        (Value::Sketch(mut a), Value::Sketch(b)) => match a.merge(&b) {
+            Ok(()) => Ok(Value::Sketch(a)),
+            Err(_) => Err(RewriteError::Sum(ValueType::Sketch, ValueType::Sketch)),
+        },
+        (a, b) if a.value_type().is_numeric() && b.value_type().is_numeric() => {
+            Ok(Value::Float(a.as_f64() + b.as_f64()))
+        }
+        (a, b) => Err(RewriteError::Sum(a.value_type(), b.value_type())),
+    }
+}
+
+/// sort `points` by time, stable, and resolve the points of the same time
+fn resolve(
+    mut points: Vec<DataPoint>,
+    duplicates: Duplicates,
+) -> Result<Vec<DataPoint>, RewriteError> {
+    points.sort_by_key(|dp| dp.time);
+
+    let mut resolved: Vec<DataPoint> = Vec::with_capacity(points.len());
+    for dp in points {
+        match resolved.last_mut() {
+            Some(last) if last.time == dp.time => match duplicates {
+                Duplicates::KeepFirst => {}
+                Duplicates::KeepLast => *last = dp,
+                Duplicates::Sum => {
+                    let value = std::mem::replace(&mut last.value, Value::Integer(0));
+                    last.value = sum(value, dp.value)?;
+                }
+            },
+            _ => resolved.push(dp),
+        }
+    }
+    Ok(resolved)
+}
+
+/// the earliest start of `blocks` and their points, time ordered and resolved
+fn read_all(
+    blocks: &[&[u8]],
+    precision: TimePrecision,
+    duplicates: Duplicates,
+) -> Result<(u64, Vec<DataPoint>), RewriteError> {
+    let mut start = None;
+    let mut points = Vec::new();
+    for block in blocks {
+        let (block_start, block_points) = read(block, precision)?;
+        start = Some(earliest(start.unwrap_or(block_start), &block_points).min(block_start));
+        points.extend(block_points);
+    }
+    Ok((start.unwrap_or(0), resolve(points, duplicates)?))
+}
+
+/// merge `blocks` into one time ordered block which starts at the earliest start of them
+pub fn merge(
+    blocks: &[&[u8]],
+    precision: TimePrecision,
+    codec: CodecId,
+    duplicates: Duplicates,
+) -> Result<Box<[u8]>, RewriteError> {
+    let (start, points) = read_all(blocks, precision, duplicates)?;
+    Ok(write(start, precision, codec, points))
+}
+
+/// `merge` keeping only the points for which `keep` is true, duplicates are resolved first.
+/// `None` when no point is kept.
+pub fn merge_retain<F>(
+    blocks: &[&[u8]],
+    precision: TimePrecision,
+    codec: CodecId,
+    duplicates: Duplicates,
+    keep: F,
+) -> Result<Option<Box<[u8]>>, RewriteError>
+where
+    F: Fn(&DataPoint) -> bool,
+{
+    let (start, mut points) = read_all(blocks, precision, duplicates)?;
+    points.retain(keep);
+    if points.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(write(start, precision, codec, points)))
+}
+
+/// the blocks of the points before and at or after the time of a `split`
+pub type Halves = (Box<[u8]>, Box<[u8]>);
+
+/// split `block` into the points before `time` and the points at or after it, the second
+/// block starts at `time`
+pub fn split(
+    block: &[u8],
+    time: u64,
+    precision: TimePrecision,
+    codec: CodecId,
+) -> Result<Halves, RewriteError> {
+    let (start, points) = read(block, precision)?;
+    let (before, after): (Vec<DataPoint>, Vec<DataPoint>) =
+        points.into_iter().partition(|dp| dp.time < time);
+    Ok((
+        write(earliest(start, &before), precision, codec, before),
+        write(time, precision, codec, after),
+    ))
+}
+
+/// encode the points of `block` again in a block which starts at `start`, which must not be
+/// after any of them
+pub fn reencode(
+    block: &[u8],
+    start: u64,
+    precision: TimePrecision,
+    codec: CodecId,
+) -> Result<Box<[u8]>, RewriteError> {
+    let (_, points) = read(block, precision)?;
+    if let Some(dp) = points.iter().find(|dp| dp.time < start) {
+        return Err(RewriteError::Start {
+            start,
+            time: dp.time,
+        });
+    }
+    Ok(write(start, precision, codec, points))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, merge_retain, reencode, split, Duplicates, RewriteError};
+    use crate::format::{CodecId, FormatVersion};
+    use crate::stream::{BufferedReader, BufferedWriter};
+    use crate::{DDSketch, DataPoint, Decode, Encode, StdDecoder, StdEncoder, Value, ValueType};
+    use common::TimePrecision;
+
+    const START: u64 = 1482268055;
+
+    fn block(version: FormatVersion, codec: CodecId, points: &[DataPoint]) -> Box<[u8]> {
+        let w = BufferedWriter::new();
+        let mut e = match version {
+            FormatVersion::V3 => StdEncoder::with_codec(START, TimePrecision::Seconds, codec, w),
+            _ => StdEncoder::with_version(START, TimePrecision::Seconds, version, w),
+        };
+        for dp in points {
+            e.encode(dp.clone());
+        }
+        e.close()
+    }
+
+    fn decode(bytes: &[u8]) -> (u64, Vec<DataPoint>) {
+        let decoder = StdDecoder::new(BufferedReader::from_bytes(bytes)).unwrap();
+        let start = decoder.start();
+        (start, decoder.points().map(|dp| dp.unwrap()).collect())
+    }
+
+    #[test]
+    fn merge_blocks() {
+        let a = block(
+            FormatVersion::V3,
+            CodecId::GorillaXor,
+            &[
+                DataPoint::new(START + 10, 1.0),
+                DataPoint::new(START + 30, 3.0),
+                DataPoint::new(START + 20, 2.0),
+            ],
+        );
+        let b = block(
+            FormatVersion::V1,
+            CodecId::GorillaXor,
+            &[
+                DataPoint::new(START + 20, 20.0),
+                DataPoint::new(START + 40, 4.0),
+            ],
+        );
+        let blocks = [&a[..], &b[..]];
+
+        let expected = |value: f64| {
+            vec![
+                DataPoint::new(START + 10, 1.0),
+                DataPoint::new(START + 20, value),
+                DataPoint::new(START + 30, 3.0),
+                DataPoint::new(START + 40, 4.0),
+            ]
+        };
+        for (duplicates, value) in [
+            (Duplicates::KeepFirst, 2.0),
+            (Duplicates::KeepLast, 20.0),
+            (Duplicates::Sum, 22.0),
+        ]
+        .iter()
+        {
+            let bytes = merge(
+                &blocks,
+                TimePrecision::Seconds,
+                CodecId::Chimp128,
+                *duplicates,
+            )
+            .unwrap();
+            assert_eq!(decode(&bytes), (START, expected(*value)));
+        }
+
+        let empty = merge(
+            &[],
+            TimePrecision::Seconds,
+            CodecId::GorillaXor,
+            Duplicates::Sum,
+        );
+        assert_eq!(decode(&empty.unwrap()), (0, vec![]));
+    }
+
+    #[test]
+    fn merge_retained() {
+        let dp = |time, value: f64| DataPoint::new(START + time, value);
+        let a = block(
+            FormatVersion::V3,
+            CodecId::GorillaXor,
+            &[dp(0, 1.0), dp(5, 2.0)],
+        );
+        let b = block(
+            FormatVersion::V3,
+            CodecId::GorillaXor,
+            &[dp(5, 3.0), dp(9, 4.0)],
+        );
+        let blocks: Vec<&[u8]> = vec![&a, &b];
+
+        // duplicates are resolved before the points are filtered
+        let kept = merge_retain(
+            &blocks,
+            TimePrecision::Seconds,
+            CodecId::GorillaXor,
+            Duplicates::KeepLast,
+            |dp| dp.time != START + 9,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(decode(&kept), (START, vec![dp(0, 1.0), dp(5, 3.0)]));
+
+        let none = merge_retain(
+            &blocks,
+            TimePrecision::Seconds,
+            CodecId::GorillaXor,
+            Duplicates::KeepLast,
+            |_| false,
+        );
+        assert_eq!(none, Ok(None));
+    }
+
+    #[test]
+    fn merge_sums() {
+        let integers = |v: i64| {
+            block(
+                FormatVersion::V3,
+                CodecId::Integer,
+                &[DataPoint::integer(START + 1, v)],
+            )
+        };
+        let (a, b) = (integers(40), integers(2));
+        let bytes = merge(
+            &[&a[..], &b[..]],
+            TimePrecision::Seconds,
+            CodecId::Integer,
+            Duplicates::Sum,
+        )
+        .unwrap();
+        assert_eq!(decode(&bytes).1, vec![DataPoint::integer(START + 1, 42)]);
+
+        let sketches = |values: &[f64]| {
+            let sketch = DDSketch::from_values(values).unwrap();
+            block(
+                FormatVersion::V3,
+                CodecId::Sketch,
+                &[DataPoint::with_value(START + 1, sketch)],
+            )
+        };
+        let (a, b) = (sketches(&[1.0, 2.0]), sketches(&[3.0]));
+        let bytes = merge(
+            &[&a[..], &b[..]],
+            TimePrecision::Seconds,
+            CodecId::Sketch,
+            Duplicates::Sum,
+        )
+        .unwrap();
+        let sketch = DDSketch::from_values(&[1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(
+            decode(&bytes).1,
+            vec![DataPoint::with_value(START + 1, sketch)]
+        );
+
+        let strings = |s: &str| {
+            block(
+                FormatVersion::V3,
+                CodecId::String,
+                &[DataPoint::with_value(START + 1, s)],
+            )
+        };
+        let (a, b) = (strings("a"), strings("b"));
+        let err = merge(
+            &[&a[..], &b[..]],
+            TimePrecision::Seconds,
+            CodecId::String,
+            Duplicates::Sum,
+        );
+        assert_eq!(
+            err.err().unwrap(),
+            RewriteError::Sum(ValueType::String, ValueType::String)
+        );
+        let bytes = merge(
+            &[&a[..], &b[..]],
+            TimePrecision::Seconds,
+            CodecId::String,
+            Duplicates::KeepLast,
+        )
+        .unwrap();
+        assert_eq!(decode(&bytes).1[0].value, Value::from("b"));
+    }
+
+    #[test]
+    fn split_and_reencode() {
+        let points: Vec<DataPoint> = (0..1000)
+            .map(|i| DataPoint::new(START + 10 * i, (i % 13) as f64 * 0.5))
+            .collect();
+        let bytes = block(FormatVersion::V2, CodecId::GorillaXor, &points);
+
+        let (before, after) = split(
+            &bytes,
+            START + 5000,
+            TimePrecision::Seconds,
+            CodecId::GorillaXor,
+        )
+        .unwrap();
+        assert_eq!(decode(&before), (START, points[..500].to_vec()));
+        assert_eq!(decode(&after), (START + 5000, points[500..].to_vec()));
+
+        // merging the halves gives the block back
+        let merged = merge(
+            &[&after[..], &before[..]],
+            TimePrecision::Seconds,
+            CodecId::GorillaXor,
+            Duplicates::KeepFirst,
+        )
+        .unwrap();
+        assert_eq!(decode(&merged), (START, points.clone()));
+
+        let moved = reencode(&after, START, TimePrecision::Seconds, CodecId::Decimal).unwrap();
+        assert_eq!(decode(&moved), (START, points[500..].to_vec()));
+        assert_eq!(
+            reencode(
+                &bytes,
+                START + 1,
+                TimePrecision::Seconds,
+                CodecId::GorillaXor
+            ),
+            Err(RewriteError::Start {
+                start: START + 1,
+                time: START
+            })
+        );
+
+        let mut corrupted = bytes.to_vec();
+        corrupted.truncate(corrupted.len() / 2);
+        assert!(split(
+            &corrupted,
+            START,
+            TimePrecision::Seconds,
+            CodecId::GorillaXor
+        )
+        .is_err());
+    }
+}
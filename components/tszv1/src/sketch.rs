@@ -0,0 +1,283 @@
+//! DDSketch, a mergeable quantile sketch with a relative error guarantee.
+//!
+//! A value `x` is counted in the bin `ceil(log_gamma(|x|))` with `gamma = (1 + alpha) / (1 - alpha)`,
+//! every quantile is then estimated within a relative error of `alpha` of the exact value. Two
+//! sketches of the same accuracy merge by adding their bins, so latencies recorded per point
+//! can be re-aggregated over any time range and any set of series. See
+//! [DDSketch: A Fast and Fully-Mergeable Quantile Sketch with Relative-Error Guarantees](https://arxiv.org/abs/1908.10693).
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// relative accuracy of the sketches built by the server
+pub const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// values closer to zero are counted in the zero bin
+pub const MIN_INDEXABLE_VALUE: f64 = 1e-9;
+
+/// bins per sign, the bins of the smallest magnitudes are collapsed beyond it. With the default
+/// accuracy 2048 bins cover 18 orders of magnitude.
+pub const MAX_BINS: usize = 2048;
+
+/// SketchError
+///
+/// The errors of building or merging sketches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SketchError {
+    /// the relative accuracy is not in (0, 1)
+    Accuracy(f64),
+    /// sketches of different accuracies can not be merged
+    Mismatch(f64, f64),
+    /// NaN and infinite values can not be counted
+    Value(f64),
+}
+
+impl fmt::Display for SketchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SketchError::Accuracy(alpha) => {
+                write!(f, "relative accuracy must be in (0, 1), got {}", alpha)
+            }
+            SketchError::Mismatch(a, b) => {
+                write!(f, "sketches of accuracy {} and {} can not be merged", a, b)
+            }
+            SketchError::Value(v) => write!(f, "value {} can not be counted", v),
+        }
+    }
+}
+
+impl std::error::Error for SketchError {}
+
+/// DDSketch
+///
+/// The distribution of a set of values, see the module documentation. Bins are keyed by index,
+/// `negative` counts the magnitudes of the negative values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DDSketch {
+    pub alpha: f64,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub zero: u64,
+    pub positive: BTreeMap<i32, u64>,
+    pub negative: BTreeMap<i32, u64>,
+}
+
+impl DDSketch {
+    pub fn new(alpha: f64) -> Result<Self, SketchError> {
+        // written to reject NaN
+        if !(alpha > 0.0 && alpha < 1.0) {
+            return Err(SketchError::Accuracy(alpha));
+        }
+        Ok(DDSketch {
+            alpha,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            zero: 0,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+        })
+    }
+
+    /// a sketch of `values` with the default accuracy
+    pub fn from_values(values: &[f64]) -> Result<Self, SketchError> {
+        let mut sketch = DDSketch::new(DEFAULT_RELATIVE_ACCURACY)?;
+        for v in values {
+            sketch.add(*v)?;
+        }
+        Ok(sketch)
+    }
+
+    fn gamma(&self) -> f64 {
+        (1.0 + self.alpha) / (1.0 - self.alpha)
+    }
+
+    fn index(&self, magnitude: f64) -> i32 {
+        (magnitude.ln() / self.gamma().ln()).ceil() as i32
+    }
+
+    /// the estimate of the values of bin `index`, within `alpha` of all of them
+    fn bin_value(&self, index: i32) -> f64 {
+        let gamma = self.gamma();
+        2.0 * gamma.powi(index) / (gamma + 1.0)
+    }
+
+    pub fn add(&mut self, value: f64) -> Result<(), SketchError> {
+        if !value.is_finite() {
+            return Err(SketchError::Value(value));
+        }
+
+        if value.abs() < MIN_INDEXABLE_VALUE {
+            self.zero += 1;
+        } else {
+            let index = self.index(value.abs());
+            let bins = if value > 0.0 {
+                &mut self.positive
+            } else {
+                &mut self.negative
+            };
+            *bins.entry(index).or_default() += 1;
+            collapse(bins);
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        Ok(())
+    }
+
+    /// add the values counted by `other` to the sketch
+    pub fn merge(&mut self, other: &DDSketch) -> Result<(), SketchError> {
+        if self.alpha != other.alpha {
+            return Err(SketchError::Mismatch(self.alpha, other.alpha));
+        }
+
+        for (index, count) in &other.positive {
+            *self.positive.entry(*index).or_default() += count;
+        }
+        for (index, count) in &other.negative {
+            *self.negative.entry(*index).or_default() += count;
+        }
+        collapse(&mut self.positive);
+        collapse(&mut self.negative);
+        self.zero += other.zero;
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        Ok(())
+    }
+
+    /// the estimate of the `q` quantile, `None` for an empty sketch or `q` outside [0, 1]
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+
+        let rank = q * (self.count - 1) as f64;
+        let mut seen = 0u64;
+        // in value order: the largest negative magnitudes first, then zero, then positives
+        for (index, count) in self.negative.iter().rev() {
+            seen += count;
+            if seen as f64 > rank {
+                return Some(self.clamp(-self.bin_value(*index)));
+            }
+        }
+        seen += self.zero;
+        if seen as f64 > rank {
+            return Some(self.clamp(0.0));
+        }
+        for (index, count) in &self.positive {
+            seen += count;
+            if seen as f64 > rank {
+                return Some(self.clamp(self.bin_value(*index)));
+            }
+        }
+        Some(self.max)
+    }
+
+    fn clamp(&self, value: f64) -> f64 {
+        value.max(self.min).min(self.max)
+    }
+
+    /// whether the counts agree with each other, decoded sketches are checked before use
+    pub fn is_consistent(&self) -> bool {
+        let binned: u64 = self
+            .positive
+            .values()
+            .chain(self.negative.values())
+            .fold(self.zero, |a, b| a.saturating_add(*b));
+        binned == self.count
+            && self.alpha > 0.0
+            && self.alpha < 1.0
+            && self.positive.len() <= MAX_BINS
+            && self.negative.len() <= MAX_BINS
+    }
+}
+
+/// fold the bins of the smallest magnitudes into their neighbour until `MAX_BINS` remain
+fn collapse(bins: &mut BTreeMap<i32, u64>) {
+    while bins.len() > MAX_BINS {
+        let (_, count) = bins.pop_first().unwrap();
+        if let Some(mut next) = bins.first_entry() {
+            *next.get_mut() += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sketch::{DDSketch, SketchError, MAX_BINS};
+
+    fn assert_close(estimate: f64, exact: f64, alpha: f64) {
+        assert!(
+            (estimate - exact).abs() <= alpha * exact.abs() + 1e-9,
+            "{} is not within {} of {}",
+            estimate,
+            alpha,
+            exact
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let values: Vec<f64> = (1..=1000).map(|i| i as f64).collect();
+        let sketch = DDSketch::from_values(&values).unwrap();
+        assert_eq!(sketch.count, 1000);
+        assert_eq!(sketch.quantile(0.0), Some(1.0));
+        assert_eq!(sketch.quantile(1.0), Some(1000.0));
+        assert_close(sketch.quantile(0.5).unwrap(), 500.0, 0.01);
+        assert_close(sketch.quantile(0.99).unwrap(), 990.0, 0.01);
+        assert_eq!(sketch.quantile(1.5), None);
+        assert!(sketch.is_consistent());
+    }
+
+    #[test]
+    fn signed_values() {
+        let sketch = DDSketch::from_values(&[-100.0, -10.0, 0.0, 10.0, 100.0]).unwrap();
+        assert_eq!(sketch.quantile(0.0), Some(-100.0));
+        assert_close(sketch.quantile(0.25).unwrap(), -10.0, 0.01);
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        assert_close(sketch.quantile(0.75).unwrap(), 10.0, 0.01);
+    }
+
+    #[test]
+    fn merge() {
+        let low: Vec<f64> = (1..=500).map(|i| i as f64).collect();
+        let high: Vec<f64> = (501..=1000).map(|i| i as f64).collect();
+        let mut sketch = DDSketch::from_values(&low).unwrap();
+        sketch
+            .merge(&DDSketch::from_values(&high).unwrap())
+            .unwrap();
+
+        let all: Vec<f64> = (1..=1000).map(|i| i as f64).collect();
+        assert_eq!(sketch, DDSketch::from_values(&all).unwrap());
+
+        let other = DDSketch::new(0.05).unwrap();
+        assert_eq!(sketch.merge(&other), Err(SketchError::Mismatch(0.01, 0.05)));
+    }
+
+    #[test]
+    fn bounded_bins() {
+        let mut sketch = DDSketch::new(0.001).unwrap();
+        for i in 0..100_000 {
+            sketch.add(1.001f64.powi(i / 10)).unwrap();
+        }
+        assert_eq!(sketch.positive.len(), MAX_BINS);
+        assert!(sketch.is_consistent());
+        // the high quantiles keep their accuracy
+        assert_close(sketch.quantile(0.99).unwrap(), 1.001f64.powi(9900), 0.001);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(DDSketch::new(1.0), Err(SketchError::Accuracy(1.0)));
+        assert!(DDSketch::new(f64::NAN).is_err());
+        let mut sketch = DDSketch::new(0.01).unwrap();
+        assert!(sketch.add(f64::INFINITY).is_err());
+        assert_eq!(sketch.quantile(0.5), None);
+    }
+}
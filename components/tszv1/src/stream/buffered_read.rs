@@ -4,14 +4,81 @@ use crate::stream::{Error, Read};
 use crate::Buffer;
 use crate::{buffer_into_vec, Bit};
 
+/// ByteSource
+///
+/// ByteSource is the immutable bytes read by a `BufferedReader`. Besides a `Buffer` any byte
+/// slice can be read, readers of an `Arc<[u8]>` or of a borrowed `&[u8]` share one copy.
+pub trait ByteSource {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the byte at `index`, `None` past the end
+    fn byte(&self, index: usize) -> Option<u8>;
+
+    /// CRC-32 of the bytes from `offset` on
+    fn checksum(&self, offset: usize) -> u32;
+}
+
+impl ByteSource for Buffer {
+    fn len(&self) -> usize {
+        Buffer::len(self)
+    }
+
+    fn byte(&self, index: usize) -> Option<u8> {
+        self.get(index).cloned()
+    }
+
+    fn checksum(&self, offset: usize) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        let mut skip = offset;
+        for chunk in self.chunks() {
+            hasher.update(&chunk[skip.min(chunk.len())..]);
+            skip = skip.saturating_sub(chunk.len());
+        }
+        hasher.finalize()
+    }
+}
+
+impl ByteSource for &Buffer {
+    fn len(&self) -> usize {
+        Buffer::len(self)
+    }
+
+    fn byte(&self, index: usize) -> Option<u8> {
+        self.get(index).cloned()
+    }
+
+    fn checksum(&self, offset: usize) -> u32 {
+        (*self).checksum(offset)
+    }
+}
+
+impl<T: AsRef<[u8]>> ByteSource for T {
+    fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    fn byte(&self, index: usize) -> Option<u8> {
+        self.as_ref().get(index).cloned()
+    }
+
+    fn checksum(&self, offset: usize) -> u32 {
+        let bytes = self.as_ref();
+        crate::format::checksum(&bytes[offset.min(bytes.len())..])
+    }
+}
+
 /// BufferedReader
 ///
 /// BufferedReader encapsulates a buffer of bytes which can be read from.
 #[derive(Debug)]
-pub struct BufferedReader {
-    bytes: Buffer, // internal buffer of bytes
-    index: usize,  // index into bytes
-    pos: u32,      // position in the byte we are currently reading
+pub struct BufferedReader<B = Buffer> {
+    bytes: B,     // internal buffer of bytes
+    index: usize, // index into bytes
+    pos: u32,     // position in the byte we are currently reading
 }
 
 impl BufferedReader {
@@ -32,13 +99,42 @@ impl BufferedReader {
             pos: 0,
         }
     }
+}
+
+impl<B: ByteSource> BufferedReader<B> {
+    /// a `BufferedReader` of any `ByteSource`, such as bytes shared with other readers
+    pub fn from_bytes(bytes: B) -> Self {
+        BufferedReader {
+            bytes,
+            index: 0,
+            pos: 0,
+        }
+    }
 
     fn get_byte(&mut self) -> Result<u8, Error> {
-        self.bytes.get(self.index).cloned().ok_or(Error::EOF)
+        self.bytes.byte(self.index).ok_or(Error::EOF)
     }
 }
 
-impl Read for BufferedReader {
+impl<B: ByteSource> Read for BufferedReader<B> {
+    fn checksum(&self, offset: usize) -> u32 {
+        self.bytes.checksum(offset)
+    }
+
+    fn seek_bit(&mut self, offset: u64) -> Result<(), Error> {
+        if offset > self.bytes.len() as u64 * 8 {
+            return Err(Error::EOF);
+        }
+
+        self.index = (offset / 8) as usize;
+        self.pos = (offset % 8) as u32;
+        Ok(())
+    }
+
+    fn bit_offset(&self) -> u64 {
+        self.index as u64 * 8 + u64::from(self.pos)
+    }
+
     fn read_bit(&mut self) -> Result<Bit, Error> {
         if self.pos == 8 {
             self.index += 1;
@@ -122,8 +218,9 @@ impl Read for BufferedReader {
 #[cfg(test)]
 mod tests {
     use super::BufferedReader;
-    use crate::stream::{Error, Read};
+    use crate::stream::{BufferedWriter, Error, Read, Write};
     use crate::Bit;
+    use std::sync::Arc;
 
     #[test]
     fn read_bit() {
@@ -219,4 +316,43 @@ mod tests {
 
         assert_eq!(b.peak_bits(22).err().unwrap(), Error::EOF);
     }
+
+    #[test]
+    fn seek_bit() {
+        let bytes = vec![0b01101101, 0b01101101];
+        let mut b = BufferedReader::new(bytes.into_boxed_slice());
+
+        b.seek_bit(5).unwrap();
+        assert_eq!(b.read_bits(5).unwrap(), 0b10101);
+        b.seek_bit(8).unwrap();
+        assert_eq!(b.read_byte().unwrap(), 0b01101101);
+        b.seek_bit(1).unwrap();
+        assert_eq!(b.read_bit().unwrap(), Bit::One);
+
+        assert_eq!(b.seek_bit(17).err().unwrap(), Error::EOF);
+    }
+
+    #[test]
+    fn shared_bytes() {
+        let bytes: Arc<[u8]> = Arc::from(vec![0b01101100, 0b11101001]);
+        let mut a = BufferedReader::from_bytes(bytes.clone());
+        let mut b = BufferedReader::from_bytes(bytes.clone());
+        assert_eq!(a.read_bits(12).unwrap(), 0b011011001110);
+        assert_eq!(b.read_byte().unwrap(), 0b01101100);
+        assert_eq!(a.checksum(1), b.checksum(1));
+
+        let mut borrowed = BufferedReader::from_bytes(&bytes[1..]);
+        assert_eq!(borrowed.read_byte().unwrap(), 0b11101001);
+        assert_eq!(borrowed.read_bit().err().unwrap(), Error::EOF);
+
+        // the bytes of a writer are read in place
+        let mut w = BufferedWriter::new();
+        w.write_bits(0b10_1101_1001, 10);
+        let mut r = BufferedReader::from_bytes(w.buffer());
+        assert_eq!(r.read_bits(16).unwrap(), 0b1011_0110_0100_0000);
+        assert_eq!(
+            r.checksum(0),
+            BufferedReader::new(w.clone().close()).checksum(0)
+        );
+    }
 }
@@ -31,6 +31,12 @@ impl BufferedWriter {
         }
     }
 
+    /// the bytes written so far, without an end of stream marker. A `BufferedReader` borrowing
+    /// them reads the stream without closing the writer.
+    pub fn buffer(&self) -> &Buffer {
+        &self.buf
+    }
+
     fn grow(&mut self) {
         self.buf.push(0);
     }
@@ -107,6 +113,11 @@ impl Write for BufferedWriter {
     fn close(self) -> Box<[u8]> {
         self.buf.into_boxed_slice()
     }
+
+    fn bit_len(&self) -> u64 {
+        // pos counts the bits used in the last byte
+        self.buf.len() as u64 * 8 - u64::from(8 - self.pos)
+    }
 }
 
 // TODO clone always
@@ -258,4 +269,17 @@ mod tests {
         assert_eq!(b.buf[2], 156); // 0b10011100 = 156
         assert_eq!(b.buf[3], 207); // 0b11001111 = 207
     }
+
+    #[test]
+    fn bit_len() {
+        let mut b = BufferedWriter::new();
+        assert_eq!(b.bit_len(), 0);
+
+        b.write_bit(Bit::One);
+        assert_eq!(b.bit_len(), 1);
+        b.write_byte(9);
+        assert_eq!(b.bit_len(), 9);
+        b.write_bits(2508, 23);
+        assert_eq!(b.bit_len(), 32);
+    }
 }
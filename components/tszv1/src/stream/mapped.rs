@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::stream::{BufferedReader, Error, Read};
+use crate::Bit;
+
+/// MappedFile
+///
+/// MappedFile is a byte slice of a file mapped read only into memory. Its pages are read from
+/// the page cache when touched, nothing is copied to the heap and the OS decides which pages
+/// stay in memory. Clones and sub slices share the mapping, which outlives the removal of the
+/// file.
+///
+/// The file must not be written or truncated while it is mapped, as by the engine, which
+/// writes block files once under a temporary name and renames them.
+#[derive(Debug, Clone)]
+pub struct MappedFile {
+    map: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+impl MappedFile {
+    /// map the whole file at `path`
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // safety: see the type documentation, the file is not changed while mapped
+        let map = unsafe { Mmap::map(&file)? };
+        let range = 0..map.len();
+        Ok(MappedFile {
+            map: Arc::new(map),
+            range,
+        })
+    }
+
+    /// the bytes in `range` of this slice, sharing the mapping, `None` past its end
+    pub fn slice(&self, range: Range<usize>) -> Option<MappedFile> {
+        if range.start > range.end || range.end > self.range.len() {
+            return None;
+        }
+        Some(MappedFile {
+            map: self.map.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        })
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+}
+
+/// MmapReader
+///
+/// MmapReader reads the bits of a `MappedFile` in place, so a block file is decoded straight
+/// from the page cache.
+#[derive(Debug)]
+pub struct MmapReader {
+    inner: BufferedReader<MappedFile>,
+}
+
+impl MmapReader {
+    pub fn new(file: MappedFile) -> Self {
+        MmapReader {
+            inner: BufferedReader::from_bytes(file),
+        }
+    }
+
+    /// a reader of the whole file at `path`
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(MmapReader::new(MappedFile::open(path)?))
+    }
+}
+
+impl Read for MmapReader {
+    fn read_bit(&mut self) -> Result<Bit, Error> {
+        self.inner.read_bit()
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        self.inner.read_byte()
+    }
+
+    fn read_bits(&mut self, num: u32) -> Result<u64, Error> {
+        self.inner.read_bits(num)
+    }
+
+    fn peak_bits(&mut self, num: u32) -> Result<u64, Error> {
+        self.inner.peak_bits(num)
+    }
+
+    fn checksum(&self, offset: usize) -> u32 {
+        self.inner.checksum(offset)
+    }
+
+    fn seek_bit(&mut self, offset: u64) -> Result<(), Error> {
+        self.inner.seek_bit(offset)
+    }
+
+    fn bit_offset(&self) -> u64 {
+        self.inner.bit_offset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MappedFile, MmapReader};
+    use crate::stream::{BufferedReader, BufferedWriter, Error, Read, Write};
+    use crate::{DataPoint, Encode, StdDecoder, StdEncoder};
+
+    #[test]
+    fn mapped_bytes() {
+        let path = std::env::temp_dir().join(format!("tszv1-mapped-{}", std::process::id()));
+        let mut w = BufferedWriter::new();
+        w.write_bits(0b1011_0110_0100_1110, 16);
+        let bytes = w.close();
+        std::fs::write(&path, &bytes).unwrap();
+
+        let file = MappedFile::open(&path).unwrap();
+        // the mapping outlives the file
+        std::fs::remove_file(&path).unwrap();
+        let mut r = MmapReader::new(file.clone());
+        assert_eq!(r.read_bits(12).unwrap(), 0b1011_0110_0100);
+        assert_eq!(
+            r.checksum(1),
+            BufferedReader::new(bytes.clone()).checksum(1)
+        );
+        r.seek_bit(14).unwrap();
+        assert_eq!(r.read_bits(2).unwrap(), 0b10);
+        assert_eq!(r.read_bit().err().unwrap(), Error::EOF);
+
+        let mut tail = MmapReader::new(file.slice(1..2).unwrap());
+        assert_eq!(tail.read_byte().unwrap(), 0b0100_1110);
+        assert_eq!(tail.read_bit().err().unwrap(), Error::EOF);
+        assert!(file.slice(1..3).is_none());
+    }
+
+    #[test]
+    fn decode_mapped_block() {
+        let path = std::env::temp_dir().join(format!("tszv1-block-{}", std::process::id()));
+        let mut encoder = StdEncoder::new(0, BufferedWriter::new());
+        let points: Vec<DataPoint> = (0..100).map(|i| DataPoint::new(i * 10, i as f64)).collect();
+        for dp in &points {
+            encoder.encode(dp.clone());
+        }
+        std::fs::write(&path, encoder.close()).unwrap();
+
+        let decoder = StdDecoder::new(MmapReader::open(&path).unwrap()).unwrap();
+        let decoded: Vec<DataPoint> = decoder.into_iter().map(|dp| dp.unwrap()).collect();
+        assert_eq!(decoded, points);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -41,12 +41,36 @@ pub trait Read {
 
     /// Get the next `num` bits, but do not update place in stream.
     fn peak_bits(&mut self, num: u32) -> Result<u64, Error>;
+
+    /// CRC-32 of the whole underlying stream from byte `offset` on, does not update place in
+    /// stream.
+    fn checksum(&self, offset: usize) -> u32;
+
+    /// Move the place in stream to `offset` bits from the start.
+    fn seek_bit(&mut self, offset: u64) -> Result<(), Error>;
+
+    /// The place in stream in bits from the start, as taken by `seek_bit`.
+    fn bit_offset(&self) -> u64;
+
+    /// Read a variable length integer written by `Write::write_varint`.
+    fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let group = self.read_bits(8)?;
+            value |= (group & 0x7f).wrapping_shl(shift);
+            if group & 0x80 == 0 || shift >= 63 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
 }
 
 /// Write
 ///
 /// Write is a trait that encapsulates the functionality required to write a stream of bytes.
-pub trait Write : Clone{
+pub trait Write: Clone {
     // Write a single bit to the underlying stream.
     fn write_bit(&mut self, bit: Bit);
 
@@ -58,10 +82,37 @@ pub trait Write : Clone{
 
     // Close the underlying stream and return a pointer to the array of bytes.
     fn close(self) -> Box<[u8]>;
+
+    // Count of bits written so far.
+    fn bit_len(&self) -> u64;
+
+    // Mark the bits written so far as complete, readers sharing the stream may see them from
+    // now on. Nothing to do for streams which are only read once closed.
+    fn commit(&mut self) {}
+
+    // Write `bits` as a variable length integer: groups of 7 bits, lowest first, each preceded
+    // by a continuation bit. Values below 128 take 8 bits, a full u64 takes 80 bits.
+    fn write_varint(&mut self, mut bits: u64) {
+        loop {
+            let group = bits & 0x7f;
+            bits >>= 7;
+            if bits == 0 {
+                self.write_bits(group, 8);
+                return;
+            }
+            self.write_bits(0x80 | group, 8);
+        }
+    }
 }
 
 pub mod buffered_write;
 pub use self::buffered_write::BufferedWriter;
 
 pub mod buffered_read;
-pub use self::buffered_read::BufferedReader;
+pub use self::buffered_read::{BufferedReader, ByteSource};
+
+pub mod shared;
+pub use self::shared::{SharedReader, SharedWriter};
+
+pub mod mapped;
+pub use self::mapped::{MappedFile, MmapReader};
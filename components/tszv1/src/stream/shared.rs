@@ -0,0 +1,334 @@
+use std::boxed::Box;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::stream::{Error, Read, Write};
+use crate::Bit;
+
+/// bytes per chunk, chunks are never moved or reallocated once created
+pub const CHUNK_LEN: usize = 4096;
+
+type Chunk = Arc<[AtomicU8]>;
+
+fn new_chunk() -> Chunk {
+    (0..CHUNK_LEN).map(|_| AtomicU8::new(0)).collect()
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    chunks: RwLock<Vec<Chunk>>,
+    committed: AtomicU64, // bits readable by snapshots
+}
+
+/// SharedWriter
+///
+/// SharedWriter writes bits to chunks shared with the `SharedReader` snapshots taken from it.
+/// The bits written up to the last `commit` are never changed again, so a snapshot reads them
+/// in place while the writer keeps appending; the bytes are atomics, which makes or-ing bits
+/// into the last byte safe while it is read. Cloning a SharedWriter copies its bytes into new
+/// chunks, two writers never share chunks.
+#[derive(Debug)]
+pub struct SharedWriter {
+    shared: Arc<Shared>,
+    tail: Option<Chunk>, // the chunk holding bit `len`
+    len: u64,            // bits written
+}
+
+impl SharedWriter {
+    pub fn new() -> Self {
+        SharedWriter {
+            shared: Arc::new(Shared::default()),
+            tail: None,
+            len: 0,
+        }
+    }
+
+    /// a reader of the bits committed so far followed by the `tail_len` lowest bits of `tail`,
+    /// which lets the decoder find an end of stream marker the writer has not written yet
+    pub fn snapshot(&self, tail: u128, tail_len: u32) -> SharedReader {
+        let committed = self.shared.committed.load(Ordering::Acquire);
+        let chunks = self.shared.chunks.read().unwrap().clone();
+        SharedReader {
+            chunks,
+            committed,
+            tail,
+            tail_len,
+            pos: 0,
+        }
+    }
+
+    /// the chunk holding bit `len`, created when `len` is at a chunk boundary
+    fn tail(&mut self) -> &Chunk {
+        let index = (self.len / 8) as usize;
+        if index.is_multiple_of(CHUNK_LEN) && self.len.is_multiple_of(8) {
+            let chunk = new_chunk();
+            self.shared.chunks.write().unwrap().push(chunk.clone());
+            self.tail = Some(chunk);
+        }
+        self.tail.as_ref().unwrap()
+    }
+
+    /// or the `num` lowest bits of `bits` into the current byte, `num` must fit in it
+    fn write_in_byte(&mut self, bits: u64, num: u32) {
+        let offset = (self.len % 8) as u32;
+        let index = (self.len / 8) as usize % CHUNK_LEN;
+        let byte = ((bits & ((1 << num) - 1)) << (8 - offset - num)) as u8;
+        if byte != 0 {
+            self.tail()[index].fetch_or(byte, Ordering::Relaxed);
+        } else {
+            self.tail();
+        }
+        self.len += u64::from(num);
+    }
+}
+
+impl Default for SharedWriter {
+    fn default() -> Self {
+        SharedWriter::new()
+    }
+}
+
+impl Clone for SharedWriter {
+    fn clone(&self) -> Self {
+        let mut w = SharedWriter::new();
+        for chunk in self.shared.chunks.read().unwrap().iter() {
+            let copy: Chunk = chunk
+                .iter()
+                .map(|b| AtomicU8::new(b.load(Ordering::Relaxed)))
+                .collect();
+            w.shared.chunks.write().unwrap().push(copy.clone());
+            w.tail = Some(copy);
+        }
+        w.len = self.len;
+        w.shared.committed.store(
+            self.shared.committed.load(Ordering::Acquire),
+            Ordering::Release,
+        );
+        w
+    }
+}
+
+impl Write for SharedWriter {
+    fn write_bit(&mut self, bit: Bit) {
+        self.write_in_byte(bit.to_u64(), 1);
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.write_bits(u64::from(byte), 8);
+    }
+
+    fn write_bits(&mut self, bits: u64, num: u32) {
+        // we should never write more than 64 bits for a u64
+        let mut num = num.min(64);
+        while num > 0 {
+            let room = 8 - (self.len % 8) as u32;
+            let n = room.min(num);
+            self.write_in_byte(bits >> (num - n), n);
+            num -= n;
+        }
+    }
+
+    fn close(self) -> Box<[u8]> {
+        let len = self.len.div_ceil(8) as usize;
+        let chunks = self.shared.chunks.read().unwrap();
+        chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter())
+            .take(len)
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    fn bit_len(&self) -> u64 {
+        self.len
+    }
+
+    fn commit(&mut self) {
+        self.shared.committed.store(self.len, Ordering::Release);
+    }
+}
+
+/// SharedReader
+///
+/// SharedReader reads a snapshot of a `SharedWriter`: the bits committed when the snapshot was
+/// taken, then the tail bits given to `SharedWriter::snapshot`. Bits written later are not
+/// seen even though they land in the same chunks.
+#[derive(Debug, Clone)]
+pub struct SharedReader {
+    chunks: Vec<Chunk>,
+    committed: u64,
+    tail: u128,
+    tail_len: u32,
+    pos: u64, // bits read
+}
+
+impl SharedReader {
+    /// bits in the snapshot
+    pub fn bit_len(&self) -> u64 {
+        self.committed + u64::from(self.tail_len)
+    }
+
+    fn bit_at(&self, pos: u64) -> Result<u64, Error> {
+        if pos < self.committed {
+            let index = (pos / 8) as usize;
+            let byte = self.chunks[index / CHUNK_LEN][index % CHUNK_LEN].load(Ordering::Relaxed);
+            Ok(u64::from(byte >> (7 - pos % 8)) & 1)
+        } else if pos < self.bit_len() {
+            let shift = self.bit_len() - pos - 1;
+            Ok((self.tail >> shift) as u64 & 1)
+        } else {
+            Err(Error::EOF)
+        }
+    }
+}
+
+impl Read for SharedReader {
+    fn read_bit(&mut self) -> Result<Bit, Error> {
+        let bit = self.bit_at(self.pos)?;
+        self.pos += 1;
+        Ok(if bit == 0 { Bit::Zero } else { Bit::One })
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        self.read_bits(8).map(|bits| bits as u8)
+    }
+
+    fn read_bits(&mut self, num: u32) -> Result<u64, Error> {
+        // can't read more than 64 bits into a u64
+        let num = num.min(64);
+        if self.pos + u64::from(num) > self.bit_len() {
+            self.pos = self.bit_len();
+            return Err(Error::EOF);
+        }
+
+        let mut bits = 0u64;
+        let mut left = num;
+        while left > 0 {
+            // whole bytes of the committed bits at once
+            if self.pos.is_multiple_of(8) && left >= 8 && self.pos + 8 <= self.committed {
+                let index = (self.pos / 8) as usize;
+                let byte =
+                    self.chunks[index / CHUNK_LEN][index % CHUNK_LEN].load(Ordering::Relaxed);
+                bits = bits.wrapping_shl(8) | u64::from(byte);
+                self.pos += 8;
+                left -= 8;
+            } else {
+                bits = bits.wrapping_shl(1) | self.bit_at(self.pos)?;
+                self.pos += 1;
+                left -= 1;
+            }
+        }
+        Ok(bits)
+    }
+
+    fn peak_bits(&mut self, num: u32) -> Result<u64, Error> {
+        let pos = self.pos;
+        let bits = self.read_bits(num);
+        self.pos = pos;
+        bits
+    }
+
+    fn checksum(&self, offset: usize) -> u32 {
+        let mut r = self.clone();
+        r.pos = offset as u64 * 8;
+        let mut payload = Vec::new();
+        while r.pos + 8 <= r.bit_len() {
+            payload.push(r.read_byte().unwrap());
+        }
+        if r.pos < r.bit_len() {
+            // the last byte is padded with zeroes as by `Write::close`
+            let rest = (r.bit_len() - r.pos) as u32;
+            payload.push((r.read_bits(rest).unwrap() << (8 - rest)) as u8);
+        }
+        crate::format::checksum(&payload)
+    }
+
+    fn seek_bit(&mut self, offset: u64) -> Result<(), Error> {
+        if offset > self.bit_len() {
+            return Err(Error::EOF);
+        }
+        self.pos = offset;
+        Ok(())
+    }
+
+    fn bit_offset(&self) -> u64 {
+        self.pos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SharedWriter, CHUNK_LEN};
+    use crate::stream::{BufferedWriter, Error, Read, Write};
+    use crate::Bit;
+
+    #[test]
+    fn same_bytes_as_buffered() {
+        let mut shared = SharedWriter::new();
+        let mut buffered = BufferedWriter::new();
+        for i in 0..(CHUNK_LEN as u64 * 3) {
+            shared.write_bits(i * 7919, (i % 23) as u32 + 1);
+            buffered.write_bits(i * 7919, (i % 23) as u32 + 1);
+            shared.write_bit(Bit::One);
+            buffered.write_bit(Bit::One);
+        }
+        assert_eq!(shared.bit_len(), buffered.bit_len());
+        assert_eq!(shared.clone().close(), buffered.close());
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut w = SharedWriter::new();
+        w.write_bits(0b1011, 4);
+        w.commit();
+        w.write_bits(0b111, 3);
+
+        // uncommitted bits are not seen, the tail follows the committed bits
+        let mut r = w.snapshot(0b00, 2);
+        assert_eq!(r.bit_len(), 6);
+        assert_eq!(r.read_bits(6).unwrap(), 0b101100);
+        assert_eq!(r.read_bit().err().unwrap(), Error::EOF);
+
+        w.commit();
+        w.write_byte(0xff);
+        let mut later = w.snapshot(0, 0);
+        assert_eq!(later.read_bits(7).unwrap(), 0b1011111);
+        assert_eq!(later.read_bit().err().unwrap(), Error::EOF);
+
+        // the first snapshot is unchanged by the writes which followed
+        r.seek_bit(0).unwrap();
+        assert_eq!(r.read_bits(6).unwrap(), 0b101100);
+    }
+
+    #[test]
+    fn concurrent_appends() {
+        let mut w = SharedWriter::new();
+        let snapshots = std::thread::scope(|scope| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let reader = scope.spawn(move || {
+                let mut seen = Vec::new();
+                while let Ok(r) = rx.recv() {
+                    seen.push(r);
+                }
+                seen
+            });
+            for i in 0..20_000u64 {
+                w.write_bits(i, 16);
+                w.commit();
+                if i % 1000 == 0 {
+                    tx.send(w.snapshot(0, 0)).unwrap();
+                }
+            }
+            drop(tx);
+            reader.join().unwrap()
+        });
+
+        for (n, mut r) in snapshots.into_iter().enumerate() {
+            let count = n as u64 * 1000 + 1;
+            assert_eq!(r.bit_len(), count * 16);
+            for i in 0..count {
+                assert_eq!(r.read_bits(16).unwrap(), i);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::sketch::DDSketch;
+use std::fmt;
+
+/// ValueType
+///
+/// The type of the values of a block, decided by its codec.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Float,
+    Integer,
+    Bool,
+    /// low cardinality strings such as states or enums
+    String,
+    /// distributions, see `DDSketch`
+    Sketch,
+}
+
+impl ValueType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::Float => "float",
+            ValueType::Integer => "integer",
+            ValueType::Bool => "bool",
+            ValueType::String => "string",
+            ValueType::Sketch => "sketch",
+        }
+    }
+
+    /// whether values of the type can be summed and averaged
+    pub fn is_numeric(self) -> bool {
+        match self {
+            ValueType::Float | ValueType::Integer => true,
+            ValueType::Bool | ValueType::String | ValueType::Sketch => false,
+        }
+    }
+}
+
+/// Value
+///
+/// The value of a `DataPoint`. Integers are kept apart from floats so that counters above
+/// 2^53 keep every digit, they serialize as json numbers without a fraction. Bools and strings
+/// serialize as json booleans and strings, sketches as objects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Sketch(Box<DDSketch>),
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Float(_) => ValueType::Float,
+            Value::Integer(_) => ValueType::Integer,
+            Value::Bool(_) => ValueType::Bool,
+            Value::String(_) => ValueType::String,
+            Value::Sketch(_) => ValueType::Sketch,
+        }
+    }
+
+    /// the value as a float, integers beyond 2^53 are rounded, bools are 0 or 1, strings and
+    /// sketches are NaN
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Value::Float(v) => v,
+            Value::Integer(v) => v as f64,
+            Value::Bool(v) => f64::from(u8::from(v)),
+            Value::String(_) | Value::Sketch(_) => f64::NAN,
+        }
+    }
+
+    /// the value as an integer, floats are truncated and saturate at the bounds of i64, NaN
+    /// is 0, bools are 0 or 1, strings and sketches are 0
+    pub fn as_i64(&self) -> i64 {
+        match *self {
+            Value::Float(v) => v as i64,
+            Value::Integer(v) => v,
+            Value::Bool(v) => i64::from(v),
+            Value::String(_) | Value::Sketch(_) => 0,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match *self {
+            Value::Float(v) => v.is_nan(),
+            _ => false,
+        }
+    }
+
+    /// the 64 bits stored for the value, see `from_bits`, strings and sketches have no bits
+    pub fn to_bits(&self) -> u64 {
+        match *self {
+            Value::Float(v) => v.to_bits(),
+            Value::Integer(v) => v as u64,
+            Value::Bool(v) => u64::from(v),
+            Value::String(_) | Value::Sketch(_) => 0,
+        }
+    }
+
+    /// the value stored as `bits`, `None` for strings and sketches
+    pub fn from_bits(bits: u64, value_type: ValueType) -> Option<Value> {
+        match value_type {
+            ValueType::Float => Some(Value::Float(f64::from_bits(bits))),
+            ValueType::Integer => Some(Value::Integer(bits as i64)),
+            ValueType::Bool => Some(Value::Bool(bits != 0)),
+            ValueType::String | ValueType::Sketch => None,
+        }
+    }
+
+    /// the smaller value, NaN is ignored as by `f64::min`, values of different types are
+    /// compared as floats
+    pub fn min(&self, other: &Value) -> Value {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => Value::Integer(*a.min(b)),
+            (Value::Bool(a), Value::Bool(b)) => Value::Bool(*a && *b),
+            (Value::String(a), Value::String(b)) => Value::String(a.min(b).clone()),
+            (a, b) => Value::Float(a.as_f64().min(b.as_f64())),
+        }
+    }
+
+    /// the larger value, NaN is ignored as by `f64::max`, values of different types are
+    /// compared as floats
+    pub fn max(&self, other: &Value) -> Value {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => Value::Integer(*a.max(b)),
+            (Value::Bool(a), Value::Bool(b)) => Value::Bool(*a || *b),
+            (Value::String(a), Value::String(b)) => Value::String(a.max(b).clone()),
+            (a, b) => Value::Float(a.as_f64().max(b.as_f64())),
+        }
+    }
+
+    /// whether the value is strictly greater than `other`, comparisons with NaN are false
+    pub fn gt(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a > b,
+            (Value::Bool(a), Value::Bool(b)) => a > b,
+            (Value::String(a), Value::String(b)) => a > b,
+            (a, b) => a.as_f64() > b.as_f64(),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Integer(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::String(v.to_string())
+    }
+}
+
+impl From<DDSketch> for Value {
+    fn from(v: DDSketch) -> Self {
+        Value::Sketch(Box::new(v))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Integer(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::String(ref v) => write!(f, "{:?}", v),
+            Value::Sketch(ref v) => write!(f, "sketch of {} values", v.count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::{Value, ValueType};
+
+    #[test]
+    fn bits_round_trip() {
+        let values = [
+            Value::Integer(i64::MIN),
+            Value::Integer(-1),
+            Value::Float(-0.5),
+            Value::Bool(true),
+        ];
+        for v in values.iter() {
+            assert_eq!(
+                Value::from_bits(v.to_bits(), v.value_type()).as_ref(),
+                Some(v)
+            );
+        }
+        assert_eq!(
+            Value::from_bits(Value::Integer(i64::MAX).to_bits(), ValueType::Integer),
+            Some(Value::Integer(i64::MAX))
+        );
+        assert_eq!(Value::from_bits(0, ValueType::String), None);
+    }
+
+    #[test]
+    fn json() {
+        let big = Value::Integer(9_007_199_254_740_993);
+        assert_eq!(serde_json::to_string(&big).unwrap(), "9007199254740993");
+        assert_eq!(
+            serde_json::from_str::<Value>("9007199254740993").unwrap(),
+            big
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>("1.5").unwrap(),
+            Value::Float(1.5)
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>("true").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>("\"down\"").unwrap(),
+            Value::from("down")
+        );
+    }
+}
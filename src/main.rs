@@ -4,7 +4,16 @@ extern crate log4rs;
 fn main() {
     init_log();
 
-    let engine = engine::create_engine("b-tree").unwrap();
+    // closed blocks are persisted under data_dir, kept in memory only without it. Past
+    // memory_budget bytes the persisted blocks read least recently are evicted.
+    let options = engine::EngineOptions {
+        data_dir: parse_arg("data_dir".to_string()).map(std::path::PathBuf::from),
+        memory_budget: parse_arg("memory_budget".to_string()).and_then(|b| b.parse().ok()),
+        ..engine::EngineOptions::default()
+    };
+    let engine = engine::create_engine_with("b-tree", options)
+        .expect("can not load the data directory")
+        .unwrap();
     net::serve(engine);
 }
 